@@ -0,0 +1,285 @@
+use std::fmt;
+
+use super::{cpu_image::CpuImage, plane::Plane};
+
+// The coarsest level of the search pyramid is at most this big on its longer side.
+const COARSE_SIZE: u32 = 64;
+// A candidate has to correlate at least this well to be reported...
+const MIN_CORRELATION: f64 = 0.9;
+// ...and be at least this much better than the images as they are.
+const MIN_IMPROVEMENT: f64 = 0.05;
+// Common DPI scale factors and their inverses.
+const SCALE_CANDIDATES: [f64; 11] = [
+    0.5,
+    2.0 / 3.0,
+    0.75,
+    0.8,
+    1.25,
+    1.5,
+    1.75,
+    2.0,
+    2.25,
+    2.5,
+    3.0,
+];
+
+/// A likely explanation for why an image doesn't match what was expected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Misalignment {
+    /// The content of the actual image is the expected content moved by this many pixels.
+    Shifted { x: i32, y: i32 },
+    /// The content of the actual image is the expected content scaled by this factor,
+    /// anchored at the top left corner.
+    Scaled { factor: f64 },
+}
+
+impl fmt::Display for Misalignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Misalignment::Shifted { x, y } => {
+                write!(f, "content appears shifted by ({:+}, {:+}) px", x, y)
+            }
+            Misalignment::Scaled { factor } => {
+                write!(f, "content appears scaled by {:.2}x", factor)
+            }
+        }
+    }
+}
+
+/// Tries to explain the difference between `expected` and `actual` as a translation
+/// or a uniform scale, which is what off-by-a-border or off-by-a-DPI-factor crop
+/// bugs look like. Returns `None` if neither explains the difference well.
+pub fn diagnose_misalignment(expected: &CpuImage, actual: &CpuImage) -> Option<Misalignment> {
+    let expected = Plane::from_image(expected);
+    let actual = Plane::from_image(actual);
+    let baseline = correlation(&expected, &actual, 0, 0, 1.0)?;
+
+    let shift = estimate_shift(&expected, &actual);
+    let scale = estimate_scale(&expected, &actual);
+
+    let mut best: Option<(f64, Misalignment)> = None;
+    if let Some((x, y, score)) = shift {
+        if (x, y) != (0, 0) {
+            best = Some((score, Misalignment::Shifted { x, y }));
+        }
+    }
+    if let Some((factor, score)) = scale {
+        if best.is_none_or(|(best_score, _)| score > best_score) {
+            best = Some((score, Misalignment::Scaled { factor }));
+        }
+    }
+
+    best.filter(|(score, _)| *score >= MIN_CORRELATION && *score - baseline >= MIN_IMPROVEMENT)
+        .map(|(_, misalignment)| misalignment)
+}
+
+/// Computes the zero-mean normalized cross-correlation between `expected` and
+/// `actual`, where the expected pixel at (x, y) is compared against the actual
+/// pixel at (x * scale + shift_x, y * scale + shift_y). Returns `None` if less
+/// than a quarter of the expected image overlaps or either side has no variation.
+fn correlation(
+    expected: &Plane,
+    actual: &Plane,
+    shift_x: i64,
+    shift_y: i64,
+    scale: f64,
+) -> Option<f64> {
+    let mut pairs = Vec::new();
+    for y in 0..expected.height as i64 {
+        for x in 0..expected.width as i64 {
+            let actual_x = (x as f64 * scale).floor() as i64 + shift_x;
+            let actual_y = (y as f64 * scale).floor() as i64 + shift_y;
+            if let Some(actual) = actual.get(actual_x, actual_y) {
+                pairs.push((expected.value(x as u32, y as u32), actual));
+            }
+        }
+    }
+    if pairs.len() * 4 < (expected.width * expected.height) as usize {
+        return None;
+    }
+
+    let count = pairs.len() as f64;
+    let mean_expected = pairs.iter().map(|(expected, _)| expected).sum::<f64>() / count;
+    let mean_actual = pairs.iter().map(|(_, actual)| actual).sum::<f64>() / count;
+    let (mut covariance, mut var_expected, mut var_actual) = (0.0, 0.0, 0.0);
+    for (expected, actual) in &pairs {
+        let de = expected - mean_expected;
+        let da = actual - mean_actual;
+        covariance += de * da;
+        var_expected += de * de;
+        var_actual += da * da;
+    }
+    if var_expected < f64::EPSILON || var_actual < f64::EPSILON {
+        return None;
+    }
+    Some(covariance / (var_expected * var_actual).sqrt())
+}
+
+/// Builds an image pyramid whose last level is at most `COARSE_SIZE` on its longer side.
+fn pyramid(plane: &Plane, levels: usize) -> Vec<Plane> {
+    let mut result = vec![plane.clone()];
+    for _ in 0..levels {
+        let next = result.last().unwrap().downsample();
+        result.push(next);
+    }
+    result
+}
+
+fn pyramid_levels(expected: &Plane) -> usize {
+    let mut levels = 0;
+    let mut size = expected.width.max(expected.height);
+    while size > COARSE_SIZE {
+        size /= 2;
+        levels += 1;
+    }
+    levels
+}
+
+/// Finds the shift with the best correlation with an exhaustive search at the
+/// coarsest level, which is then refined one level at a time.
+fn estimate_shift(expected: &Plane, actual: &Plane) -> Option<(i32, i32, f64)> {
+    let levels = pyramid_levels(expected);
+    let expected_levels = pyramid(expected, levels);
+    let actual_levels = pyramid(actual, levels);
+
+    let coarse_expected = &expected_levels[levels];
+    let coarse_actual = &actual_levels[levels];
+    let range_x = (coarse_expected.width / 3) as i64;
+    let range_y = (coarse_expected.height / 3) as i64;
+    let mut best = search_shift(coarse_expected, coarse_actual, 0, 0, range_x, range_y)?;
+
+    for level in (0..levels).rev() {
+        best = search_shift(
+            &expected_levels[level],
+            &actual_levels[level],
+            best.0 * 2,
+            best.1 * 2,
+            1,
+            1,
+        )?;
+    }
+    Some((best.0 as i32, best.1 as i32, best.2))
+}
+
+fn search_shift(
+    expected: &Plane,
+    actual: &Plane,
+    center_x: i64,
+    center_y: i64,
+    range_x: i64,
+    range_y: i64,
+) -> Option<(i64, i64, f64)> {
+    let mut best: Option<(i64, i64, f64)> = None;
+    for shift_y in center_y - range_y..=center_y + range_y {
+        for shift_x in center_x - range_x..=center_x + range_x {
+            if let Some(score) = correlation(expected, actual, shift_x, shift_y, 1.0) {
+                if best.is_none_or(|(_, _, best_score)| score > best_score) {
+                    best = Some((shift_x, shift_y, score));
+                }
+            }
+        }
+    }
+    best
+}
+
+/// Tries common scale factors (and the ratio of the image sizes) at the coarsest
+/// level and returns the one with the best correlation.
+fn estimate_scale(expected: &Plane, actual: &Plane) -> Option<(f64, f64)> {
+    let mut candidates = SCALE_CANDIDATES.to_vec();
+    if actual.width != expected.width {
+        candidates.push(actual.width as f64 / expected.width as f64);
+    }
+
+    let levels = pyramid_levels(expected);
+    let expected = pyramid(expected, levels).pop().unwrap();
+    let actual = pyramid(actual, levels).pop().unwrap();
+
+    let mut best: Option<(f64, f64)> = None;
+    for factor in candidates {
+        if let Some(score) = correlation(&expected, &actual, 0, 0, factor) {
+            if best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((factor, score));
+            }
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    /// Blobs of different shades on a dark background, so that only one shift
+    /// lines them up.
+    fn scene(x: i64, y: i64) -> Color {
+        let blobs = [(30, 25, 12, 200), (80, 60, 18, 120), (50, 70, 8, 250)];
+        let value = blobs
+            .iter()
+            .find(|(cx, cy, r, _)| (x - cx).pow(2) + (y - cy).pow(2) <= r * r)
+            .map_or(20, |(_, _, _, value)| *value);
+        Color {
+            A: 255,
+            R: value,
+            G: value / 2,
+            B: 255 - value,
+        }
+    }
+
+    #[test]
+    fn finds_a_known_shift() {
+        let expected = CpuImage::from_fn(128, 96, |x, y| scene(x as i64, y as i64));
+        let actual = CpuImage::from_fn(128, 96, |x, y| scene(x as i64 - 7, y as i64 - 4));
+        assert_eq!(
+            diagnose_misalignment(&expected, &actual),
+            Some(Misalignment::Shifted { x: 7, y: 4 })
+        );
+    }
+
+    #[test]
+    fn finds_a_known_shift_through_the_pyramid() {
+        // Large enough that the search starts on a downsampled level.
+        let expected = CpuImage::from_fn(256, 192, |x, y| scene(x as i64 / 2, y as i64 / 2));
+        let actual = CpuImage::from_fn(256, 192, |x, y| {
+            scene((x as i64 + 9) / 2, (y as i64 - 5) / 2)
+        });
+        assert_eq!(
+            diagnose_misalignment(&expected, &actual),
+            Some(Misalignment::Shifted { x: -9, y: 5 })
+        );
+    }
+
+    #[test]
+    fn finds_a_known_scale() {
+        let expected = CpuImage::from_fn(128, 96, |x, y| scene(x as i64, y as i64));
+        let doubled = CpuImage::from_fn(256, 192, |x, y| scene(x as i64 / 2, y as i64 / 2));
+        assert_eq!(
+            diagnose_misalignment(&expected, &doubled),
+            Some(Misalignment::Scaled { factor: 2.0 })
+        );
+        let one_and_a_half =
+            CpuImage::from_fn(192, 144, |x, y| scene(x as i64 * 2 / 3, y as i64 * 2 / 3));
+        assert_eq!(
+            diagnose_misalignment(&expected, &one_and_a_half),
+            Some(Misalignment::Scaled { factor: 1.5 })
+        );
+    }
+
+    #[test]
+    fn aligned_images_have_no_diagnosis() {
+        let image = CpuImage::from_fn(128, 96, |x, y| scene(x as i64, y as i64));
+        assert_eq!(diagnose_misalignment(&image, &image), None);
+    }
+
+    #[test]
+    fn shift_is_described_with_signs() {
+        assert_eq!(
+            Misalignment::Shifted { x: 3, y: -2 }.to_string(),
+            "content appears shifted by (+3, -2) px"
+        );
+        assert_eq!(
+            Misalignment::Scaled { factor: 1.5 }.to_string(),
+            "content appears scaled by 1.50x"
+        );
+    }
+}
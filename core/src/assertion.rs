@@ -0,0 +1,228 @@
+use std::fmt;
+
+use super::{color::Color, cpu_image::CpuImage, error::TestResult, geometry::Rect};
+
+/// The kind of check that failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AssertionKind {
+    PixelColor,
+    RegionColor,
+    GoldenImage,
+    Shape,
+    /// No frame satisfied a condition before the deadline.
+    FrameWait,
+    FrameTiming,
+    /// A frame had the size of the frame pool before it was recreated.
+    StaleFrame,
+    /// A frame changed outside of the dirty regions it reported.
+    DirtyRegions,
+    /// A window wasn't where the test needs it, e.g. on the captured monitor.
+    WindowPlacement,
+}
+
+impl AssertionKind {
+    /// A stable identifier for reports, e.g. "pixel_color".
+    pub fn id(&self) -> &'static str {
+        match self {
+            AssertionKind::PixelColor => "pixel_color",
+            AssertionKind::RegionColor => "region_color",
+            AssertionKind::GoldenImage => "golden_image",
+            AssertionKind::Shape => "shape",
+            AssertionKind::FrameWait => "frame_wait",
+            AssertionKind::FrameTiming => "frame_timing",
+            AssertionKind::StaleFrame => "stale_frame",
+            AssertionKind::DirtyRegions => "dirty_regions",
+            AssertionKind::WindowPlacement => "window_placement",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            AssertionKind::PixelColor => "Color comparison",
+            AssertionKind::RegionColor => "Region color comparison",
+            AssertionKind::GoldenImage => "Golden comparison",
+            AssertionKind::Shape => "Shape check",
+            AssertionKind::FrameWait => "Waiting for a frame",
+            AssertionKind::FrameTiming => "Frame timing check",
+            AssertionKind::StaleFrame => "Frame size check",
+            AssertionKind::DirtyRegions => "Dirty region check",
+            AssertionKind::WindowPlacement => "Window placement check",
+        }
+    }
+}
+
+/// How close a value has to be to the expected one to pass.
+#[derive(Clone, Copy, Debug)]
+pub enum Tolerance {
+    /// Every channel of every pixel must be within this distance.
+    PerPixel(u8),
+    /// The SSIM must be at least this value.
+    Ssim(f64),
+    /// The MS-SSIM must be at least this value.
+    MsSsim(f64),
+    /// The PSNR must be at least this many dB.
+    Psnr(f64),
+}
+
+impl From<u8> for Tolerance {
+    fn from(tolerance: u8) -> Self {
+        Tolerance::PerPixel(tolerance)
+    }
+}
+
+impl fmt::Display for Tolerance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tolerance::PerPixel(tolerance) => write!(f, "{} per channel", tolerance),
+            Tolerance::Ssim(minimum) => write!(f, "SSIM >= {}", minimum),
+            Tolerance::MsSsim(minimum) => write!(f, "MS-SSIM >= {}", minimum),
+            Tolerance::Psnr(minimum) => write!(f, "PSNR >= {} dB", minimum),
+        }
+    }
+}
+
+/// A value that took part in a failed check.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Color(Color),
+    Size { width: u32, height: u32 },
+    Point { x: f64, y: f64 },
+    Count(u64),
+    Number(f64),
+    Text(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Color(color) => write!(
+                f,
+                "( B: {}, G: {}, R: {}, A: {} )",
+                color.B, color.G, color.R, color.A
+            ),
+            Value::Size { width, height } => write!(f, "{} x {}", width, height),
+            Value::Point { x, y } => write!(f, "( X: {:.2}, Y: {:.2} )", x, y),
+            Value::Count(count) => write!(f, "{}", count),
+            Value::Number(number) => {
+                // Enough digits for metrics like SSIM without printing float noise.
+                let text = format!("{:.5}", number);
+                let text = if text.contains('.') {
+                    text.trim_end_matches('0').trim_end_matches('.')
+                } else {
+                    &text
+                };
+                write!(f, "{}", text)
+            }
+            Value::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// A named value that describes a failure beyond what was expected and found,
+/// e.g. the number of mismatched pixels.
+#[derive(Clone, Debug)]
+pub struct Detail {
+    pub label: &'static str,
+    pub value: Value,
+}
+
+/// What a failed check found, in a form that reporters can pick apart. The
+/// `Display` implementation produces the message printed to the console.
+#[derive(Clone, Debug)]
+pub struct AssertionFailure {
+    pub kind: AssertionKind,
+    /// What was being checked, e.g. the name of a golden image.
+    pub subject: Option<String>,
+    /// The pixel that failed the check. For checks over many pixels, the first one.
+    pub location: Option<(u32, u32)>,
+    /// The part of the image the check was about.
+    pub region: Option<Rect>,
+    pub expected: Option<Value>,
+    pub actual: Option<Value>,
+    pub tolerance: Option<Tolerance>,
+    pub details: Vec<Detail>,
+    /// Free-form text printed after everything else, e.g. a pixel dump.
+    pub notes: Vec<String>,
+}
+
+impl AssertionFailure {
+    pub fn new(kind: AssertionKind) -> Self {
+        Self {
+            kind,
+            subject: None,
+            location: None,
+            region: None,
+            expected: None,
+            actual: None,
+            tolerance: None,
+            details: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// The first line of the message, e.g. `Golden comparison failed for "alpha/red_circle"!`.
+    pub fn headline(&self) -> String {
+        match &self.subject {
+            Some(subject) => format!("{} failed for \"{}\"!", self.kind.title(), subject),
+            None => format!("{} failed!", self.kind.title()),
+        }
+    }
+}
+
+impl fmt::Display for AssertionFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.headline())?;
+        if let Some(region) = &self.region {
+            writeln!(
+                f,
+                "  Region: ( X: {}, Y: {}, Width: {}, Height: {} )",
+                region.x, region.y, region.width, region.height
+            )?;
+        }
+        if let Some((x, y)) = self.location {
+            writeln!(f, "  Location: ({}, {})", x, y)?;
+        }
+        if let Some(actual) = &self.actual {
+            writeln!(f, "  Actual: {}", actual)?;
+        }
+        if let Some(expected) = &self.expected {
+            writeln!(f, "  Expected: {}", expected)?;
+        }
+        if let Some(tolerance) = &self.tolerance {
+            writeln!(f, "  Tolerance: {}", tolerance)?;
+        }
+        for detail in &self.details {
+            writeln!(f, "  {}: {}", detail.label, detail.value)?;
+        }
+        for note in &self.notes {
+            for line in note.lines() {
+                writeln!(f, "  {}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The image a failed check looked at.
+#[derive(Debug)]
+pub enum Capture {
+    /// An image that still lives on the GPU.
+    Texture(Box<dyn Readback>),
+    Image(CpuImage),
+}
+
+/// An image that has to be read back before it can be saved or inspected, e.g.
+/// a texture. Reading it back is put off until the failure is reported. Errors
+/// are passed between threads, e.g. by `spawn_session_stream`, so it has to be
+/// `Send`.
+pub trait Readback: fmt::Debug + Send {
+    fn read_back(&self) -> TestResult<CpuImage>;
+}
+
+/// An additional image that is saved alongside the failing test's capture,
+/// e.g. the expected image or a diff.
+#[derive(Debug)]
+pub struct Artifact {
+    pub name: String,
+    pub image: CpuImage,
+}
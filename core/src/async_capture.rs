@@ -0,0 +1,712 @@
+//! An async layer over captures, for tests that wait on several things at once,
+//! e.g. the next frame or the item closing, whichever comes first. Frames come
+//! through a bounded `FrameStream`: once it is full, producers hold on to new
+//! frames, so a test that falls behind slows the capture down instead of
+//! queueing up frames without end.
+//!
+//! Nothing here needs a particular executor. `block_on` runs a future on the
+//! current thread, which is all the tests need.
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    mem,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, Once, OnceLock},
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+pub use futures_core::Stream;
+pub use pollster::block_on;
+
+use super::{
+    capture::{CaptureFrame, CaptureSession},
+    error::{TestError, TestResult},
+};
+
+/// Why a stream doesn't have any more frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamEnd {
+    /// The captured item went away.
+    ItemClosed,
+    /// The capture, or the stream, was closed.
+    Closed,
+    /// The producer failed, see `FrameSender::fail`.
+    Failed,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+    end: Option<StreamEnd>,
+    /// Why the producer failed, until `next_within` reports it.
+    error: Option<TestError>,
+    waker: Option<Waker>,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    /// Signaled whenever the stream takes an item or ends.
+    space: Condvar,
+}
+
+/// Creates a stream that holds up to `capacity` frames, and the sender that
+/// feeds it.
+pub fn frame_stream<T>(capacity: usize) -> (FrameSender<T>, FrameStream<T>) {
+    assert!(
+        capacity > 0,
+        "a frame stream needs room for at least one frame"
+    );
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::with_capacity(capacity),
+            capacity,
+            end: None,
+            error: None,
+            waker: None,
+        }),
+        space: Condvar::new(),
+    });
+    let sender = FrameSender {
+        shared: shared.clone(),
+    };
+    let stream = FrameStream {
+        sender: sender.clone(),
+        refill: None,
+    };
+    (sender, stream)
+}
+
+/// The producing end of a `FrameStream`.
+pub struct FrameSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for FrameSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> FrameSender<T> {
+    /// Whether `try_send` would take another frame.
+    pub fn has_space(&self) -> bool {
+        let state = self.shared.state.lock().unwrap();
+        state.end.is_none() && state.items.len() < state.capacity
+    }
+
+    /// Queues `item`, or hands it back if the stream is full. Once the stream
+    /// has ended, items are dropped.
+    pub fn try_send(&self, item: T) -> Result<(), T> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.end.is_some() {
+            return Ok(());
+        }
+        if state.items.len() >= state.capacity {
+            return Err(item);
+        }
+        state.items.push_back(item);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    /// Queues `item`, waiting for room if the stream is full. Returns `false`
+    /// if the stream has ended, in which case nothing will take items anymore.
+    pub fn send(&self, item: T) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        while state.end.is_none() && state.items.len() >= state.capacity {
+            state = self.shared.space.wait(state).unwrap();
+        }
+        if state.end.is_some() {
+            return false;
+        }
+        state.items.push_back(item);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+
+    /// Ends the stream. Frames that are already queued are still delivered.
+    /// Only the first end counts.
+    pub fn end(&self, end: StreamEnd) {
+        self.finish(end, None);
+    }
+
+    /// Ends the stream with `error`, which `FrameStream::next_within` returns
+    /// once the frames that are already queued have been taken.
+    pub fn fail(&self, error: TestError) {
+        self.finish(StreamEnd::Failed, Some(error));
+    }
+
+    fn finish(&self, end: StreamEnd, error: Option<TestError>) {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.end.is_none() {
+            state.end = Some(end);
+            state.error = error;
+        }
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.shared.space.notify_all();
+    }
+}
+
+/// Queues frames a producer held on to, see `FrameStream::with_refill`.
+type Refill<T> = Box<dyn FnMut(&FrameSender<T>) + Send>;
+
+/// A bounded, asynchronous sequence of frames.
+pub struct FrameStream<T> {
+    sender: FrameSender<T>,
+    refill: Option<Refill<T>>,
+}
+
+impl<T> FrameStream<T> {
+    /// Calls `refill` whenever a frame was taken, so that a producer that held
+    /// on to frames while the stream was full can queue them.
+    pub fn with_refill<F>(mut self, refill: F) -> Self
+    where
+        F: FnMut(&FrameSender<T>) + Send + 'static,
+    {
+        self.refill = Some(Box::new(refill));
+        self
+    }
+
+    /// Waits for the next frame. Resolves to `None` once the stream has ended
+    /// and every queued frame has been taken.
+    pub fn next_frame(&mut self) -> Next<'_, T> {
+        Next { stream: self }
+    }
+
+    /// Waits up to `timeout` for the next frame. Fails with
+    /// `TestError::ItemClosed` if the item went away, with the producer's error
+    /// if it failed, and with a timeout if the capture was closed or no frame
+    /// arrived in time. A producer's error is only returned once, after that
+    /// the stream reads as closed.
+    pub async fn next_within(&mut self, timeout: Duration) -> TestResult<T> {
+        let end = match with_timeout(self.next_frame(), timeout).await {
+            Some(Some(item)) => return Ok(item),
+            Some(None) => self.end(),
+            None => None,
+        };
+        match end {
+            Some(StreamEnd::ItemClosed) => return Err(TestError::ItemClosed),
+            Some(StreamEnd::Failed) => {
+                if let Some(error) = self.sender.shared.state.lock().unwrap().error.take() {
+                    return Err(error);
+                }
+            }
+            Some(StreamEnd::Closed) | None => {}
+        }
+        Err(TestError::Timeout {
+            waiting_for: "the next frame".to_owned(),
+            timeout,
+        })
+    }
+
+    /// Another sender for the stream, e.g. to end it from an event handler.
+    pub fn sender(&self) -> FrameSender<T> {
+        self.sender.clone()
+    }
+
+    /// Why the stream ended, or `None` if it is still going.
+    pub fn end(&self) -> Option<StreamEnd> {
+        self.sender.shared.state.lock().unwrap().end
+    }
+
+    fn poll_frame(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let item = {
+            let mut state = self.sender.shared.state.lock().unwrap();
+            match state.items.pop_front() {
+                Some(item) => item,
+                None if state.end.is_some() => return Poll::Ready(None),
+                None => {
+                    state.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        };
+        self.sender.shared.space.notify_all();
+        if let Some(refill) = &mut self.refill {
+            refill(&self.sender);
+        }
+        Poll::Ready(Some(item))
+    }
+}
+
+impl<T> Stream for FrameStream<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.get_mut().poll_frame(cx)
+    }
+}
+
+impl<T> Drop for FrameStream<T> {
+    /// Lets producers that are waiting for room go.
+    fn drop(&mut self) {
+        self.sender.end(StreamEnd::Closed);
+    }
+}
+
+/// The future returned by `FrameStream::next_frame`.
+pub struct Next<'a, T> {
+    stream: &'a mut FrameStream<T>,
+}
+
+impl<T> Future for Next<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.stream.poll_frame(cx)
+    }
+}
+
+/// Streams the frames of `session` from a separate thread, e.g. to use a
+/// `FakeSession` through the async API. The thread waits up to `frame_timeout`
+/// for each frame and ends the stream once the item closes or the session
+/// fails, e.g. because no frame came. `next_within` returns the session's error.
+pub fn spawn_session_stream<S>(
+    mut session: S,
+    capacity: usize,
+    frame_timeout: Duration,
+) -> FrameStream<CaptureFrame>
+where
+    S: CaptureSession + Send + 'static,
+{
+    let (sender, stream) = frame_stream(capacity);
+    thread::spawn(move || {
+        let end = loop {
+            match session.next_frame(frame_timeout) {
+                Ok(frame) => {
+                    if !sender.send(frame) {
+                        break Ok(StreamEnd::Closed);
+                    }
+                }
+                Err(TestError::ItemClosed) => break Ok(StreamEnd::ItemClosed),
+                Err(error) => break Err(error),
+            }
+        };
+        match end {
+            Ok(end) => sender.end(end),
+            Err(error) => sender.fail(error),
+        }
+        let _ = session.close();
+    });
+    stream
+}
+
+/// Resolves to the output of `future`, or to `None` if it takes longer than
+/// `timeout`.
+pub fn with_timeout<F: Future>(future: F, timeout: Duration) -> Timeout<F> {
+    Timeout {
+        future: Box::pin(future),
+        deadline: Instant::now() + timeout,
+        timer: None,
+    }
+}
+
+/// The future returned by `with_timeout`.
+pub struct Timeout<F> {
+    future: Pin<Box<F>>,
+    deadline: Instant,
+    /// The timer that wakes the task at the deadline, once it is registered.
+    timer: Option<u64>,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Option<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Poll::Ready(output) = self.future.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        let now = Instant::now();
+        if now >= self.deadline {
+            return Poll::Ready(None);
+        }
+        let deadline = self.deadline;
+        self.timer = Some(timers().register(self.timer, deadline, cx.waker()));
+        Poll::Pending
+    }
+}
+
+impl<F> Drop for Timeout<F> {
+    fn drop(&mut self) {
+        if let Some(timer) = self.timer {
+            timers().cancel(timer);
+        }
+    }
+}
+
+/// There is no reactor to register timers with, so one thread wakes the tasks
+/// whose timeouts have passed. It sleeps until the earliest deadline.
+#[derive(Default)]
+struct Timers {
+    state: Mutex<TimerState>,
+    /// Signaled whenever a timer is registered.
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct TimerState {
+    next_id: u64,
+    timers: Vec<Timer>,
+}
+
+struct Timer {
+    id: u64,
+    deadline: Instant,
+    waker: Waker,
+}
+
+fn timers() -> &'static Timers {
+    static TIMERS: OnceLock<Timers> = OnceLock::new();
+    static THREAD: Once = Once::new();
+    let timers = TIMERS.get_or_init(Timers::default);
+    THREAD.call_once(|| {
+        thread::Builder::new()
+            .name("wgctest-timers".to_owned())
+            .spawn(move || timers.run())
+            .expect("starting the timer thread");
+    });
+    timers
+}
+
+impl Timers {
+    /// Wakes `waker` once `deadline` has passed. Registering a timer that is
+    /// still pending again only updates its waker.
+    fn register(&self, id: Option<u64>, deadline: Instant, waker: &Waker) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        if let Some(timer) = id.and_then(|id| state.timers.iter_mut().find(|timer| timer.id == id))
+        {
+            if !timer.waker.will_wake(waker) {
+                timer.waker = waker.clone();
+            }
+            return timer.id;
+        }
+        let id = state.next_id;
+        state.next_id += 1;
+        state.timers.push(Timer {
+            id,
+            deadline,
+            waker: waker.clone(),
+        });
+        self.changed.notify_one();
+        id
+    }
+
+    fn cancel(&self, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.timers.retain(|timer| timer.id != id);
+    }
+
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let (due, pending): (Vec<_>, Vec<_>) = mem::take(&mut state.timers)
+                .into_iter()
+                .partition(|timer| timer.deadline <= now);
+            state.timers = pending;
+            if !due.is_empty() {
+                // Wakers may run arbitrary code, so don't hold the lock.
+                drop(state);
+                for timer in due {
+                    timer.waker.wake();
+                }
+                state = self.state.lock().unwrap();
+                continue;
+            }
+            state = match state.timers.iter().map(|timer| timer.deadline).min() {
+                Some(deadline) => self.changed.wait_timeout(state, deadline - now).unwrap().0,
+                None => self.changed.wait(state).unwrap(),
+            };
+        }
+    }
+}
+
+/// Creates an unbounded queue of messages, e.g. the messages a window receives,
+/// and the sender that feeds it. Unlike frames, messages don't hold on to
+/// anything scarce, so posting one never blocks or drops it.
+pub fn message_queue<M>() -> (MessageSender<M>, MessageQueue<M>) {
+    let messages = Arc::new(Mutex::new(Messages {
+        queued: VecDeque::new(),
+        waker: None,
+    }));
+    let sender = MessageSender {
+        messages: messages.clone(),
+    };
+    (sender, MessageQueue { messages })
+}
+
+struct Messages<M> {
+    queued: VecDeque<M>,
+    waker: Option<Waker>,
+}
+
+/// The producing end of a `MessageQueue`.
+pub struct MessageSender<M> {
+    messages: Arc<Mutex<Messages<M>>>,
+}
+
+impl<M> Clone for MessageSender<M> {
+    fn clone(&self) -> Self {
+        Self {
+            messages: self.messages.clone(),
+        }
+    }
+}
+
+impl<M> MessageSender<M> {
+    pub fn post(&self, message: M) {
+        let mut messages = self.messages.lock().unwrap();
+        messages.queued.push_back(message);
+        if let Some(waker) = messages.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Messages in the order they were posted.
+pub struct MessageQueue<M> {
+    messages: Arc<Mutex<Messages<M>>>,
+}
+
+impl<M> MessageQueue<M> {
+    /// Waits for the first message `predicate` accepts. The messages before it
+    /// are dropped.
+    pub fn wait_for<P>(&mut self, predicate: P) -> WaitFor<'_, M, P>
+    where
+        P: FnMut(&M) -> bool,
+    {
+        WaitFor {
+            queue: self,
+            predicate,
+        }
+    }
+}
+
+/// The future returned by `MessageQueue::wait_for`.
+pub struct WaitFor<'a, M, P> {
+    queue: &'a mut MessageQueue<M>,
+    predicate: P,
+}
+
+impl<M, P> Future for WaitFor<'_, M, P>
+where
+    P: FnMut(&M) -> bool + Unpin,
+{
+    type Output = M;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<M> {
+        let this = self.get_mut();
+        let mut messages = this.queue.messages.lock().unwrap();
+        while let Some(message) = messages.queued.pop_front() {
+            if (this.predicate)(&message) {
+                return Poll::Ready(message);
+            }
+        }
+        messages.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// The outcome of `race`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Either<A, B> {
+    Left(A),
+    Right(B),
+}
+
+/// Resolves to the output of whichever future finishes first, e.g. the next
+/// frame or the window receiving a message. The other future is dropped.
+pub fn race<A: Future, B: Future>(left: A, right: B) -> Race<A, B> {
+    Race {
+        left: Box::pin(left),
+        right: Box::pin(right),
+    }
+}
+
+/// The future returned by `race`.
+pub struct Race<A, B> {
+    left: Pin<Box<A>>,
+    right: Pin<Box<B>>,
+}
+
+impl<A: Future, B: Future> Future for Race<A, B> {
+    type Output = Either<A::Output, B::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Poll::Ready(output) = self.left.as_mut().poll(cx) {
+            return Poll::Ready(Either::Left(output));
+        }
+        if let Poll::Ready(output) = self.right.as_mut().poll(cx) {
+            return Poll::Ready(Either::Right(output));
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        capture::{CaptureBackend, CaptureOptions},
+        color::common_colors,
+        cpu_image::CpuImage,
+        error::PlatformError,
+        fake_capture::FakeBackend,
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn fake_stream(frames: u64) -> FrameStream<CaptureFrame> {
+        let backend = (1..=frames).fold(FakeBackend::new(), |backend, number| {
+            backend.with_frame(
+                CpuImage::from_fn(4, 4, |_, _| common_colors::RED),
+                Duration::from_millis(number * 10),
+            )
+        });
+        let session = backend.start(&(), CaptureOptions::default()).unwrap();
+        spawn_session_stream(session, 2, Duration::from_millis(100))
+    }
+
+    #[test]
+    fn queued_frames_outlive_the_item() {
+        let (sender, mut stream) = frame_stream(2);
+        assert!(sender.try_send(1).is_ok());
+        assert!(sender.try_send(2).is_ok());
+        assert_eq!(sender.try_send(3), Err(3));
+        sender.end(StreamEnd::ItemClosed);
+        // Only the first end counts.
+        sender.end(StreamEnd::Closed);
+
+        assert_eq!(stream.end(), Some(StreamEnd::ItemClosed));
+        assert_eq!(block_on(stream.next_within(TIMEOUT)).unwrap(), 1);
+        assert_eq!(block_on(stream.next_within(TIMEOUT)).unwrap(), 2);
+        assert!(matches!(
+            block_on(stream.next_within(TIMEOUT)),
+            Err(TestError::ItemClosed)
+        ));
+        // Nothing is queued once the stream has ended.
+        assert!(sender.try_send(4).is_ok());
+        assert!(!sender.has_space());
+        assert!(block_on(stream.next_frame()).is_none());
+    }
+
+    #[test]
+    fn dropping_the_stream_lets_blocked_senders_go() {
+        let (sender, stream) = frame_stream(1);
+        assert!(sender.send(1));
+        let blocked = thread::spawn(move || sender.send(2));
+        drop(stream);
+        assert!(!blocked.join().unwrap());
+    }
+
+    #[test]
+    fn session_errors_reach_next_within() {
+        let mut stream = fake_stream(1);
+        let frame = block_on(stream.next_within(TIMEOUT)).unwrap();
+        assert_eq!(frame.timestamp, Duration::from_millis(10));
+        // The fake session times out on its own clock, after the 100ms the
+        // stream's thread waits for each frame.
+        assert!(matches!(
+            block_on(stream.next_within(TIMEOUT)),
+            Err(TestError::Timeout { timeout, .. }) if timeout == Duration::from_millis(100)
+        ));
+        assert_eq!(stream.end(), Some(StreamEnd::Failed));
+        // The error is only reported once.
+        assert!(matches!(
+            block_on(stream.next_within(TIMEOUT)),
+            Err(TestError::Timeout { timeout, .. }) if timeout == TIMEOUT
+        ));
+    }
+
+    #[test]
+    fn failing_keeps_the_queued_frames() {
+        let (sender, mut stream) = frame_stream(2);
+        assert!(sender.try_send(1).is_ok());
+        sender.fail(TestError::Capture {
+            step: "reading the frame".to_owned(),
+            error: PlatformError {
+                code: -1,
+                message: "the device was removed".to_owned(),
+            },
+        });
+        sender.end(StreamEnd::ItemClosed);
+        assert_eq!(block_on(stream.next_within(TIMEOUT)).unwrap(), 1);
+        assert!(matches!(
+            block_on(stream.next_within(TIMEOUT)),
+            Err(TestError::Capture { step, .. }) if step == "reading the frame"
+        ));
+    }
+
+    #[test]
+    fn a_queued_message_wins_the_race() {
+        let mut frames = fake_stream(3);
+        let (sender, mut messages) = message_queue();
+        sender.post("resized");
+        sender.post("closed");
+        let winner = block_on(race(
+            messages.wait_for(|message| *message == "closed"),
+            frames.next_within(TIMEOUT),
+        ));
+        assert!(matches!(winner, Either::Left("closed")));
+    }
+
+    #[test]
+    fn a_frame_wins_the_race_against_other_messages() {
+        let mut frames = fake_stream(3);
+        let (sender, mut messages) = message_queue();
+        sender.post("resized");
+        let winner = block_on(race(
+            messages.wait_for(|message| *message == "closed"),
+            frames.next_within(TIMEOUT),
+        ));
+        let Either::Right(frame) = winner else {
+            panic!("a message won the race");
+        };
+        assert_eq!(frame.unwrap().timestamp, Duration::from_millis(10));
+        // The message the race was waiting for can still come in later.
+        sender.post("closed");
+        assert_eq!(block_on(messages.wait_for(|_| true)), "closed");
+    }
+
+    #[test]
+    fn a_message_from_another_thread_wakes_the_race() {
+        let (_sender, mut frames) = frame_stream::<u32>(1);
+        let (sender, mut messages) = message_queue();
+        let poster = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            sender.post(42);
+        });
+        let winner = block_on(race(
+            messages.wait_for(|message| *message == 42),
+            frames.next_within(TIMEOUT),
+        ));
+        assert!(matches!(winner, Either::Left(42)));
+        poster.join().unwrap();
+    }
+
+    #[test]
+    fn timeouts_resolve_at_their_deadline() {
+        let (_sender, mut frames) = frame_stream::<u32>(1);
+        let started = Instant::now();
+        let (short, long) = block_on(async {
+            let short = with_timeout(frames.next_frame(), Duration::from_millis(10)).await;
+            let long = with_timeout(async { 7 }, TIMEOUT).await;
+            (short, long)
+        });
+        assert_eq!(short, None);
+        assert_eq!(long, Some(7));
+        assert!(started.elapsed() >= Duration::from_millis(10));
+        assert!(started.elapsed() < TIMEOUT);
+    }
+}
@@ -0,0 +1,31 @@
+//! Plays recordings made with `wgctest --record` back through the checks of the
+//! tests that made them, and writes the same reports as a live run. This runs on
+//! any machine, so a failure can be looked into without the one it happened on.
+//!
+//! ```text
+//! wgctest-replay [--preview[=ascii]] <recording>...
+//! ```
+
+use std::{error::Error, path::Path};
+
+use wgctest_core::{scenarios::replay_recording, test_runner::TestContext};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let (context, recordings) = TestContext::from_args_with_inputs()?;
+    if recordings.is_empty() {
+        return Err("pass the recordings to replay, e.g. fullscreen_transition_test.wgcrec".into());
+    }
+
+    for recording in &recordings {
+        let path = Path::new(recording);
+        let test_name = path.file_stem().map_or_else(
+            || recording.clone(),
+            |stem| stem.to_string_lossy().into_owned(),
+        );
+        context.record_result(&test_name, replay_recording(&context, path));
+    }
+
+    context.write_report()?;
+
+    Ok(())
+}
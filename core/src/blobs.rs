@@ -0,0 +1,294 @@
+use std::f64::consts::PI;
+
+use super::{
+    assertion::{AssertionFailure, AssertionKind, Capture, Detail, Tolerance, Value},
+    color::Color,
+    color_math::colors_match,
+    cpu_image::CpuImage,
+    error::{AssertionError, TestError, TestResult},
+    geometry::Rect,
+};
+
+/// A connected group of pixels of the same color.
+#[derive(Clone, Debug)]
+pub struct Blob {
+    pub bounds: Rect,
+    /// The mean position of the blob's pixel centers.
+    pub centroid: (f64, f64),
+    /// The number of pixels in the blob.
+    pub area: u64,
+    /// How close the blob is to a disc, from the blob's second moment. A rasterized
+    /// disc is close to 1.0, a square is about 0.95 and elongated shapes go towards 0.
+    pub circularity: f64,
+}
+
+impl Blob {
+    /// The radius of a disc with the same area as the blob.
+    pub fn equivalent_radius(&self) -> f64 {
+        (self.area as f64 / PI).sqrt()
+    }
+}
+
+/// Finds the 8-connected groups of pixels within `tolerance` of `color`, largest first.
+pub fn find_blobs(image: &CpuImage, color: Color, tolerance: u8) -> Vec<Blob> {
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    let matches: Vec<bool> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            colors_match(
+                image.read_pixel(x as u32, y as u32).unwrap(),
+                color,
+                tolerance,
+            )
+        })
+        .collect();
+
+    let mut visited = vec![false; width * height];
+    let mut blobs = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..width * height {
+        if !matches[start] || visited[start] {
+            continue;
+        }
+
+        visited[start] = true;
+        stack.push(start);
+        let mut pixels = Vec::new();
+        while let Some(index) = stack.pop() {
+            let (x, y) = ((index % width) as i64, (index / width) as i64);
+            pixels.push((x, y));
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                        continue;
+                    }
+                    let neighbor = ny as usize * width + nx as usize;
+                    if matches[neighbor] && !visited[neighbor] {
+                        visited[neighbor] = true;
+                        stack.push(neighbor);
+                    }
+                }
+            }
+        }
+        blobs.push(measure(&pixels));
+    }
+
+    blobs.sort_by_key(|blob| std::cmp::Reverse(blob.area));
+    blobs
+}
+
+fn measure(pixels: &[(i64, i64)]) -> Blob {
+    let area = pixels.len() as u64;
+    let (mut left, mut top, mut right, mut bottom) = (i64::MAX, i64::MAX, i64::MIN, i64::MIN);
+    let (mut sum_x, mut sum_y) = (0.0, 0.0);
+    for (x, y) in pixels {
+        left = left.min(*x);
+        top = top.min(*y);
+        right = right.max(*x);
+        bottom = bottom.max(*y);
+        sum_x += *x as f64 + 0.5;
+        sum_y += *y as f64 + 0.5;
+    }
+    let centroid = (sum_x / area as f64, sum_y / area as f64);
+
+    // For a disc, the sum of the squared distances from the centroid is A^2 / 2pi.
+    // Each pixel contributes its own second moment (1/6) on top of its center's.
+    let second_moment: f64 = pixels
+        .iter()
+        .map(|(x, y)| {
+            let dx = *x as f64 + 0.5 - centroid.0;
+            let dy = *y as f64 + 0.5 - centroid.1;
+            dx * dx + dy * dy + 1.0 / 6.0
+        })
+        .sum();
+    let circularity = (area as f64 * area as f64) / (2.0 * PI * second_moment);
+
+    Blob {
+        bounds: Rect::new(
+            left as i32,
+            top as i32,
+            (right - left + 1) as i32,
+            (bottom - top + 1) as i32,
+        ),
+        centroid,
+        area,
+        circularity,
+    }
+}
+
+/// Checks that the largest blob of `color` is a circle of `radius` centered on
+/// `center`, give or take `max_error` pixels for both.
+pub fn expect_circle(
+    image: &CpuImage,
+    color: Color,
+    tolerance: u8,
+    center: (f64, f64),
+    radius: f64,
+    max_error: f64,
+) -> TestResult<()> {
+    const MIN_CIRCULARITY: f64 = 0.98;
+
+    let blobs = find_blobs(image, color, tolerance);
+    let mut failure = AssertionFailure {
+        tolerance: Some(Tolerance::PerPixel(tolerance)),
+        details: vec![
+            Detail {
+                label: "Color",
+                value: Value::Color(color),
+            },
+            Detail {
+                label: "Expected center",
+                value: Value::Point {
+                    x: center.0,
+                    y: center.1,
+                },
+            },
+            Detail {
+                label: "Expected radius",
+                value: Value::Number(radius),
+            },
+            Detail {
+                label: "Max error",
+                value: Value::Number(max_error),
+            },
+        ],
+        ..AssertionFailure::new(AssertionKind::Shape)
+    };
+    match blobs.first() {
+        None => failure
+            .notes
+            .push("No pixels of the expected color were found.".to_owned()),
+        Some(blob) => {
+            let distance = ((blob.centroid.0 - center.0).powi(2)
+                + (blob.centroid.1 - center.1).powi(2))
+            .sqrt();
+            let radius_error = (blob.equivalent_radius() - radius).abs();
+            if distance <= max_error
+                && radius_error <= max_error
+                && blob.circularity >= MIN_CIRCULARITY
+            {
+                return Ok(());
+            }
+            // The region is the largest blob, which is what the details describe.
+            failure.region = Some(blob.bounds);
+            failure.details.extend([
+                Detail {
+                    label: "Center",
+                    value: Value::Point {
+                        x: blob.centroid.0,
+                        y: blob.centroid.1,
+                    },
+                },
+                Detail {
+                    label: "Radius",
+                    value: Value::Number(blob.equivalent_radius()),
+                },
+                Detail {
+                    label: "Circularity",
+                    value: Value::Number(blob.circularity),
+                },
+                Detail {
+                    label: "Minimum circularity",
+                    value: Value::Number(MIN_CIRCULARITY),
+                },
+                Detail {
+                    label: "Blobs found",
+                    value: Value::Count(blobs.len() as u64),
+                },
+            ]);
+        }
+    }
+
+    TestError::Assertion(Box::new(AssertionError {
+        failure,
+        capture: Some(Capture::Image(image.clone())),
+        artifacts: Vec::new(),
+    }))
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::common_colors;
+
+    /// A red disc on transparent black, filled where the pixel center is inside.
+    fn disc(size: u32, center: (f64, f64), radius: f64) -> CpuImage {
+        CpuImage::from_fn(size, size, |x, y| {
+            let dx = x as f64 + 0.5 - center.0;
+            let dy = y as f64 + 0.5 - center.1;
+            if dx * dx + dy * dy <= radius * radius {
+                common_colors::RED
+            } else {
+                common_colors::TRANSPARENT_BLACK
+            }
+        })
+    }
+
+    #[test]
+    fn disc_is_measured() {
+        let blobs = find_blobs(&disc(100, (40.0, 55.0), 30.0), common_colors::RED, 0);
+        assert_eq!(blobs.len(), 1);
+        let blob = &blobs[0];
+        assert!((blob.centroid.0 - 40.0).abs() < 0.1);
+        assert!((blob.centroid.1 - 55.0).abs() < 0.1);
+        assert!((blob.equivalent_radius() - 30.0).abs() < 0.2);
+        assert!(
+            blob.circularity > 0.99,
+            "circularity was {}",
+            blob.circularity
+        );
+        assert_eq!(blob.bounds, Rect::new(10, 25, 60, 60));
+    }
+
+    #[test]
+    fn square_and_bar_are_less_circular() {
+        let square = CpuImage::from_fn(40, 40, |x, y| {
+            if (10..30).contains(&x) && (10..30).contains(&y) {
+                common_colors::BLUE
+            } else {
+                common_colors::TRANSPARENT_BLACK
+            }
+        });
+        let circularity = find_blobs(&square, common_colors::BLUE, 0)[0].circularity;
+        assert!((circularity - 3.0 / PI).abs() < 0.01, "{}", circularity);
+
+        let bar = CpuImage::from_fn(100, 10, |_, y| {
+            if y < 2 {
+                common_colors::BLUE
+            } else {
+                common_colors::TRANSPARENT_BLACK
+            }
+        });
+        assert!(find_blobs(&bar, common_colors::BLUE, 0)[0].circularity < 0.1);
+    }
+
+    #[test]
+    fn blobs_are_sorted_by_area() {
+        let image = CpuImage::from_fn(60, 20, |x, y| {
+            let in_small = x < 5 && y < 5;
+            let in_large = (20..40).contains(&x) && y < 20;
+            if in_small || in_large {
+                common_colors::GREEN
+            } else {
+                common_colors::TRANSPARENT_BLACK
+            }
+        });
+        let areas: Vec<_> = find_blobs(&image, common_colors::GREEN, 0)
+            .iter()
+            .map(|blob| blob.area)
+            .collect();
+        assert_eq!(areas, vec![400, 25]);
+    }
+
+    #[test]
+    fn expect_circle_checks_center_and_radius() {
+        let image = disc(100, (50.0, 50.0), 40.0);
+        assert!(expect_circle(&image, common_colors::RED, 0, (50.0, 50.0), 40.0, 1.0).is_ok());
+        assert!(expect_circle(&image, common_colors::RED, 0, (55.0, 50.0), 40.0, 1.0).is_err());
+        assert!(expect_circle(&image, common_colors::RED, 0, (50.0, 50.0), 35.0, 1.0).is_err());
+        assert!(expect_circle(&image, common_colors::BLUE, 0, (50.0, 50.0), 40.0, 1.0).is_err());
+    }
+}
@@ -0,0 +1,701 @@
+use std::{collections::VecDeque, error::Error, fmt, time::Duration};
+
+use super::{
+    assertion::{Artifact, AssertionFailure, AssertionKind, Capture, Detail, Value},
+    color::Color,
+    color_math::colors_match,
+    cpu_image::CpuImage,
+    error::{AssertionError, TestError, TestResult},
+    geometry::Rect,
+    resize::SizeChange,
+    timing::TimingRecorder,
+};
+
+/// How many of the most recent frames a failed `wait_for_frame` attaches: the
+/// last one as the capture, the others as artifacts.
+pub const WAIT_ARTIFACT_FRAMES: usize = 3;
+
+/// The pixel formats a capture can produce. The values match `DirectXPixelFormat`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    R16G16B16A16Float = 10,
+    B8G8R8A8UIntNormalized = 87,
+}
+
+impl PixelFormat {
+    pub fn from_id(id: u32) -> Option<Self> {
+        match id {
+            10 => Some(PixelFormat::R16G16B16A16Float),
+            87 => Some(PixelFormat::B8G8R8A8UIntNormalized),
+            _ => None,
+        }
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::R16G16B16A16Float => 8,
+            PixelFormat::B8G8R8A8UIntNormalized => 4,
+        }
+    }
+}
+
+/// Which parts of a frame are reported as changed, and which are rendered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DirtyRegionMode {
+    /// Frames are rendered in full, dirty regions are only reported.
+    ReportOnly,
+    /// Only the dirty regions are rendered into a frame.
+    ReportAndRender,
+}
+
+/// Which thread frame pool events are raised on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FramePoolKind {
+    /// Events are raised on a thread pool thread.
+    FreeThreaded,
+    /// Events are raised on the `DispatcherQueue` of the thread that created
+    /// the frame pool, which needs to have one.
+    DispatcherQueue,
+}
+
+/// A combination of capture options that can't work.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvalidCaptureOptions {
+    /// The frame pool needs at least one buffer.
+    NoBuffers,
+    /// A minimum update interval of zero doesn't limit anything, leave it unset.
+    ZeroUpdateInterval,
+    /// Frames in this format can't be read back into a `CpuImage`.
+    UnreadablePixelFormat(PixelFormat),
+    /// The capture backend can't do what this option asks for, e.g. because
+    /// the Windows bindings are too old. Holds what the option asks for.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for InvalidCaptureOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidCaptureOptions::NoBuffers => write!(f, "the buffer count has to be at least 1"),
+            InvalidCaptureOptions::ZeroUpdateInterval => write!(
+                f,
+                "the minimum update interval has to be longer than zero, or unset"
+            ),
+            InvalidCaptureOptions::UnreadablePixelFormat(format) => write!(
+                f,
+                "frames in {:?} can't be read back, only B8G8R8A8UIntNormalized can",
+                format
+            ),
+            InvalidCaptureOptions::Unsupported(option) => {
+                write!(f, "the capture backend doesn't support {}", option)
+            }
+        }
+    }
+}
+
+impl Error for InvalidCaptureOptions {}
+
+/// Settings that apply to a capture session for its whole lifetime. Start from
+/// the defaults and change what's needed, e.g.
+/// `CaptureOptions::default().with_cursor(false).with_buffer_count(2)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CaptureOptions {
+    pub pixel_format: PixelFormat,
+    /// How many frames the frame pool can hold before the test releases them.
+    pub buffer_count: u32,
+    /// Whether the cursor is drawn into captured frames.
+    pub cursor_enabled: bool,
+    /// Whether the yellow capture border is drawn around the captured item.
+    pub border_required: bool,
+    /// The shortest time between two frames, or `None` to not limit it. The
+    /// Windows.Graphics.Capture backend doesn't support this yet.
+    pub min_update_interval: Option<Duration>,
+    /// How dirty regions are reported, or `None` to not report them. The
+    /// Windows.Graphics.Capture backend doesn't support this yet.
+    pub dirty_region_mode: Option<DirtyRegionMode>,
+    pub frame_pool: FramePoolKind,
+    /// Whether a frame that still has the size from before the item was
+    /// resized fails the test. Otherwise such frames are handed out as they are.
+    pub fail_on_stale_frames: bool,
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        Self {
+            pixel_format: PixelFormat::B8G8R8A8UIntNormalized,
+            buffer_count: 1,
+            cursor_enabled: true,
+            border_required: true,
+            min_update_interval: None,
+            dirty_region_mode: None,
+            frame_pool: FramePoolKind::FreeThreaded,
+            fail_on_stale_frames: false,
+        }
+    }
+}
+
+impl CaptureOptions {
+    pub fn with_pixel_format(mut self, pixel_format: PixelFormat) -> Self {
+        self.pixel_format = pixel_format;
+        self
+    }
+
+    pub fn with_buffer_count(mut self, buffer_count: u32) -> Self {
+        self.buffer_count = buffer_count;
+        self
+    }
+
+    pub fn with_cursor(mut self, cursor_enabled: bool) -> Self {
+        self.cursor_enabled = cursor_enabled;
+        self
+    }
+
+    pub fn with_border(mut self, border_required: bool) -> Self {
+        self.border_required = border_required;
+        self
+    }
+
+    pub fn with_min_update_interval(mut self, interval: Duration) -> Self {
+        self.min_update_interval = Some(interval);
+        self
+    }
+
+    pub fn with_dirty_regions(mut self, mode: DirtyRegionMode) -> Self {
+        self.dirty_region_mode = Some(mode);
+        self
+    }
+
+    pub fn with_frame_pool(mut self, frame_pool: FramePoolKind) -> Self {
+        self.frame_pool = frame_pool;
+        self
+    }
+
+    pub fn with_fail_on_stale_frames(mut self, fail_on_stale_frames: bool) -> Self {
+        self.fail_on_stale_frames = fail_on_stale_frames;
+        self
+    }
+
+    /// Checks that the options make sense together.
+    pub fn validate(&self) -> Result<(), InvalidCaptureOptions> {
+        if self.buffer_count == 0 {
+            return Err(InvalidCaptureOptions::NoBuffers);
+        }
+        if self.min_update_interval == Some(Duration::ZERO) {
+            return Err(InvalidCaptureOptions::ZeroUpdateInterval);
+        }
+        Ok(())
+    }
+
+    /// Like `validate`, but also checks that frames can be read back into a
+    /// `CpuImage`, which is what `CaptureSession` hands out.
+    pub fn validate_for_read_back(&self) -> Result<(), InvalidCaptureOptions> {
+        self.validate()?;
+        if self.pixel_format != PixelFormat::B8G8R8A8UIntNormalized {
+            return Err(InvalidCaptureOptions::UnreadablePixelFormat(
+                self.pixel_format,
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Where a capture session is in its lifetime.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionState {
+    Capturing,
+    /// The captured item went away, no more frames will arrive.
+    ItemClosed,
+    /// The session was closed by the test.
+    Closed,
+}
+
+/// Keeps track of a session's `SessionState`, for `CaptureSession`
+/// implementations.
+#[derive(Clone, Copy, Debug)]
+pub struct Lifecycle {
+    state: SessionState,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self {
+            state: SessionState::Capturing,
+        }
+    }
+}
+
+impl Lifecycle {
+    pub fn state(&self) -> SessionState {
+        self.state
+    }
+
+    /// The captured item went away. This doesn't change a closed session.
+    pub fn item_closed(&mut self) {
+        if self.state == SessionState::Capturing {
+            self.state = SessionState::ItemClosed;
+        }
+    }
+
+    /// The test closed the session. Returns whether the session still needs to
+    /// be torn down, which is only the case the first time.
+    pub fn close(&mut self) -> bool {
+        let was_open = self.state != SessionState::Closed;
+        self.state = SessionState::Closed;
+        was_open
+    }
+
+    /// What `next_frame` fails with when no frame arrived within `timeout`.
+    pub fn no_frame(&self, timeout: Duration) -> TestError {
+        match self.state {
+            SessionState::ItemClosed => TestError::ItemClosed,
+            SessionState::Capturing | SessionState::Closed => TestError::Timeout {
+                waiting_for: "the next frame".to_owned(),
+                timeout,
+            },
+        }
+    }
+}
+
+/// A frame delivered by a capture session, read back into system memory.
+#[derive(Clone, Debug)]
+pub struct CaptureFrame {
+    pub image: CpuImage,
+    /// When the frame was produced, relative to an arbitrary point in time that
+    /// stays the same for the whole session.
+    pub timestamp: Duration,
+    /// The size of the captured item when the frame was produced. This can be
+    /// smaller than the image if the item shrank since the session started.
+    pub content_size: (u32, u32),
+    /// The parts of the image that changed since the previous frame, or `None`
+    /// if the backend doesn't report them.
+    pub dirty_regions: Option<Vec<Rect>>,
+}
+
+/// The frame that satisfied `CaptureSession::wait_for_frame`, and what it took
+/// to get there.
+#[derive(Clone, Debug)]
+pub struct FrameMatch {
+    pub frame: CaptureFrame,
+    /// How many frames were checked, including the matching one.
+    pub frames_checked: u32,
+    pub elapsed: Duration,
+}
+
+/// Something that can capture an item, e.g. Windows.Graphics.Capture.
+pub trait CaptureBackend {
+    /// What can be captured, e.g. a `GraphicsCaptureItem`.
+    type Item;
+    type Session: CaptureSession;
+
+    /// Starts capturing `item`. Frames are produced from this point on.
+    fn start(&self, item: &Self::Item, options: CaptureOptions) -> TestResult<Self::Session>;
+}
+
+/// A running capture of a single item.
+pub trait CaptureSession {
+    /// Waits up to `timeout` for the next frame. Fails with `TestError::Timeout`
+    /// if no frame arrives in time, which is also what happens once the
+    /// session has been closed. Once the captured item has gone away and the
+    /// frames from before that have been delivered, fails with
+    /// `TestError::ItemClosed` right away.
+    fn next_frame(&mut self, timeout: Duration) -> TestResult<CaptureFrame>;
+
+    /// The current size of the captured item.
+    fn content_size(&self) -> TestResult<(u32, u32)>;
+
+    /// Stops the capture. Closing a session more than once is allowed.
+    fn close(&mut self) -> TestResult<()>;
+
+    fn state(&self) -> SessionState;
+
+    /// Every time the captured item changed size and the session followed it,
+    /// oldest first.
+    fn size_changes(&self) -> &[SizeChange];
+
+    /// The options the session was started with.
+    fn options(&self) -> CaptureOptions;
+
+    /// The timing of every frame received so far, with arrivals on the
+    /// session's clock.
+    fn timing(&self) -> &TimingRecorder;
+
+    /// The session's clock. Only the difference between two readings means
+    /// anything.
+    fn now(&self) -> Duration;
+
+    /// Drains frames until the captured item goes away, for up to `timeout`,
+    /// e.g. to check that closing a window ends its capture.
+    fn wait_for_item_closed(&mut self, timeout: Duration) -> TestResult<()> {
+        let start = self.now();
+        loop {
+            let elapsed = self.now().saturating_sub(start);
+            if elapsed >= timeout {
+                break;
+            }
+            match self.next_frame(timeout - elapsed) {
+                Ok(_) => {}
+                Err(TestError::ItemClosed) => return Ok(()),
+                Err(TestError::Timeout { .. }) => break,
+                Err(error) => return Err(error),
+            }
+        }
+        Err(TestError::Timeout {
+            waiting_for: "the captured item to close".to_owned(),
+            timeout,
+        })
+    }
+
+    /// Keeps pulling frames until one satisfies `predicate`, e.g. until the
+    /// center is green, for up to `timeout`. `condition` describes what is being
+    /// waited for in the failure, e.g. "the center to turn green". If no frame
+    /// matches in time, the last few frames are attached to the failure.
+    fn wait_for_frame<P>(
+        &mut self,
+        condition: &str,
+        mut predicate: P,
+        timeout: Duration,
+    ) -> TestResult<FrameMatch>
+    where
+        Self: Sized,
+        P: FnMut(&CaptureFrame) -> bool,
+    {
+        let start = self.now();
+        let mut frames_checked = 0;
+        let mut recent_frames = VecDeque::with_capacity(WAIT_ARTIFACT_FRAMES);
+        loop {
+            let elapsed = self.now().saturating_sub(start);
+            if elapsed >= timeout {
+                break;
+            }
+            match self.next_frame(timeout - elapsed) {
+                Ok(frame) => {
+                    frames_checked += 1;
+                    if predicate(&frame) {
+                        return Ok(FrameMatch {
+                            frame,
+                            frames_checked,
+                            elapsed: self.now().saturating_sub(start),
+                        });
+                    }
+                    if recent_frames.len() == WAIT_ARTIFACT_FRAMES {
+                        recent_frames.pop_front();
+                    }
+                    recent_frames.push_back(frame);
+                }
+                Err(TestError::Timeout { .. }) => break,
+                Err(error) => return Err(error),
+            }
+        }
+
+        let mut details = vec![
+            Detail {
+                label: "Frames checked",
+                value: Value::Count(frames_checked as u64),
+            },
+            Detail {
+                label: "Timeout (s)",
+                value: Value::Number(timeout.as_secs_f64()),
+            },
+        ];
+        if let Some(last) = recent_frames.back() {
+            details.push(Detail {
+                label: "Last content size",
+                value: Value::Size {
+                    width: last.content_size.0,
+                    height: last.content_size.1,
+                },
+            });
+        }
+        // The last frame is the capture, the ones before it are numbered with
+        // the oldest getting the lowest number.
+        let first_number = frames_checked as usize - recent_frames.len();
+        let capture = recent_frames
+            .pop_back()
+            .map(|frame| Capture::Image(frame.image));
+        let artifacts = recent_frames
+            .into_iter()
+            .enumerate()
+            .map(|(i, frame)| Artifact {
+                name: format!("frame{}", first_number + i),
+                image: frame.image,
+            })
+            .collect();
+        Err(TestError::Assertion(Box::new(AssertionError {
+            failure: AssertionFailure {
+                subject: Some(condition.to_owned()),
+                details,
+                ..AssertionFailure::new(AssertionKind::FrameWait)
+            },
+            capture,
+            artifacts,
+        })))
+    }
+}
+
+/// A `wait_for_frame` predicate that is satisfied once the pixel in the center
+/// of the content is within `tolerance` of `expected` on every channel.
+pub fn center_is(expected: Color, tolerance: u8) -> impl Fn(&CaptureFrame) -> bool {
+    move |frame| {
+        let width = frame.content_size.0.min(frame.image.width());
+        let height = frame.content_size.1.min(frame.image.height());
+        frame
+            .image
+            .read_pixel(width / 2, height / 2)
+            .is_some_and(|actual| colors_match(actual, expected, tolerance))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::common_colors, cpu_image::CpuImage, fake_capture::FakeBackend};
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn frame(color: Color) -> CpuImage {
+        CpuImage::from_fn(4, 4, |_, _| color)
+    }
+
+    fn session(colors: &[Color]) -> impl CaptureSession {
+        let backend = colors
+            .iter()
+            .enumerate()
+            .fold(FakeBackend::new(), |backend, (i, color)| {
+                backend.with_frame(frame(*color), ms(10 * i as u64))
+            });
+        backend.start(&(), CaptureOptions::default()).unwrap()
+    }
+
+    fn assertion(error: TestError) -> AssertionError {
+        match error {
+            TestError::Assertion(error) => *error,
+            error => panic!("expected an assertion, got {}", error),
+        }
+    }
+
+    #[test]
+    fn options_are_validated() {
+        let options = CaptureOptions::default();
+        assert_eq!(options.validate(), Ok(()));
+        assert_eq!(options.validate_for_read_back(), Ok(()));
+        assert_eq!(options.with_min_update_interval(ms(16)).validate(), Ok(()));
+
+        assert_eq!(
+            options.with_buffer_count(0).validate(),
+            Err(InvalidCaptureOptions::NoBuffers)
+        );
+        assert_eq!(
+            options
+                .with_min_update_interval(Duration::ZERO)
+                .validate_for_read_back(),
+            Err(InvalidCaptureOptions::ZeroUpdateInterval)
+        );
+
+        let float = options.with_pixel_format(PixelFormat::R16G16B16A16Float);
+        assert_eq!(float.validate(), Ok(()));
+        assert_eq!(
+            float.validate_for_read_back(),
+            Err(InvalidCaptureOptions::UnreadablePixelFormat(
+                PixelFormat::R16G16B16A16Float
+            ))
+        );
+        assert_eq!(
+            float.validate_for_read_back().unwrap_err().to_string(),
+            "frames in R16G16B16A16Float can't be read back, only B8G8R8A8UIntNormalized can"
+        );
+        assert_eq!(
+            InvalidCaptureOptions::Unsupported("dirty regions").to_string(),
+            "the capture backend doesn't support dirty regions"
+        );
+    }
+
+    #[test]
+    fn sessions_reject_invalid_options() {
+        let backend = FakeBackend::new();
+        let error = backend
+            .start(&(), CaptureOptions::default().with_buffer_count(0))
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error,
+            TestError::InvalidOptions(InvalidCaptureOptions::NoBuffers)
+        ));
+        assert_eq!(error.kind(), "invalid_options");
+    }
+
+    #[test]
+    fn lifecycle_only_moves_forward() {
+        let mut lifecycle = Lifecycle::default();
+        assert_eq!(lifecycle.state(), SessionState::Capturing);
+        assert!(matches!(
+            lifecycle.no_frame(ms(5)),
+            TestError::Timeout { .. }
+        ));
+        lifecycle.item_closed();
+        assert_eq!(lifecycle.state(), SessionState::ItemClosed);
+        assert!(matches!(lifecycle.no_frame(ms(5)), TestError::ItemClosed));
+        assert!(lifecycle.close());
+        assert_eq!(lifecycle.state(), SessionState::Closed);
+        // Closing again has nothing left to tear down, and a closed session
+        // stays closed.
+        assert!(!lifecycle.close());
+        lifecycle.item_closed();
+        assert_eq!(lifecycle.state(), SessionState::Closed);
+        assert!(matches!(
+            lifecycle.no_frame(ms(5)),
+            TestError::Timeout { .. }
+        ));
+    }
+
+    #[test]
+    fn frames_queued_before_the_item_closes_are_delivered() {
+        let backend = FakeBackend::new()
+            .with_frame(frame(common_colors::RED), ms(0))
+            .with_frame(frame(common_colors::GREEN), ms(10))
+            .with_frame(frame(common_colors::BLUE), ms(20))
+            .with_item_closed_at(ms(25))
+            .with_frame(frame(common_colors::RED), ms(30));
+        let mut session = backend.start(&(), CaptureOptions::default()).unwrap();
+        for timestamp in [0, 10, 20] {
+            assert_eq!(
+                session.next_frame(ms(100)).unwrap().timestamp,
+                ms(timestamp)
+            );
+            assert_eq!(session.state(), SessionState::Capturing);
+        }
+
+        // The frame after the item closed never arrives.
+        assert!(matches!(
+            session.next_frame(ms(100)),
+            Err(TestError::ItemClosed)
+        ));
+        assert_eq!(session.state(), SessionState::ItemClosed);
+        assert_eq!(session.now(), ms(25));
+        // Waiting again fails right away, without moving the clock.
+        assert!(matches!(
+            session.next_frame(ms(100)),
+            Err(TestError::ItemClosed)
+        ));
+        assert_eq!(session.now(), ms(25));
+
+        session.close().unwrap();
+        assert_eq!(session.state(), SessionState::Closed);
+        assert!(matches!(
+            session.next_frame(ms(100)),
+            Err(TestError::Timeout { .. })
+        ));
+        session.close().unwrap();
+    }
+
+    #[test]
+    fn item_closing_after_the_timeout_is_a_timeout() {
+        let backend = FakeBackend::new().with_item_closed_at(ms(150));
+        let mut session = backend.start(&(), CaptureOptions::default()).unwrap();
+        assert!(matches!(
+            session.next_frame(ms(100)),
+            Err(TestError::Timeout { .. })
+        ));
+        assert_eq!(session.state(), SessionState::Capturing);
+        assert!(session.wait_for_item_closed(ms(100)).is_ok());
+        assert_eq!(session.now(), ms(150));
+    }
+
+    #[test]
+    fn waiting_for_an_item_that_stays_open_times_out() {
+        let backend = FakeBackend::new()
+            .with_frame(frame(common_colors::RED), ms(0))
+            .with_frame(frame(common_colors::RED), ms(50));
+        let mut session = backend.start(&(), CaptureOptions::default()).unwrap();
+        let error = session.wait_for_item_closed(ms(100)).unwrap_err();
+        assert!(matches!(error, TestError::Timeout { .. }));
+        // Both frames were drained on the way.
+        assert_eq!(session.timing().timings().len(), 2);
+        assert_eq!(session.now(), ms(100));
+    }
+
+    #[test]
+    fn matching_frame_is_returned_with_the_frames_checked() {
+        let mut session = session(&[common_colors::RED, common_colors::RED, common_colors::GREEN]);
+        let found = session
+            .wait_for_frame(
+                "the center to turn green",
+                center_is(common_colors::GREEN, 0),
+                ms(100),
+            )
+            .unwrap();
+        assert_eq!(found.frames_checked, 3);
+        assert_eq!(found.frame.timestamp, ms(20));
+        assert_eq!(found.elapsed, ms(20));
+    }
+
+    #[test]
+    fn timeout_attaches_the_last_frame_once() {
+        let colors = [
+            common_colors::RED,
+            common_colors::BLUE,
+            common_colors::RED,
+            common_colors::BLUE,
+            common_colors::RED,
+        ];
+        let mut session = session(&colors);
+        let error = assertion(
+            session
+                .wait_for_frame(
+                    "the center to turn green",
+                    center_is(common_colors::GREEN, 0),
+                    ms(100),
+                )
+                .unwrap_err(),
+        );
+        assert_eq!(
+            error.failure.subject.as_deref(),
+            Some("the center to turn green")
+        );
+        let Some(Capture::Image(capture)) = &error.capture else {
+            panic!("expected the last frame as the capture");
+        };
+        assert_eq!(capture.read_pixel(0, 0), Some(common_colors::RED));
+        // Frames 0 to 4 were checked, 4 is the capture.
+        let names: Vec<_> = error
+            .artifacts
+            .iter()
+            .map(|artifact| artifact.name.as_str())
+            .collect();
+        assert_eq!(names, ["frame2", "frame3"]);
+        assert_eq!(
+            error.artifacts[1].image.read_pixel(0, 0),
+            Some(common_colors::BLUE)
+        );
+    }
+
+    #[test]
+    fn timeout_without_frames_has_no_capture() {
+        let mut session = session(&[]);
+        let error = assertion(
+            session
+                .wait_for_frame("any frame", |_| true, ms(100))
+                .unwrap_err(),
+        );
+        assert!(error.capture.is_none());
+        assert!(error.artifacts.is_empty());
+        assert_eq!(session.now(), ms(100));
+    }
+
+    #[test]
+    fn item_closing_ends_the_wait() {
+        let backend = FakeBackend::new()
+            .with_frame(frame(common_colors::RED), ms(0))
+            .with_item_closed_at(ms(50));
+        let mut session = backend.start(&(), CaptureOptions::default()).unwrap();
+        let error = session
+            .wait_for_frame(
+                "the center to turn green",
+                center_is(common_colors::GREEN, 0),
+                ms(100),
+            )
+            .unwrap_err();
+        assert!(matches!(error, TestError::ItemClosed));
+        assert_eq!(session.now(), ms(50));
+    }
+}
@@ -0,0 +1,352 @@
+use super::{
+    assertion::{AssertionFailure, AssertionKind, Capture, Detail, Tolerance, Value},
+    color_math::diagnose_color_mismatch,
+    cpu_image::CpuImage,
+    error::{AssertionError, TestError, TestResult},
+    geometry::Rect,
+    mask::Mask,
+    neighborhood::Neighborhood,
+};
+
+/// An 8-bit per channel color with straight alpha. The fields mirror
+/// `windows::UI::Color` so that colors convert field by field.
+#[allow(non_snake_case)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub A: u8,
+    pub R: u8,
+    pub G: u8,
+    pub B: u8,
+}
+
+#[cfg(feature = "windows")]
+impl From<Color> for windows::UI::Color {
+    fn from(color: Color) -> Self {
+        Self {
+            A: color.A,
+            R: color.R,
+            G: color.G,
+            B: color.B,
+        }
+    }
+}
+
+#[cfg(feature = "windows")]
+impl From<windows::UI::Color> for Color {
+    fn from(color: windows::UI::Color) -> Self {
+        Self {
+            A: color.A,
+            R: color.R,
+            G: color.G,
+            B: color.B,
+        }
+    }
+}
+
+/// How many pixels across the neighborhood shown in pixel check failures is.
+pub const NEIGHBORHOOD_SIZE: u32 = 5;
+
+pub enum ColorCheck {
+    Success,
+    Different(AssertionFailure),
+}
+
+impl ColorCheck {
+    /// Turns a failed check into an error. `capture` is only called if the
+    /// check failed and provides the image to save with it.
+    pub fn ok_with(self, capture: impl FnOnce() -> Capture) -> TestResult<()> {
+        match self {
+            ColorCheck::Success => Ok(()),
+            ColorCheck::Different(failure) => TestError::Assertion(Box::new(AssertionError {
+                failure,
+                capture: Some(capture()),
+                artifacts: Vec::new(),
+            }))
+            .ok(),
+        }
+    }
+
+    pub fn ok_with_image(self, image: &CpuImage) -> TestResult<()> {
+        self.ok_with(|| Capture::Image(image.clone()))
+    }
+}
+
+pub fn check_color(actual: Color, expected: Color) -> ColorCheck {
+    if actual != expected {
+        let mut failure = AssertionFailure {
+            expected: Some(Value::Color(expected)),
+            actual: Some(Value::Color(actual)),
+            ..AssertionFailure::new(AssertionKind::PixelColor)
+        };
+        if let Some(cause) = diagnose_color_mismatch(actual, expected) {
+            failure.details.push(Detail {
+                label: "Likely cause",
+                value: Value::Text(cause.to_owned()),
+            });
+        }
+        ColorCheck::Different(failure)
+    } else {
+        ColorCheck::Success
+    }
+}
+
+/// Checks the pixel at (`x`, `y`) of an image of `image_size` read through
+/// `read_pixel`, which returns `None` outside of the image. If it doesn't match,
+/// the failure includes the `neighborhood_size` x `neighborhood_size` pixels
+/// around it. Probing outside of the image fails the check.
+pub fn check_pixel_with(
+    x: u32,
+    y: u32,
+    expected: Color,
+    neighborhood_size: u32,
+    image_size: (u32, u32),
+    read_pixel: impl Fn(u32, u32) -> Option<Color>,
+) -> ColorCheck {
+    let Some(actual) = read_pixel(x, y) else {
+        return ColorCheck::Different(AssertionFailure {
+            expected: Some(Value::Color(expected)),
+            details: vec![
+                Detail {
+                    label: "Probed pixel",
+                    value: Value::Text(format!("({}, {})", x, y)),
+                },
+                Detail {
+                    label: "Image size",
+                    value: Value::Size {
+                        width: image_size.0,
+                        height: image_size.1,
+                    },
+                },
+            ],
+            notes: vec!["The probed pixel is outside of the image.".to_owned()],
+            ..AssertionFailure::new(AssertionKind::PixelColor)
+        });
+    };
+    match check_color(actual, expected) {
+        ColorCheck::Success => ColorCheck::Success,
+        ColorCheck::Different(mut failure) => {
+            let neighborhood = Neighborhood::sample((x, y), neighborhood_size, read_pixel);
+            failure.location = Some((x, y));
+            failure
+                .notes
+                .push(format!("Neighborhood (BGRA):\n{}", neighborhood));
+            ColorCheck::Different(failure)
+        }
+    }
+}
+
+/// Checks the pixel at (`x`, `y`) of an image, see [`check_pixel_with`].
+pub fn check_image_pixel(
+    image: &CpuImage,
+    x: u32,
+    y: u32,
+    expected: Color,
+    neighborhood_size: u32,
+) -> ColorCheck {
+    check_pixel_with(
+        x,
+        y,
+        expected,
+        neighborhood_size,
+        (image.width(), image.height()),
+        |x, y| image.read_pixel(x, y),
+    )
+}
+
+/// Checks that every pixel in `region` is within `tolerance` of `expected` on every
+/// channel, skipping the pixels ignored by `mask`. Parts of the region outside of
+/// the image are skipped as well. Fails if the mask doesn't have the image's size.
+pub fn expect_region_color(
+    image: &CpuImage,
+    region: Rect,
+    expected: Color,
+    tolerance: u8,
+    mask: Option<&Mask>,
+) -> TestResult<()> {
+    if let Some(Err(mismatch)) = mask.map(|mask| mask.expect_size(image.width(), image.height())) {
+        return TestError::Assertion(Box::new(AssertionError {
+            failure: AssertionFailure {
+                region: Some(region),
+                details: mismatch.details(),
+                notes: vec![format!("Nothing was compared, {}.", mismatch)],
+                ..AssertionFailure::new(AssertionKind::RegionColor)
+            },
+            capture: Some(Capture::Image(image.clone())),
+            artifacts: Vec::new(),
+        }))
+        .ok();
+    }
+
+    let left = region.x.max(0) as u32;
+    let top = region.y.max(0) as u32;
+    let right = region.right().clamp(0, image.width() as i32) as u32;
+    let bottom = region.bottom().clamp(0, image.height() as i32) as u32;
+
+    let mut compared_pixels = 0u64;
+    let mut mismatched_pixels = 0u64;
+    let mut first_mismatch = None;
+    for y in top..bottom {
+        for x in left..right {
+            if mask.is_some_and(|mask| mask.is_ignored(x, y)) {
+                continue;
+            }
+            compared_pixels += 1;
+            let actual = image.read_pixel(x, y).unwrap();
+            let difference = [
+                actual.B.abs_diff(expected.B),
+                actual.G.abs_diff(expected.G),
+                actual.R.abs_diff(expected.R),
+                actual.A.abs_diff(expected.A),
+            ]
+            .into_iter()
+            .max()
+            .unwrap();
+            if difference > tolerance {
+                mismatched_pixels += 1;
+                first_mismatch.get_or_insert((x, y, actual));
+            }
+        }
+    }
+
+    match first_mismatch {
+        None => Ok(()),
+        Some((x, y, actual)) => {
+            let neighborhood =
+                Neighborhood::sample((x, y), NEIGHBORHOOD_SIZE, |x, y| image.read_pixel(x, y));
+            TestError::Assertion(Box::new(AssertionError {
+                failure: AssertionFailure {
+                    location: Some((x, y)),
+                    region: Some(region),
+                    expected: Some(Value::Color(expected)),
+                    actual: Some(Value::Color(actual)),
+                    tolerance: Some(Tolerance::PerPixel(tolerance)),
+                    details: vec![
+                        Detail {
+                            label: "Mismatched pixels",
+                            value: Value::Count(mismatched_pixels),
+                        },
+                        Detail {
+                            label: "Compared pixels",
+                            value: Value::Count(compared_pixels),
+                        },
+                        Detail {
+                            label: "Ignored pixels",
+                            value: Value::Count(
+                                mask.map_or(0, |mask| mask.ignored_pixels() as u64),
+                            ),
+                        },
+                    ],
+                    notes: vec![format!("Neighborhood (BGRA):\n{}", neighborhood)],
+                    ..AssertionFailure::new(AssertionKind::RegionColor)
+                },
+                capture: Some(Capture::Image(image.clone())),
+                artifacts: Vec::new(),
+            }))
+            .ok()
+        }
+    }
+}
+
+pub mod common_colors {
+    use super::Color;
+
+    pub const TRANSPARENT_BLACK: Color = Color {
+        A: 0,
+        R: 0,
+        G: 0,
+        B: 0,
+    };
+    pub const RED: Color = Color {
+        A: 255,
+        R: 255,
+        G: 0,
+        B: 0,
+    };
+    pub const BLUE: Color = Color {
+        A: 255,
+        R: 0,
+        G: 0,
+        B: 255,
+    };
+    pub const GREEN: Color = Color {
+        A: 255,
+        R: 0,
+        G: 255,
+        B: 0,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn green_image() -> CpuImage {
+        CpuImage::from_fn(4, 3, |_, _| common_colors::GREEN)
+    }
+
+    #[test]
+    fn matching_pixel_passes() {
+        let image = green_image();
+        assert!(
+            check_image_pixel(&image, 3, 2, common_colors::GREEN, NEIGHBORHOOD_SIZE)
+                .ok_with_image(&image)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn mismatched_pixel_reports_location_and_cause() {
+        let image = green_image();
+        let ColorCheck::Different(failure) =
+            check_image_pixel(&image, 1, 1, common_colors::RED, NEIGHBORHOOD_SIZE)
+        else {
+            panic!("the check should fail");
+        };
+        assert_eq!(failure.location, Some((1, 1)));
+        assert!(failure.notes[0].starts_with("Neighborhood (BGRA):"));
+    }
+
+    #[test]
+    fn out_of_bounds_probe_fails_instead_of_panicking() {
+        let image = green_image();
+        let error = check_image_pixel(&image, 4, 1, common_colors::GREEN, NEIGHBORHOOD_SIZE)
+            .ok_with_image(&image)
+            .unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("Probed pixel: (4, 1)"), "{}", message);
+        assert!(message.contains("Image size: 4 x 3"), "{}", message);
+    }
+
+    #[test]
+    fn region_skips_masked_pixels() {
+        let mut image = green_image();
+        image.write_pixel(2, 1, common_colors::RED);
+        let region = Rect::new(0, 0, 4, 3);
+        assert!(expect_region_color(&image, region, common_colors::GREEN, 0, None).is_err());
+
+        let mut mask = Mask::new(4, 3);
+        mask.ignore_rect(Rect::new(2, 1, 1, 1));
+        expect_region_color(&image, region, common_colors::GREEN, 0, Some(&mask)).unwrap();
+    }
+
+    #[test]
+    fn region_with_a_mask_of_another_size_fails() {
+        let image = green_image();
+        let region = Rect::new(0, 0, 4, 3);
+        let mask = Mask::new(500, 500);
+        let Err(TestError::Assertion(error)) =
+            expect_region_color(&image, region, common_colors::GREEN, 0, Some(&mask))
+        else {
+            panic!("the check should fail");
+        };
+        assert_eq!(error.failure.kind, AssertionKind::RegionColor);
+        assert!(matches!(
+            error.failure.details[1].value,
+            Value::Size {
+                width: 4,
+                height: 3
+            }
+        ));
+        assert!(error.failure.notes[0].starts_with("Nothing was compared"));
+    }
+}
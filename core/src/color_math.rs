@@ -0,0 +1,234 @@
+use super::color::Color;
+
+// Conversions can be off by one from rounding, so allow a little slack when
+// checking whether a converted color matches.
+const HYPOTHESIS_TOLERANCE: u8 = 2;
+
+/// A possible fix for a color, along with the mixup it would explain.
+type Hypothesis = (fn(Color) -> Color, &'static str);
+
+pub fn swap_red_blue(color: Color) -> Color {
+    Color {
+        R: color.B,
+        B: color.R,
+        ..color
+    }
+}
+
+pub fn premultiply(color: Color) -> Color {
+    let scale = |channel: u8| ((channel as u32 * color.A as u32 + 127) / 255) as u8;
+    Color {
+        A: color.A,
+        R: scale(color.R),
+        G: scale(color.G),
+        B: scale(color.B),
+    }
+}
+
+/// Converts a premultiplied color to straight alpha. Fully transparent colors
+/// stay transparent black.
+pub fn unpremultiply(color: Color) -> Color {
+    if color.A == 0 {
+        return Color::default();
+    }
+    let scale =
+        |channel: u8| ((channel as u32 * 255 + color.A as u32 / 2) / color.A as u32).min(255) as u8;
+    Color {
+        A: color.A,
+        R: scale(color.R),
+        G: scale(color.G),
+        B: scale(color.B),
+    }
+}
+
+/// Decodes the sRGB transfer function from the color channels. Alpha is linear
+/// either way and is left alone.
+pub fn srgb_to_linear(color: Color) -> Color {
+    map_color_channels(color, |value| {
+        if value <= 0.04045 {
+            value / 12.92
+        } else {
+            ((value + 0.055) / 1.055).powf(2.4)
+        }
+    })
+}
+
+/// Applies the sRGB transfer function to the color channels. Alpha is left alone.
+pub fn linear_to_srgb(color: Color) -> Color {
+    map_color_channels(color, |value| {
+        if value <= 0.0031308 {
+            value * 12.92
+        } else {
+            1.055 * value.powf(1.0 / 2.4) - 0.055
+        }
+    })
+}
+
+pub fn colors_match(a: Color, b: Color, tolerance: u8) -> bool {
+    a.B.abs_diff(b.B) <= tolerance
+        && a.G.abs_diff(b.G) <= tolerance
+        && a.R.abs_diff(b.R) <= tolerance
+        && a.A.abs_diff(b.A) <= tolerance
+}
+
+/// Checks whether a common mixup explains why `actual` isn't `expected`: the
+/// channel order, the alpha mode or the transfer function. Each hypothesis is
+/// applied to `actual`, and the first one that turns it into `expected` is returned.
+/// Colors that are only off by rounding have no diagnosis, and neither does a
+/// hypothesis that barely changes `actual`, e.g. swapping red and blue when they
+/// are about the same.
+pub fn diagnose_color_mismatch(actual: Color, expected: Color) -> Option<&'static str> {
+    if colors_match(actual, expected, HYPOTHESIS_TOLERANCE) {
+        return None;
+    }
+    let hypotheses: [Hypothesis; 7] = [
+        (
+            swap_red_blue,
+            "red and blue are swapped (BGRA and RGBA mixed up)",
+        ),
+        (
+            unpremultiply,
+            "the actual color is premultiplied, but straight alpha was expected",
+        ),
+        (
+            premultiply,
+            "the actual color has straight alpha, but premultiplied alpha was expected",
+        ),
+        (
+            linear_to_srgb,
+            "the actual color is linear, but sRGB was expected",
+        ),
+        (
+            srgb_to_linear,
+            "the actual color is sRGB, but linear was expected",
+        ),
+        (
+            |color| swap_red_blue(unpremultiply(color)),
+            "red and blue are swapped and the actual color is premultiplied",
+        ),
+        (
+            |color| swap_red_blue(premultiply(color)),
+            "red and blue are swapped and the actual color has straight alpha",
+        ),
+    ];
+    hypotheses
+        .iter()
+        .find(|(fix, _)| {
+            let fixed = fix(actual);
+            !colors_match(fixed, actual, HYPOTHESIS_TOLERANCE)
+                && colors_match(fixed, expected, HYPOTHESIS_TOLERANCE)
+        })
+        .map(|(_, cause)| *cause)
+}
+
+fn map_color_channels<F: Fn(f64) -> f64>(color: Color, transfer: F) -> Color {
+    let convert = |channel: u8| {
+        (transfer(channel as f64 / 255.0) * 255.0)
+            .round()
+            .clamp(0.0, 255.0) as u8
+    };
+    Color {
+        A: color.A,
+        R: convert(color.R),
+        G: convert(color.G),
+        B: convert(color.B),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::common_colors;
+
+    fn color(a: u8, r: u8, g: u8, b: u8) -> Color {
+        Color {
+            A: a,
+            R: r,
+            G: g,
+            B: b,
+        }
+    }
+
+    #[test]
+    fn swapped_channels_are_diagnosed() {
+        assert_eq!(
+            diagnose_color_mismatch(common_colors::BLUE, common_colors::RED),
+            Some("red and blue are swapped (BGRA and RGBA mixed up)")
+        );
+    }
+
+    #[test]
+    fn premultiplied_alpha_is_diagnosed() {
+        let straight = color(128, 200, 100, 50);
+        assert_eq!(
+            diagnose_color_mismatch(premultiply(straight), straight),
+            Some("the actual color is premultiplied, but straight alpha was expected")
+        );
+        assert_eq!(
+            diagnose_color_mismatch(straight, premultiply(straight)),
+            Some("the actual color has straight alpha, but premultiplied alpha was expected")
+        );
+    }
+
+    #[test]
+    fn transfer_function_is_diagnosed() {
+        let srgb = color(255, 180, 90, 30);
+        assert_eq!(
+            diagnose_color_mismatch(srgb_to_linear(srgb), srgb),
+            Some("the actual color is linear, but sRGB was expected")
+        );
+    }
+
+    #[test]
+    fn combined_mixups_are_diagnosed() {
+        let straight = color(100, 220, 40, 10);
+        assert_eq!(
+            diagnose_color_mismatch(swap_red_blue(premultiply(straight)), straight),
+            Some("red and blue are swapped and the actual color is premultiplied")
+        );
+    }
+
+    #[test]
+    fn rounding_errors_have_no_diagnosis() {
+        // Swapping red and blue barely changes this color, so it doesn't
+        // explain the difference.
+        assert_eq!(
+            diagnose_color_mismatch(color(255, 0, 255, 1), common_colors::GREEN),
+            None
+        );
+        // Colors that are within rounding of each other need no explanation.
+        assert_eq!(
+            diagnose_color_mismatch(color(255, 1, 254, 0), common_colors::GREEN),
+            None
+        );
+    }
+
+    #[test]
+    fn unrelated_colors_have_no_diagnosis() {
+        assert_eq!(
+            diagnose_color_mismatch(common_colors::GREEN, common_colors::RED),
+            None
+        );
+    }
+
+    #[test]
+    fn alpha_conversions_round_trip() {
+        let straight = color(200, 180, 90, 30);
+        assert!(colors_match(
+            unpremultiply(premultiply(straight)),
+            straight,
+            1
+        ));
+        assert_eq!(unpremultiply(color(0, 10, 20, 30)), Color::default());
+    }
+
+    #[test]
+    fn transfer_functions_round_trip() {
+        let srgb = color(255, 200, 128, 64);
+        assert!(colors_match(
+            linear_to_srgb(srgb_to_linear(srgb)),
+            srgb,
+            HYPOTHESIS_TOLERANCE
+        ));
+    }
+}
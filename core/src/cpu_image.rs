@@ -0,0 +1,169 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::Path,
+};
+
+use super::color::Color;
+
+/// A tightly packed BGRA8 image that lives in system memory. Unlike a mapped
+/// texture, it can be kept around after the test is done with the GPU and
+/// compared or saved without a device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CpuImage {
+    width: u32,
+    height: u32,
+    bytes: Vec<u8>,
+}
+
+impl CpuImage {
+    /// Creates a transparent black image.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self::from_bgra8(width, height, vec![0u8; (width * height * 4) as usize])
+    }
+
+    pub fn from_bgra8(width: u32, height: u32, bytes: Vec<u8>) -> Self {
+        assert_eq!(bytes.len() as u64, width as u64 * height as u64 * 4);
+        Self {
+            width,
+            height,
+            bytes,
+        }
+    }
+
+    /// Creates an image with the color `pixel` returns for every coordinate,
+    /// e.g. to build synthetic images to check against.
+    pub fn from_fn(width: u32, height: u32, pixel: impl Fn(u32, u32) -> Color) -> Self {
+        let mut image = Self::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.write_pixel(x, y, pixel(x, y));
+            }
+        }
+        image
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The pixel data in BGRA order, row by row with no padding.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn read_pixel(&self, x: u32, y: u32) -> Option<Color> {
+        if x < self.width && y < self.height {
+            let offset = ((y * self.width + x) * 4) as usize;
+            let pixel = &self.bytes[offset..offset + 4];
+            Some(Color {
+                B: pixel[0],
+                G: pixel[1],
+                R: pixel[2],
+                A: pixel[3],
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn write_pixel(&mut self, x: u32, y: u32, color: Color) {
+        assert!(x < self.width && y < self.height);
+        let offset = ((y * self.width + x) * 4) as usize;
+        self.bytes[offset..offset + 4].copy_from_slice(&[color.B, color.G, color.R, color.A]);
+    }
+
+    pub fn load_png<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let mut decoder = png::Decoder::new(BufReader::new(file));
+        // Expand palettes and low bit depths so we only have to deal with 8-bit channels.
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(png_error)?;
+        let mut buffer = vec![0u8; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(png_error)?;
+        let buffer = &buffer[..info.buffer_size()];
+
+        let mut bytes = Vec::with_capacity((info.width * info.height * 4) as usize);
+        match info.color_type {
+            png::ColorType::Rgba => {
+                for pixel in buffer.chunks_exact(4) {
+                    bytes.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+                }
+            }
+            png::ColorType::Rgb => {
+                for pixel in buffer.chunks_exact(3) {
+                    bytes.extend_from_slice(&[pixel[2], pixel[1], pixel[0], 255]);
+                }
+            }
+            png::ColorType::GrayscaleAlpha => {
+                for pixel in buffer.chunks_exact(2) {
+                    bytes.extend_from_slice(&[pixel[0], pixel[0], pixel[0], pixel[1]]);
+                }
+            }
+            png::ColorType::Grayscale => {
+                for pixel in buffer {
+                    bytes.extend_from_slice(&[*pixel, *pixel, *pixel, 255]);
+                }
+            }
+            png::ColorType::Indexed => unreachable!("palettes are expanded by the decoder"),
+        }
+
+        Ok(Self::from_bgra8(info.width, info.height, bytes))
+    }
+
+    /// Writes the image as an RGBA8 PNG. Pixel values are written as-is, no
+    /// premultiplied alpha conversion happens, so that loading the file back
+    /// produces exactly the same bytes.
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = File::create(path)?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(png_error)?;
+
+        let mut rgba = Vec::with_capacity(self.bytes.len());
+        for pixel in self.bytes.chunks_exact(4) {
+            rgba.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+        }
+        writer.write_image_data(&rgba).map_err(png_error)?;
+        writer.finish().map_err(png_error)?;
+        Ok(())
+    }
+}
+
+fn png_error<E: std::error::Error + Send + Sync + 'static>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_runner::test_output_dir;
+
+    #[test]
+    fn png_round_trip_keeps_every_byte() {
+        // Includes partially transparent pixels, which must not be
+        // premultiplied or unpremultiplied on the way.
+        let image = CpuImage::from_fn(7, 5, |x, y| Color {
+            B: (x * 30) as u8,
+            G: (y * 50) as u8,
+            R: 200,
+            A: ((x + y) * 20) as u8,
+        });
+        let path = test_output_dir("png-round-trip").join("image.png");
+        image.save_png(&path).unwrap();
+        assert_eq!(CpuImage::load_png(&path).unwrap(), image);
+    }
+
+    #[test]
+    fn loading_something_else_fails() {
+        let path = test_output_dir("png-invalid").join("image.png");
+        std::fs::write(&path, b"not a png").unwrap();
+        let error = CpuImage::load_png(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,244 @@
+//! Monitors and the coordinates on the virtual desktop. Window and monitor
+//! rectangles come in virtual desktop coordinates, where the primary monitor's
+//! top left corner is the origin and other monitors can be at negative
+//! coordinates. A monitor capture's frame starts at its monitor's top left
+//! corner instead.
+
+use super::{
+    assertion::{AssertionFailure, AssertionKind, Detail, Value},
+    error::{AssertionError, TestError},
+    geometry::{Rect, DEFAULT_DPI},
+};
+
+/// A monitor, as found when enumerating them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MonitorDescriptor {
+    /// The whole monitor, in virtual desktop coordinates.
+    pub bounds: Rect,
+    /// The part of the monitor that isn't taken up by the taskbar and other
+    /// app bars, in virtual desktop coordinates.
+    pub work_area: Rect,
+    /// The effective DPI of the monitor.
+    pub dpi: u32,
+    pub is_primary: bool,
+}
+
+impl MonitorDescriptor {
+    /// How many pixels one logical unit takes up, e.g. 1.5 at 144 DPI.
+    pub fn scale_factor(&self) -> f64 {
+        self.dpi as f64 / DEFAULT_DPI as f64
+    }
+
+    /// Moves `rect` from virtual desktop coordinates to coordinates relative
+    /// to the monitor's top left corner.
+    pub fn to_local(&self, rect: &Rect) -> Rect {
+        rect.relative_to((self.bounds.x, self.bounds.y))
+    }
+
+    /// Moves `rect` from monitor coordinates back to virtual desktop
+    /// coordinates.
+    pub fn to_desktop(&self, rect: &Rect) -> Rect {
+        rect.offset(self.bounds.x, self.bounds.y)
+    }
+
+    /// The part of `rect`, given in virtual desktop coordinates, that a capture
+    /// of this monitor shows, relative to the frame. `None` if it's entirely
+    /// on other monitors.
+    pub fn crop_for(&self, rect: &Rect) -> Option<Rect> {
+        rect.intersection(&self.bounds)
+            .map(|visible| self.to_local(&visible))
+    }
+}
+
+/// The smallest rectangle that contains every monitor.
+pub fn virtual_desktop_bounds(monitors: &[MonitorDescriptor]) -> Rect {
+    let Some(first) = monitors.first() else {
+        return Rect::default();
+    };
+    let (mut left, mut top) = (first.bounds.x, first.bounds.y);
+    let (mut right, mut bottom) = (first.bounds.right(), first.bounds.bottom());
+    for monitor in &monitors[1..] {
+        left = left.min(monitor.bounds.x);
+        top = top.min(monitor.bounds.y);
+        right = right.max(monitor.bounds.right());
+        bottom = bottom.max(monitor.bounds.bottom());
+    }
+    Rect::new(left, top, right - left, bottom - top)
+}
+
+/// The monitor that shows the most of `rect`, like Windows picks the monitor
+/// of a window. `None` if `rect` is on none of them.
+pub fn monitor_for_rect<'a>(
+    monitors: &'a [MonitorDescriptor],
+    rect: &Rect,
+) -> Option<&'a MonitorDescriptor> {
+    monitors
+        .iter()
+        .filter_map(|monitor| {
+            rect.intersection(&monitor.bounds)
+                .map(|visible| (monitor, visible.area()))
+        })
+        .max_by_key(|(_, area)| *area)
+        .map(|(monitor, _)| monitor)
+}
+
+/// The failure for a window that a capture of `monitor` doesn't show, e.g.
+/// because it was moved off screen. `monitors` are all of the monitors, to
+/// tell where the window is instead.
+pub fn window_off_monitor_error(
+    monitor: &MonitorDescriptor,
+    monitors: &[MonitorDescriptor],
+    window: &Rect,
+) -> TestError {
+    let rect = |rect: &Rect| Value::Text(rect.to_string());
+    let shown_on = match monitor_for_rect(monitors, window) {
+        Some(other) => rect(&other.bounds),
+        None => Value::Text("none".to_owned()),
+    };
+    TestError::Assertion(Box::new(AssertionError {
+        failure: AssertionFailure {
+            subject: Some("the window is on the captured monitor".to_owned()),
+            details: vec![
+                Detail {
+                    label: "Window bounds",
+                    value: rect(window),
+                },
+                Detail {
+                    label: "Captured monitor",
+                    value: rect(&monitor.bounds),
+                },
+                Detail {
+                    label: "Monitor showing the window",
+                    value: shown_on,
+                },
+                Detail {
+                    label: "Virtual desktop",
+                    value: rect(&virtual_desktop_bounds(monitors)),
+                },
+            ],
+            ..AssertionFailure::new(AssertionKind::WindowPlacement)
+        },
+        capture: None,
+        artifacts: Vec::new(),
+    }))
+}
+
+/// The monitor that contains the point, given in virtual desktop coordinates.
+pub fn monitor_at(monitors: &[MonitorDescriptor], x: i32, y: i32) -> Option<&MonitorDescriptor> {
+    monitors
+        .iter()
+        .find(|monitor| monitor.bounds.contains(x, y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 1080p primary monitor, with a 1440p monitor at 150% to its left that
+    /// sits 200 pixels higher.
+    fn monitors() -> [MonitorDescriptor; 2] {
+        [
+            MonitorDescriptor {
+                bounds: Rect::new(0, 0, 1920, 1080),
+                work_area: Rect::new(0, 0, 1920, 1040),
+                dpi: 96,
+                is_primary: true,
+            },
+            MonitorDescriptor {
+                bounds: Rect::new(-2560, -200, 2560, 1440),
+                work_area: Rect::new(-2560, -200, 2560, 1380),
+                dpi: 144,
+                is_primary: false,
+            },
+        ]
+    }
+
+    #[test]
+    fn coordinates_move_between_desktop_and_monitor() {
+        let [primary, left] = monitors();
+        assert_eq!(primary.scale_factor(), 1.0);
+        assert_eq!(left.scale_factor(), 1.5);
+
+        let on_desktop = Rect::new(-2460, -100, 200, 100);
+        let local = left.to_local(&on_desktop);
+        assert_eq!(local, Rect::new(100, 100, 200, 100));
+        assert_eq!(left.to_desktop(&local), on_desktop);
+        // The primary monitor's coordinates are the desktop's.
+        assert_eq!(primary.to_local(&on_desktop), on_desktop);
+    }
+
+    #[test]
+    fn crops_keep_the_part_each_monitor_shows() {
+        let [primary, left] = monitors();
+        // A window that reaches 100 pixels onto the left monitor.
+        let window = Rect::new(-100, 50, 300, 200);
+        assert_eq!(primary.crop_for(&window), Some(Rect::new(0, 50, 200, 200)));
+        assert_eq!(left.crop_for(&window), Some(Rect::new(2460, 250, 100, 200)));
+        assert_eq!(primary.crop_for(&Rect::new(0, -300, 100, 100)), None);
+    }
+
+    #[test]
+    fn desktop_spans_every_monitor() {
+        assert_eq!(
+            virtual_desktop_bounds(&monitors()),
+            Rect::new(-2560, -200, 4480, 1440)
+        );
+        assert_eq!(
+            virtual_desktop_bounds(&monitors()[..1]),
+            monitors()[0].bounds
+        );
+        assert_eq!(virtual_desktop_bounds(&[]), Rect::default());
+    }
+
+    #[test]
+    fn monitors_are_found_by_rect_and_point() {
+        let monitors = monitors();
+        let straddling = Rect::new(-100, 50, 300, 200);
+        assert_eq!(monitor_for_rect(&monitors, &straddling), Some(&monitors[0]));
+        let mostly_left = Rect::new(-250, 50, 300, 200);
+        assert_eq!(
+            monitor_for_rect(&monitors, &mostly_left),
+            Some(&monitors[1])
+        );
+        // Above the primary monitor, where the left one doesn't reach.
+        let off_screen = Rect::new(100, -500, 50, 50);
+        assert_eq!(monitor_for_rect(&monitors, &off_screen), None);
+
+        assert_eq!(monitor_at(&monitors, 0, 0), Some(&monitors[0]));
+        assert_eq!(monitor_at(&monitors, -1, 0), Some(&monitors[1]));
+        assert_eq!(monitor_at(&monitors, -2560, -200), Some(&monitors[1]));
+        assert_eq!(monitor_at(&monitors, 1920, 0), None);
+        assert_eq!(monitor_at(&monitors, 10, -1), None);
+    }
+
+    #[test]
+    fn off_monitor_window_is_an_assertion() {
+        let monitors = monitors();
+        let window = Rect::new(-700, 0, 400, 300);
+        assert_eq!(monitors[0].crop_for(&window), None);
+        let TestError::Assertion(error) =
+            window_off_monitor_error(&monitors[0], &monitors, &window)
+        else {
+            panic!("expected an assertion");
+        };
+        assert_eq!(error.failure.kind, AssertionKind::WindowPlacement);
+        let details: Vec<_> = error
+            .failure
+            .details
+            .iter()
+            .map(|detail| (detail.label, detail.value.to_string()))
+            .collect();
+        assert_eq!(
+            details,
+            [
+                ("Window bounds", "(-700, 0) 400 x 300".to_owned()),
+                ("Captured monitor", "(0, 0) 1920 x 1080".to_owned()),
+                (
+                    "Monitor showing the window",
+                    "(-2560, -200) 2560 x 1440".to_owned()
+                ),
+                ("Virtual desktop", "(-2560, -200) 4480 x 1440".to_owned()),
+            ]
+        );
+    }
+}
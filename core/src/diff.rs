@@ -0,0 +1,191 @@
+use super::{color::Color, cpu_image::CpuImage};
+
+const PANEL_GAP: u32 = 4;
+const SCALE_BAR_HEIGHT: u32 = 12;
+const BACKGROUND: Color = Color {
+    A: 255,
+    R: 64,
+    G: 64,
+    B: 64,
+};
+
+/// Visualizations of the difference between an expected and an actual image.
+pub struct ImageDiff {
+    /// Per-pixel max channel difference, scaled so that the largest difference is white.
+    pub heatmap: CpuImage,
+    /// White where a pixel differs by more than the tolerance, black elsewhere.
+    pub mask: CpuImage,
+    /// Expected, actual and heatmap side by side, with the heatmap's scale bar underneath.
+    pub composite: CpuImage,
+}
+
+/// Builds the diff images for `expected` and `actual`. If the sizes differ, the
+/// images are compared over the union of both sizes and pixels that only exist
+/// in one of them count as completely different.
+pub fn generate_diff(expected: &CpuImage, actual: &CpuImage, tolerance: u8) -> ImageDiff {
+    let width = expected.width().max(actual.width());
+    let height = expected.height().max(actual.height());
+
+    let mut differences = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let difference = match (expected.read_pixel(x, y), actual.read_pixel(x, y)) {
+                (Some(expected), Some(actual)) => [
+                    expected.B.abs_diff(actual.B),
+                    expected.G.abs_diff(actual.G),
+                    expected.R.abs_diff(actual.R),
+                    expected.A.abs_diff(actual.A),
+                ]
+                .into_iter()
+                .max()
+                .unwrap(),
+                _ => u8::MAX,
+            };
+            differences.push(difference);
+        }
+    }
+    let max_difference = differences.iter().copied().max().unwrap_or(0);
+    let scale = max_difference.max(1) as f32;
+
+    let mut heatmap = CpuImage::new(width, height);
+    let mut mask = CpuImage::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let difference = differences[(y * width + x) as usize];
+            heatmap.write_pixel(x, y, heat_color(difference as f32 / scale));
+            let mask_color = if difference > tolerance {
+                Color {
+                    A: 255,
+                    R: 255,
+                    G: 255,
+                    B: 255,
+                }
+            } else {
+                Color {
+                    A: 255,
+                    R: 0,
+                    G: 0,
+                    B: 0,
+                }
+            };
+            mask.write_pixel(x, y, mask_color);
+        }
+    }
+
+    let composite = compose(
+        &[expected, actual, &heatmap],
+        width,
+        height,
+        tolerance as f32 / scale,
+    );
+
+    ImageDiff {
+        heatmap,
+        mask,
+        composite,
+    }
+}
+
+/// Maps `value` in [0, 1] onto a black -> red -> yellow -> white ramp.
+fn heat_color(value: f32) -> Color {
+    let value = value.clamp(0.0, 1.0) * 3.0;
+    let channel = |start: f32| ((value - start).clamp(0.0, 1.0) * 255.0).round() as u8;
+    Color {
+        A: 255,
+        R: channel(0.0),
+        G: channel(1.0),
+        B: channel(2.0),
+    }
+}
+
+fn compose(panels: &[&CpuImage], width: u32, height: u32, tolerance_mark: f32) -> CpuImage {
+    let count = panels.len() as u32;
+    let composite_width = count * width + (count + 1) * PANEL_GAP;
+    let composite_height = height + SCALE_BAR_HEIGHT + 3 * PANEL_GAP;
+    let mut composite = CpuImage::new(composite_width, composite_height);
+    for y in 0..composite_height {
+        for x in 0..composite_width {
+            composite.write_pixel(x, y, BACKGROUND);
+        }
+    }
+
+    for (i, panel) in panels.iter().enumerate() {
+        let left = PANEL_GAP + i as u32 * (width + PANEL_GAP);
+        for y in 0..panel.height() {
+            for x in 0..panel.width() {
+                let color = panel.read_pixel(x, y).unwrap();
+                composite.write_pixel(left + x, PANEL_GAP + y, color);
+            }
+        }
+    }
+
+    // The scale bar runs from no difference on the left to the max difference on
+    // the right. A white tick marks where the tolerance falls on that scale.
+    let bar_left = PANEL_GAP;
+    let bar_width = composite_width - 2 * PANEL_GAP;
+    let bar_top = height + 2 * PANEL_GAP;
+    let tick = bar_left + (tolerance_mark.clamp(0.0, 1.0) * (bar_width - 1) as f32).round() as u32;
+    for x in bar_left..bar_left + bar_width {
+        let value = (x - bar_left) as f32 / (bar_width - 1).max(1) as f32;
+        let color = heat_color(value);
+        for y in bar_top..bar_top + SCALE_BAR_HEIGHT {
+            composite.write_pixel(x, y, color);
+        }
+    }
+    for y in bar_top - PANEL_GAP / 2..bar_top {
+        composite.write_pixel(tick, y, heat_color(1.0));
+    }
+
+    composite
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::common_colors;
+
+    const WHITE: Color = Color {
+        A: 255,
+        R: 255,
+        G: 255,
+        B: 255,
+    };
+
+    #[test]
+    fn mask_marks_pixels_past_the_tolerance() {
+        let expected = CpuImage::from_fn(4, 2, |_, _| common_colors::GREEN);
+        let mut actual = expected.clone();
+        // Within the tolerance.
+        actual.write_pixel(
+            0,
+            0,
+            Color {
+                G: 250,
+                ..common_colors::GREEN
+            },
+        );
+        // Past it.
+        actual.write_pixel(3, 1, common_colors::RED);
+
+        let diff = generate_diff(&expected, &actual, 5);
+        let marked: Vec<_> = (0..2)
+            .flat_map(|y| (0..4).map(move |x| (x, y)))
+            .filter(|(x, y)| diff.mask.read_pixel(*x, *y) == Some(WHITE))
+            .collect();
+        assert_eq!(marked, vec![(3, 1)]);
+        // The largest difference is white in the heatmap, none is black.
+        assert_eq!(diff.heatmap.read_pixel(3, 1), Some(WHITE));
+        assert_eq!(diff.heatmap.read_pixel(1, 0).unwrap().R, 0);
+        assert_eq!(diff.composite.width(), 3 * 4 + 4 * PANEL_GAP);
+    }
+
+    #[test]
+    fn size_mismatch_counts_as_different() {
+        let expected = CpuImage::from_fn(2, 2, |_, _| common_colors::GREEN);
+        let actual = CpuImage::from_fn(3, 2, |_, _| common_colors::GREEN);
+        let diff = generate_diff(&expected, &actual, 0);
+        assert_eq!(diff.mask.width(), 3);
+        assert_eq!(diff.mask.read_pixel(2, 0), Some(WHITE));
+        assert_ne!(diff.mask.read_pixel(1, 0), Some(WHITE));
+    }
+}
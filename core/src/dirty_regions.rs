@@ -0,0 +1,506 @@
+//! Checking the dirty regions a capture reports against what actually changed
+//! between frames. Reporting too much only costs the consumer some work, but
+//! every changed pixel has to be inside a reported region, or the consumer
+//! would miss the update.
+
+use super::{
+    assertion::{Artifact, AssertionFailure, AssertionKind, Capture, Detail, Value},
+    capture::CaptureFrame,
+    color::{common_colors, Color},
+    color_math::colors_match,
+    cpu_image::CpuImage,
+    error::{AssertionError, TestError, TestResult},
+    geometry::Rect,
+    test_runner::TestContext,
+};
+
+/// The number of pixels covered by at least one of `rects`.
+pub fn union_area(rects: &[Rect]) -> u64 {
+    let rects: Vec<&Rect> = rects.iter().filter(|rect| !rect.is_empty()).collect();
+    let mut edges: Vec<i32> = rects
+        .iter()
+        .flat_map(|rect| [rect.x, rect.right()])
+        .collect();
+    edges.sort_unstable();
+    edges.dedup();
+
+    // Sweep over the columns between neighboring edges. Within one column every
+    // rectangle either spans it completely or not at all.
+    let mut area = 0;
+    for column in edges.windows(2) {
+        let (left, right) = (column[0], column[1]);
+        let mut spans: Vec<(i32, i32)> = rects
+            .iter()
+            .filter(|rect| rect.x <= left && rect.right() >= right)
+            .map(|rect| (rect.y, rect.bottom()))
+            .collect();
+        spans.sort_unstable();
+        let mut height = 0u64;
+        let mut covered_to = i32::MIN;
+        for (top, bottom) in spans {
+            let top = top.max(covered_to);
+            if bottom > top {
+                height += (bottom - top) as u64;
+                covered_to = bottom;
+            }
+        }
+        area += height * (right - left) as u64;
+    }
+    area
+}
+
+/// The number of pixels of `target` that none of `rects` cover.
+pub fn uncovered_area(target: &Rect, rects: &[Rect]) -> u64 {
+    let clipped: Vec<Rect> = rects
+        .iter()
+        .filter_map(|rect| rect.intersection(target))
+        .collect();
+    target.area() - union_area(&clipped)
+}
+
+/// Whether every pixel of `target` is covered by at least one of `rects`.
+pub fn covers(rects: &[Rect], target: &Rect) -> bool {
+    uncovered_area(target, rects) == 0
+}
+
+/// Which pixels differ by more than `tolerance` on any channel, row by row.
+/// Both images need to have the same size.
+pub fn changed_pixels(previous: &CpuImage, current: &CpuImage, tolerance: u8) -> Vec<bool> {
+    assert_eq!(
+        (previous.width(), previous.height()),
+        (current.width(), current.height()),
+        "only images of the same size can be compared"
+    );
+    (0..current.height())
+        .flat_map(|y| (0..current.width()).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            !colors_match(
+                previous.read_pixel(x, y).unwrap(),
+                current.read_pixel(x, y).unwrap(),
+                tolerance,
+            )
+        })
+        .collect()
+}
+
+/// The bounds of each 8-connected group of changed pixels in a `width` pixels
+/// wide grid, in the order their top left pixel comes up row by row.
+pub fn changed_rects(changed: &[bool], width: u32) -> Vec<Rect> {
+    let width = width as usize;
+    if width == 0 {
+        return Vec::new();
+    }
+    let height = changed.len() / width;
+    let mut visited = vec![false; changed.len()];
+    let mut rects = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..changed.len() {
+        if !changed[start] || visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        stack.push(start);
+        let (mut left, mut top) = (start % width, start / width);
+        let (mut right, mut bottom) = (left, top);
+        while let Some(index) = stack.pop() {
+            let (x, y) = (index % width, index / width);
+            left = left.min(x);
+            right = right.max(x);
+            top = top.min(y);
+            bottom = bottom.max(y);
+            for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+                    let neighbor = ny * width + nx;
+                    if changed[neighbor] && !visited[neighbor] {
+                        visited[neighbor] = true;
+                        stack.push(neighbor);
+                    }
+                }
+            }
+        }
+        rects.push(Rect::new(
+            left as i32,
+            top as i32,
+            (right - left + 1) as i32,
+            (bottom - top + 1) as i32,
+        ));
+    }
+    rects
+}
+
+/// How well the dirty regions reported for one frame matched the actual change.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirtyRegionComparison {
+    /// The bounds of each group of changed pixels.
+    pub changed: Vec<Rect>,
+    pub changed_pixels: u64,
+    /// Changed pixels outside of every reported region.
+    pub missed_pixels: u64,
+    /// Pixels covered by the reported regions, within the frame.
+    pub reported_pixels: u64,
+    /// Reported pixels that didn't change.
+    pub over_reported_pixels: u64,
+}
+
+/// Compares the regions reported for `current` with what changed since
+/// `previous`. Both images need to have the same size.
+pub fn compare_dirty_regions(
+    previous: &CpuImage,
+    current: &CpuImage,
+    reported: &[Rect],
+    tolerance: u8,
+) -> DirtyRegionComparison {
+    let changed = changed_pixels(previous, current, tolerance);
+    let width = current.width() as i32;
+    let frame = Rect::new(0, 0, width, current.height() as i32);
+    let reported: Vec<Rect> = reported
+        .iter()
+        .filter_map(|rect| rect.intersection(&frame))
+        .collect();
+
+    let mut changed_count = 0;
+    let mut missed_pixels = 0;
+    for (index, _) in changed.iter().enumerate().filter(|(_, changed)| **changed) {
+        changed_count += 1;
+        let (x, y) = (index as i32 % width, index as i32 / width);
+        if !reported.iter().any(|rect| rect.contains(x, y)) {
+            missed_pixels += 1;
+        }
+    }
+    let reported_pixels = union_area(&reported);
+    DirtyRegionComparison {
+        changed: changed_rects(&changed, current.width()),
+        changed_pixels: changed_count,
+        missed_pixels,
+        reported_pixels,
+        over_reported_pixels: reported_pixels - (changed_count - missed_pixels),
+    }
+}
+
+/// Checks the dirty regions of consecutive frames, e.g. as they come out of
+/// `CaptureSession::next_frame`, and keeps totals for metrics.
+#[derive(Clone, Debug)]
+pub struct DirtyRegionVerifier {
+    tolerance: u8,
+    previous: Option<CpuImage>,
+    frames_checked: u32,
+    changed_pixels: u64,
+    missed_pixels: u64,
+    reported_pixels: u64,
+    over_reported_pixels: u64,
+}
+
+impl DirtyRegionVerifier {
+    /// Pixels that differ by up to `tolerance` on every channel don't count as
+    /// changed.
+    pub fn new(tolerance: u8) -> Self {
+        Self {
+            tolerance,
+            previous: None,
+            frames_checked: 0,
+            changed_pixels: 0,
+            missed_pixels: 0,
+            reported_pixels: 0,
+            over_reported_pixels: 0,
+        }
+    }
+
+    /// Checks `frame` against the frame before it. The first frame, and the
+    /// first frame after a size change, only become the baseline for the next
+    /// one. Fails if a changed pixel wasn't reported, and skips the test if the
+    /// capture doesn't report dirty regions at all.
+    pub fn check(&mut self, frame: &CaptureFrame) -> TestResult<()> {
+        let reported = frame.dirty_regions.as_ref().ok_or_else(|| {
+            TestError::Skipped("the capture doesn't report dirty regions".to_owned())
+        })?;
+        let previous = match self.previous.replace(frame.image.clone()) {
+            Some(previous)
+                if previous.width() == frame.image.width()
+                    && previous.height() == frame.image.height() =>
+            {
+                previous
+            }
+            _ => return Ok(()),
+        };
+
+        let comparison = compare_dirty_regions(&previous, &frame.image, reported, self.tolerance);
+        self.frames_checked += 1;
+        self.changed_pixels += comparison.changed_pixels;
+        self.missed_pixels += comparison.missed_pixels;
+        self.reported_pixels += comparison.reported_pixels;
+        self.over_reported_pixels += comparison.over_reported_pixels;
+        if comparison.missed_pixels == 0 {
+            return Ok(());
+        }
+
+        Err(TestError::Assertion(Box::new(AssertionError {
+            failure: AssertionFailure {
+                subject: Some("every changed pixel is in a dirty region".to_owned()),
+                details: vec![
+                    Detail {
+                        label: "Changed pixels",
+                        value: Value::Count(comparison.changed_pixels),
+                    },
+                    Detail {
+                        label: "Missed pixels",
+                        value: Value::Count(comparison.missed_pixels),
+                    },
+                    Detail {
+                        label: "Reported regions",
+                        value: Value::Count(reported.len() as u64),
+                    },
+                ],
+                ..AssertionFailure::new(AssertionKind::DirtyRegions)
+            },
+            capture: Some(Capture::Image(frame.image.clone())),
+            artifacts: vec![
+                Artifact {
+                    name: "previous".to_owned(),
+                    image: previous.clone(),
+                },
+                Artifact {
+                    name: "missed".to_owned(),
+                    image: missed_image(&previous, &frame.image, reported, self.tolerance),
+                },
+            ],
+        })))
+    }
+
+    /// How many frames were compared with the frame before them.
+    pub fn frames_checked(&self) -> u32 {
+        self.frames_checked
+    }
+
+    /// The share of reported pixels that didn't change, from 0 to 1. Zero
+    /// until something was reported.
+    pub fn over_reporting(&self) -> f64 {
+        if self.reported_pixels == 0 {
+            0.0
+        } else {
+            self.over_reported_pixels as f64 / self.reported_pixels as f64
+        }
+    }
+
+    /// All changed pixels across the checked frames.
+    pub fn changed_pixels(&self) -> u64 {
+        self.changed_pixels
+    }
+
+    /// Changed pixels outside of every reported region, across the checked
+    /// frames. Zero unless an update was missed.
+    pub fn missed_pixels(&self) -> u64 {
+        self.missed_pixels
+    }
+
+    /// Records the missed pixels and the over-reporting as metrics, once at
+    /// least one frame was checked.
+    pub fn record_metrics(&self, context: &TestContext) {
+        if self.frames_checked == 0 {
+            return;
+        }
+        context.record_metric(
+            "dirty_regions.frames_checked".to_owned(),
+            self.frames_checked as f64,
+        );
+        context.record_metric(
+            "dirty_regions.missed_pixels".to_owned(),
+            self.missed_pixels as f64,
+        );
+        context.record_metric(
+            "dirty_regions.over_reporting".to_owned(),
+            self.over_reporting(),
+        );
+    }
+}
+
+/// The current frame, dimmed, with the reported regions in green and the
+/// missed pixels in red.
+fn missed_image(
+    previous: &CpuImage,
+    current: &CpuImage,
+    reported: &[Rect],
+    tolerance: u8,
+) -> CpuImage {
+    let changed = changed_pixels(previous, current, tolerance);
+    let mut image = CpuImage::new(current.width(), current.height());
+    for y in 0..current.height() {
+        for x in 0..current.width() {
+            let index = (y * current.width() + x) as usize;
+            let in_region = reported
+                .iter()
+                .any(|rect| rect.contains(x as i32, y as i32));
+            let color = match (changed[index], in_region) {
+                (true, false) => common_colors::RED,
+                (_, true) => common_colors::GREEN,
+                (false, false) => {
+                    let pixel = current.read_pixel(x, y).unwrap();
+                    Color {
+                        A: 255,
+                        R: pixel.R / 4,
+                        G: pixel.G / 4,
+                        B: pixel.B / 4,
+                    }
+                }
+            };
+            image.write_pixel(x, y, color);
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        capture::{CaptureBackend, CaptureOptions, CaptureSession},
+        fake_capture::FakeBackend,
+    };
+
+    #[test]
+    fn union_area_counts_overlaps_once() {
+        assert_eq!(union_area(&[]), 0);
+        assert_eq!(union_area(&[Rect::new(1, 1, 2, 3)]), 6);
+        // Two 4 x 4 squares that share a 2 x 2 corner.
+        assert_eq!(
+            union_area(&[Rect::new(0, 0, 4, 4), Rect::new(2, 2, 4, 4)]),
+            28
+        );
+        // One inside the other, and the same one twice.
+        assert_eq!(
+            union_area(&[
+                Rect::new(0, 0, 10, 10),
+                Rect::new(2, 2, 3, 3),
+                Rect::new(0, 0, 10, 10)
+            ]),
+            100
+        );
+        // Apart, with negative coordinates and an empty rectangle.
+        assert_eq!(
+            union_area(&[
+                Rect::new(-5, -5, 2, 2),
+                Rect::new(5, 5, 1, 2),
+                Rect::new(0, 0, 0, 7)
+            ]),
+            6
+        );
+    }
+
+    #[test]
+    fn coverage_of_a_target() {
+        let target = Rect::new(0, 0, 4, 4);
+        let halves = [Rect::new(0, 0, 2, 4), Rect::new(2, 0, 2, 4)];
+        assert!(covers(&halves, &target));
+        assert_eq!(uncovered_area(&target, &halves), 0);
+        // Reaching past the target doesn't make up for a hole.
+        let with_hole = [Rect::new(-2, -2, 8, 3), Rect::new(0, 1, 3, 3)];
+        assert!(!covers(&with_hole, &target));
+        assert_eq!(uncovered_area(&target, &with_hole), 3);
+        assert_eq!(uncovered_area(&target, &[]), 16);
+        assert!(covers(&[], &Rect::new(3, 3, 0, 0)));
+    }
+
+    #[test]
+    fn changed_rects_group_touching_pixels() {
+        #[rustfmt::skip]
+        let changed = [
+            true,  false, false, false, false,
+            false, true,  false, false, true,
+            false, false, false, false, true,
+        ];
+        assert_eq!(
+            changed_rects(&changed, 5),
+            [Rect::new(0, 0, 2, 2), Rect::new(4, 1, 1, 2)]
+        );
+        assert!(changed_rects(&[], 0).is_empty());
+    }
+
+    fn image(marks: &[(u32, u32)]) -> CpuImage {
+        CpuImage::from_fn(6, 4, |x, y| {
+            if marks.contains(&(x, y)) {
+                common_colors::RED
+            } else {
+                common_colors::BLUE
+            }
+        })
+    }
+
+    #[test]
+    fn comparison_counts_missed_and_over_reported_pixels() {
+        let previous = image(&[]);
+        let current = image(&[(1, 1), (2, 1), (5, 3)]);
+        let comparison = compare_dirty_regions(&previous, &current, &[Rect::new(0, 0, 3, 3)], 0);
+        assert_eq!(
+            comparison,
+            DirtyRegionComparison {
+                changed: vec![Rect::new(1, 1, 2, 1), Rect::new(5, 3, 1, 1)],
+                changed_pixels: 3,
+                missed_pixels: 1,
+                reported_pixels: 9,
+                over_reported_pixels: 7,
+            }
+        );
+        // Regions are clipped to the frame before they are counted.
+        let comparison =
+            compare_dirty_regions(&previous, &current, &[Rect::new(-10, -10, 100, 100)], 0);
+        assert_eq!(comparison.missed_pixels, 0);
+        assert_eq!(comparison.reported_pixels, 24);
+    }
+
+    fn frame(marks: &[(u32, u32)], regions: Option<Vec<Rect>>, timestamp: u64) -> CaptureFrame {
+        CaptureFrame {
+            image: image(marks),
+            timestamp: Duration::from_millis(timestamp),
+            content_size: (6, 4),
+            dirty_regions: regions,
+        }
+    }
+
+    #[test]
+    fn verifier_checks_consecutive_frames_of_a_session() {
+        let backend = FakeBackend::new()
+            .with_captured_frame(frame(&[], Some(vec![Rect::new(0, 0, 6, 4)]), 0))
+            .with_captured_frame(frame(&[(1, 1)], Some(vec![Rect::new(1, 1, 2, 1)]), 10))
+            .with_captured_frame(frame(&[(1, 1), (4, 2)], Some(vec![]), 20));
+        let mut session = backend.start(&(), CaptureOptions::default()).unwrap();
+        let timeout = Duration::from_millis(100);
+        let mut verifier = DirtyRegionVerifier::new(0);
+
+        // The first frame is only the baseline.
+        verifier
+            .check(&session.next_frame(timeout).unwrap())
+            .unwrap();
+        assert_eq!(verifier.frames_checked(), 0);
+
+        verifier
+            .check(&session.next_frame(timeout).unwrap())
+            .unwrap();
+        assert_eq!(verifier.frames_checked(), 1);
+        assert_eq!(verifier.changed_pixels(), 1);
+        assert_eq!(verifier.missed_pixels(), 0);
+        assert_eq!(verifier.over_reporting(), 0.5);
+
+        let TestError::Assertion(error) = verifier
+            .check(&session.next_frame(timeout).unwrap())
+            .unwrap_err()
+        else {
+            panic!("expected an assertion");
+        };
+        assert_eq!(error.failure.kind, AssertionKind::DirtyRegions);
+        let missed = &error.artifacts[1].image;
+        assert_eq!(missed.read_pixel(4, 2), Some(common_colors::RED));
+        assert_eq!(verifier.missed_pixels(), 1);
+    }
+
+    #[test]
+    fn verifier_skips_frames_without_regions() {
+        let error = DirtyRegionVerifier::new(0)
+            .check(&frame(&[], None, 0))
+            .unwrap_err();
+        assert_eq!(
+            error.skip_reason(),
+            Some("the capture doesn't report dirty regions")
+        );
+    }
+}
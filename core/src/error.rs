@@ -0,0 +1,285 @@
+use std::{error::Error, fmt, time::Duration};
+
+use super::{
+    assertion::{Artifact, AssertionFailure, AssertionKind, Capture, Detail, Value},
+    capture::InvalidCaptureOptions,
+    geometry::CropOutsideFrame,
+};
+
+pub type TestResult<T> = std::result::Result<T, TestError>;
+
+#[derive(Debug)]
+pub enum TestError {
+    /// A Windows API call failed somewhere that wasn't classified any further.
+    General(PlatformError),
+    Io(std::io::Error),
+    /// Getting the test ready failed, e.g. creating a window or a swap chain.
+    Setup {
+        step: String,
+        error: PlatformError,
+    },
+    /// Windows.Graphics.Capture itself failed, e.g. creating a frame pool.
+    Capture {
+        step: String,
+        error: PlatformError,
+    },
+    /// Something the test was waiting for didn't happen in time.
+    Timeout {
+        waiting_for: String,
+        timeout: Duration,
+    },
+    Assertion(Box<AssertionError>),
+    /// The captured item went away, e.g. because its window was closed.
+    ItemClosed,
+    /// The test asked for a capture with options that can't work together.
+    InvalidOptions(InvalidCaptureOptions),
+    /// The test can't run on this machine. This doesn't count as a failure.
+    Skipped(String),
+    /// The test panicked. Holds the panic message.
+    Panicked(String),
+    /// `source` happened while the test was doing `context`.
+    Context {
+        context: String,
+        source: Box<TestError>,
+    },
+}
+
+/// A failed platform API call, e.g. a Windows API call that returned an HRESULT.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlatformError {
+    pub code: i32,
+    pub message: String,
+}
+
+impl fmt::Display for PlatformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (HRESULT 0x{:08X})", self.message, self.code)
+    }
+}
+
+impl Error for PlatformError {}
+
+#[cfg(feature = "windows")]
+impl From<windows::core::Error> for PlatformError {
+    fn from(error: windows::core::Error) -> Self {
+        Self {
+            code: error.code().0,
+            message: error.message().to_string(),
+        }
+    }
+}
+
+/// A failed check, along with the images needed to investigate it.
+#[derive(Debug)]
+pub struct AssertionError {
+    pub failure: AssertionFailure,
+    /// The image the check looked at. It is saved as `<test>.png`.
+    pub capture: Option<Capture>,
+    pub artifacts: Vec<Artifact>,
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TestError::General(error) => write!(f, "{}", error),
+            TestError::Io(error) => write!(f, "{}", error),
+            TestError::Setup { step, error } => {
+                write!(f, "Setup failed while {}: {}", step, error)
+            }
+            TestError::Capture { step, error } => {
+                write!(f, "Capture failed while {}: {}", step, error)
+            }
+            TestError::Timeout {
+                waiting_for,
+                timeout,
+            } => write!(
+                f,
+                "Timed out after {:.1}s waiting for {}",
+                timeout.as_secs_f64(),
+                waiting_for
+            ),
+            TestError::Assertion(error) => write!(f, "{}", error),
+            TestError::ItemClosed => write!(f, "The captured item was closed"),
+            TestError::InvalidOptions(error) => write!(f, "Invalid capture options: {}", error),
+            TestError::Skipped(reason) => write!(f, "Skipped: {}", reason),
+            TestError::Panicked(message) => write!(f, "Panicked: {}", message),
+            // Print the whole chain, with each cause indented under what it caused.
+            TestError::Context { context, source } => {
+                write!(f, "{}", context)?;
+                let source = source.to_string();
+                for (i, line) in source.lines().enumerate() {
+                    if i == 0 {
+                        write!(f, "\n  caused by: {}", line)?;
+                    } else {
+                        write!(f, "\n  {}", line)?;
+                    }
+                }
+                if source.ends_with('\n') {
+                    writeln!(f)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for TestError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TestError::General(error) => Some(error),
+            TestError::Io(error) => Some(error),
+            TestError::Setup { error, .. } => Some(error),
+            TestError::Capture { error, .. } => Some(error),
+            TestError::Assertion(error) => Some(error),
+            TestError::InvalidOptions(error) => Some(error),
+            TestError::Context { source, .. } => Some(source.as_ref()),
+            TestError::Timeout { .. }
+            | TestError::ItemClosed
+            | TestError::Skipped(_)
+            | TestError::Panicked(_) => None,
+        }
+    }
+}
+
+#[cfg(feature = "windows")]
+impl From<windows::core::Error> for TestError {
+    fn from(error: windows::core::Error) -> Self {
+        TestError::General(error.into())
+    }
+}
+
+impl From<InvalidCaptureOptions> for TestError {
+    fn from(error: InvalidCaptureOptions) -> Self {
+        TestError::InvalidOptions(error)
+    }
+}
+
+/// The part of the screen a test looked up isn't all in the captured frame, e.g.
+/// because the window moved or resized in between.
+impl From<CropOutsideFrame> for TestError {
+    fn from(error: CropOutsideFrame) -> Self {
+        TestError::Assertion(Box::new(AssertionError {
+            failure: AssertionFailure {
+                subject: Some("the crop fits in the frame".to_owned()),
+                details: vec![
+                    Detail {
+                        label: "Crop",
+                        value: Value::Text(error.crop.to_string()),
+                    },
+                    Detail {
+                        label: "Frame size",
+                        value: Value::Size {
+                            width: error.frame_size.0,
+                            height: error.frame_size.1,
+                        },
+                    },
+                ],
+                ..AssertionFailure::new(AssertionKind::WindowPlacement)
+            },
+            capture: None,
+            artifacts: Vec::new(),
+        }))
+    }
+}
+
+impl From<std::io::Error> for TestError {
+    fn from(error: std::io::Error) -> Self {
+        TestError::Io(error)
+    }
+}
+
+impl TestError {
+    pub fn ok(self) -> TestResult<()> {
+        TestResult::Err(self)
+    }
+
+    /// The error underneath all of the context that was added to it.
+    pub fn root(&self) -> &TestError {
+        match self {
+            TestError::Context { source, .. } => source.root(),
+            error => error,
+        }
+    }
+
+    /// Why the test was skipped, if the root error is a skip. Context added on
+    /// the way up doesn't turn a skip into a failure.
+    pub fn skip_reason(&self) -> Option<&str> {
+        match self.root() {
+            TestError::Skipped(reason) => Some(reason),
+            _ => None,
+        }
+    }
+
+    /// A stable identifier for the kind of the root error, for reports.
+    pub fn kind(&self) -> &'static str {
+        match self.root() {
+            TestError::General(_) => "windows",
+            TestError::Io(_) => "io",
+            TestError::Setup { .. } => "setup",
+            TestError::Capture { .. } => "capture",
+            TestError::Timeout { .. } => "timeout",
+            TestError::Assertion(_) => "assertion",
+            TestError::ItemClosed => "item_closed",
+            TestError::InvalidOptions(_) => "invalid_options",
+            TestError::Skipped(_) => "skipped",
+            TestError::Panicked(_) => "panic",
+            TestError::Context { .. } => unreachable!("the root error never has context"),
+        }
+    }
+}
+
+/// Adds a description of what the test was doing to a failed result, e.g.
+/// `capture.get_next_frame().context("waiting for the fullscreen frame")?`.
+pub trait Context<T> {
+    fn context<C: Into<String>>(self, context: C) -> TestResult<T>;
+}
+
+impl<T> Context<T> for TestResult<T> {
+    fn context<C: Into<String>>(self, context: C) -> TestResult<T> {
+        self.map_err(|error| TestError::Context {
+            context: context.into(),
+            source: Box::new(error),
+        })
+    }
+}
+
+#[cfg(feature = "windows")]
+impl<T> Context<T> for windows::core::Result<T> {
+    fn context<C: Into<String>>(self, context: C) -> TestResult<T> {
+        self.map_err(TestError::from).context(context)
+    }
+}
+
+/// Classifies a failed Windows API call by the part of the test it happened in.
+#[cfg(feature = "windows")]
+pub trait WindowsResultExt<T> {
+    /// Turns an error into a [`TestError::Setup`] for `step`, e.g. "creating the swap chain".
+    fn during_setup<S: Into<String>>(self, step: S) -> TestResult<T>;
+    /// Turns an error into a [`TestError::Capture`] for `step`, e.g. "starting the capture".
+    fn during_capture<S: Into<String>>(self, step: S) -> TestResult<T>;
+}
+
+#[cfg(feature = "windows")]
+impl<T> WindowsResultExt<T> for windows::core::Result<T> {
+    fn during_setup<S: Into<String>>(self, step: S) -> TestResult<T> {
+        self.map_err(|error| TestError::Setup {
+            step: step.into(),
+            error: error.into(),
+        })
+    }
+
+    fn during_capture<S: Into<String>>(self, step: S) -> TestResult<T> {
+        self.map_err(|error| TestError::Capture {
+            step: step.into(),
+            error: error.into(),
+        })
+    }
+}
+
+impl fmt::Display for AssertionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.failure)
+    }
+}
+
+impl Error for AssertionError {}
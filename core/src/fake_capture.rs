@@ -0,0 +1,275 @@
+use std::{collections::VecDeque, io, path::Path, time::Duration};
+
+use super::{
+    assertion::Capture,
+    capture::{
+        CaptureBackend, CaptureFrame, CaptureOptions, CaptureSession, Lifecycle, SessionState,
+    },
+    cpu_image::CpuImage,
+    error::TestResult,
+    recording::read_recording,
+    resize::{stale_frame_error, FrameSize, ResizeTracker, SizeChange},
+    timing::TimingRecorder,
+};
+
+/// A capture backend that plays back a script of frames instead of capturing
+/// anything. Sessions run on a virtual clock that only moves when frames are
+/// requested, so waiting for a frame never sleeps and every run behaves the same.
+#[derive(Clone, Debug, Default)]
+pub struct FakeBackend {
+    frames: Vec<CaptureFrame>,
+    start_time: Duration,
+    item_closed_at: Option<Duration>,
+}
+
+impl FakeBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Plays back a recording made with a `FrameRecorder`. The virtual clock
+    /// starts at the first frame, so the recorded gaps between frames are kept.
+    pub fn from_recording<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let frames = read_recording(path)?;
+        let start_time = frames
+            .first()
+            .map_or(Duration::ZERO, |frame| frame.timestamp);
+        Ok(Self {
+            frames,
+            start_time,
+            item_closed_at: None,
+        })
+    }
+
+    /// Adds a frame that arrives at `timestamp`, with the image's size as the
+    /// content size. Frames have to be added in the order they arrive.
+    pub fn with_frame(self, image: CpuImage, timestamp: Duration) -> Self {
+        let content_size = (image.width(), image.height());
+        self.with_captured_frame(CaptureFrame {
+            image,
+            timestamp,
+            content_size,
+            dirty_regions: None,
+        })
+    }
+
+    /// Adds a frame exactly as given, e.g. one whose content size differs from
+    /// the image size to simulate a resize.
+    pub fn with_captured_frame(mut self, frame: CaptureFrame) -> Self {
+        if let Some(last) = self.frames.last() {
+            assert!(
+                last.timestamp <= frame.timestamp,
+                "frames have to be added in order"
+            );
+        }
+        self.frames.push(frame);
+        self
+    }
+
+    /// Makes the captured item go away at `timestamp`, e.g. like a window that
+    /// gets closed. Frames from then on are never delivered.
+    pub fn with_item_closed_at(mut self, timestamp: Duration) -> Self {
+        self.item_closed_at = Some(timestamp);
+        self
+    }
+}
+
+impl CaptureBackend for FakeBackend {
+    /// There is nothing to pick from, every session plays back the same script.
+    type Item = ();
+    type Session = FakeSession;
+
+    /// Rejects the same options as a live session that reads frames back.
+    fn start(&self, _item: &(), options: CaptureOptions) -> TestResult<FakeSession> {
+        options.validate_for_read_back()?;
+        let content_size = self
+            .frames
+            .first()
+            .map_or((0, 0), |frame| frame.content_size);
+        Ok(FakeSession {
+            frames: self.frames.iter().cloned().collect(),
+            content_size,
+            resize: ResizeTracker::new(content_size),
+            now: self.start_time,
+            item_closed_at: self.item_closed_at,
+            lifecycle: Lifecycle::default(),
+            timing: TimingRecorder::default(),
+            options,
+        })
+    }
+}
+
+pub struct FakeSession {
+    frames: VecDeque<CaptureFrame>,
+    content_size: (u32, u32),
+    resize: ResizeTracker,
+    now: Duration,
+    item_closed_at: Option<Duration>,
+    lifecycle: Lifecycle,
+    timing: TimingRecorder,
+    options: CaptureOptions,
+}
+
+impl CaptureSession for FakeSession {
+    /// Returns the next frame if it arrives within `timeout` of the virtual clock
+    /// and moves the clock to its timestamp. Otherwise the clock moves ahead by
+    /// `timeout` and the call times out, like a real session would. The frame
+    /// pool is resized along with the content, like `GraphicsCapture` does, so
+    /// frames whose image has an earlier content size count as stale. If the
+    /// item closes before the next frame arrives, the clock only moves up to
+    /// that point and the call fails with `TestError::ItemClosed`.
+    fn next_frame(&mut self, timeout: Duration) -> TestResult<CaptureFrame> {
+        let deadline = self.now + timeout;
+        let arrives_in_time = self.lifecycle.state() == SessionState::Capturing
+            && self.frames.front().is_some_and(|frame| {
+                frame.timestamp <= deadline
+                    && self
+                        .item_closed_at
+                        .is_none_or(|closed_at| frame.timestamp < closed_at)
+            });
+        if arrives_in_time {
+            let frame = self.frames.pop_front().unwrap();
+            self.now = self.now.max(frame.timestamp);
+            self.timing.record(frame.timestamp, self.now);
+            self.content_size = frame.content_size;
+            let frame_size = (frame.image.width(), frame.image.height());
+            if let FrameSize::Stale {
+                frame_size,
+                pool_size,
+            } = self.resize.on_frame(frame_size, frame.content_size)
+            {
+                if self.options.fail_on_stale_frames {
+                    return Err(stale_frame_error(
+                        frame_size,
+                        pool_size,
+                        Some(Capture::Image(frame.image)),
+                    ));
+                }
+            }
+            Ok(frame)
+        } else {
+            let item_closes = self
+                .item_closed_at
+                .filter(|closed_at| *closed_at <= deadline);
+            if let (Some(closed_at), SessionState::Capturing) =
+                (item_closes, self.lifecycle.state())
+            {
+                self.now = self.now.max(closed_at);
+                self.lifecycle.item_closed();
+            } else if self.lifecycle.state() != SessionState::ItemClosed {
+                self.now = deadline;
+            }
+            Err(self.lifecycle.no_frame(timeout))
+        }
+    }
+
+    fn content_size(&self) -> TestResult<(u32, u32)> {
+        Ok(self.content_size)
+    }
+
+    fn close(&mut self) -> TestResult<()> {
+        self.lifecycle.close();
+        Ok(())
+    }
+
+    fn state(&self) -> SessionState {
+        self.lifecycle.state()
+    }
+
+    fn size_changes(&self) -> &[SizeChange] {
+        self.resize.size_changes()
+    }
+
+    fn options(&self) -> CaptureOptions {
+        self.options
+    }
+
+    fn timing(&self) -> &TimingRecorder {
+        &self.timing
+    }
+
+    /// The virtual clock, which starts at zero, or at the first frame for
+    /// recordings.
+    fn now(&self) -> Duration {
+        self.now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color::{common_colors, Color},
+        error::TestError,
+    };
+
+    fn frame(color: Color) -> CpuImage {
+        CpuImage::from_fn(4, 4, |_, _| color)
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn start(backend: &FakeBackend) -> FakeSession {
+        backend.start(&(), CaptureOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn frames_move_the_clock_to_their_timestamp() {
+        let backend = FakeBackend::new()
+            .with_frame(frame(common_colors::RED), ms(10))
+            .with_frame(frame(common_colors::GREEN), ms(30));
+        let mut session = start(&backend);
+        assert_eq!(session.now(), Duration::ZERO);
+        assert_eq!(session.content_size().unwrap(), (4, 4));
+
+        let first = session.next_frame(ms(100)).unwrap();
+        assert_eq!(first.timestamp, ms(10));
+        assert_eq!(first.image.read_pixel(0, 0), Some(common_colors::RED));
+        assert_eq!(session.now(), ms(10));
+
+        let second = session.next_frame(ms(100)).unwrap();
+        assert_eq!(second.timestamp, ms(30));
+        assert_eq!(session.now(), ms(30));
+        assert_eq!(session.timing().analyze(ms(100)).frame_count, 2);
+    }
+
+    #[test]
+    fn late_frame_times_out_and_moves_the_clock_by_the_timeout() {
+        let backend = FakeBackend::new().with_frame(frame(common_colors::RED), ms(500));
+        let mut session = start(&backend);
+        let error = session.next_frame(ms(100)).unwrap_err();
+        assert!(matches!(error, TestError::Timeout { timeout, .. } if timeout == ms(100)));
+        assert_eq!(session.now(), ms(100));
+
+        // The frame is still there for a caller that waits long enough.
+        assert_eq!(session.next_frame(ms(400)).unwrap().timestamp, ms(500));
+        assert_eq!(session.state(), SessionState::Capturing);
+    }
+
+    #[test]
+    fn closed_session_delivers_no_more_frames() {
+        let backend = FakeBackend::new()
+            .with_frame(frame(common_colors::RED), ms(0))
+            .with_frame(frame(common_colors::GREEN), ms(10));
+        let mut session = start(&backend);
+        session.next_frame(ms(100)).unwrap();
+        session.close().unwrap();
+        assert_eq!(session.state(), SessionState::Closed);
+        assert!(matches!(
+            session.next_frame(ms(100)),
+            Err(TestError::Timeout { .. })
+        ));
+    }
+
+    #[test]
+    fn sessions_replay_the_script_from_the_start() {
+        let backend = FakeBackend::new().with_frame(frame(common_colors::RED), ms(0));
+        let mut first = start(&backend);
+        first.next_frame(ms(10)).unwrap();
+        let mut second = start(&backend);
+        assert_eq!(second.next_frame(ms(10)).unwrap().timestamp, ms(0));
+        assert_eq!(second.options(), CaptureOptions::default());
+    }
+}
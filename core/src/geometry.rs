@@ -0,0 +1,275 @@
+//! Rectangle math for windows, client areas and captured frames. Nothing here
+//! talks to Windows, the callers pass in what they looked up.
+
+use std::{error::Error, fmt};
+
+/// The DPI at which one pixel is one logical unit.
+pub const DEFAULT_DPI: u32 = 96;
+
+/// An axis-aligned rectangle in pixels. The right and bottom edges are exclusive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl fmt::Display for Rect {
+    /// E.g. "(-1920, 0) 1920 x 1080".
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "({}, {}) {} x {}",
+            self.x, self.y, self.width, self.height
+        )
+    }
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// The rectangle from `left`, `top` up to, but not including, `right`,
+    /// `bottom`, like a Win32 `RECT`.
+    pub fn from_edges(left: i32, top: i32, right: i32, bottom: i32) -> Self {
+        Self::new(left, top, right - left, bottom - top)
+    }
+
+    pub fn right(&self) -> i32 {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.y + self.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    /// The number of pixels in the rectangle, zero if it's empty.
+    pub fn area(&self) -> u64 {
+        if self.is_empty() {
+            0
+        } else {
+            self.width as u64 * self.height as u64
+        }
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    /// The part both rectangles cover, or `None` if they don't overlap.
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let rect = Rect::new(
+            x,
+            y,
+            self.right().min(other.right()) - x,
+            self.bottom().min(other.bottom()) - y,
+        );
+        (!rect.is_empty()).then_some(rect)
+    }
+
+    /// The rectangle moved by `dx`, `dy`.
+    pub fn offset(&self, dx: i32, dy: i32) -> Rect {
+        Rect::new(self.x + dx, self.y + dy, self.width, self.height)
+    }
+
+    /// The rectangle relative to `origin`, e.g. a window's rectangle relative
+    /// to the monitor it is on.
+    pub fn relative_to(&self, origin: (i32, i32)) -> Rect {
+        self.offset(-origin.0, -origin.1)
+    }
+
+    /// Checks that the rectangle is a non-empty part of a frame of
+    /// `frame_size`, e.g. before copying that part of the frame. Crops are
+    /// never clamped, a crop that doesn't fit means the test looked up the
+    /// wrong part of the screen.
+    pub fn crop_of(&self, frame_size: (u32, u32)) -> Result<Rect, CropOutsideFrame> {
+        let frame = Rect::new(0, 0, frame_size.0 as i32, frame_size.1 as i32);
+        if !self.is_empty() && self.intersection(&frame) == Some(*self) {
+            Ok(*self)
+        } else {
+            Err(CropOutsideFrame {
+                crop: *self,
+                frame_size,
+            })
+        }
+    }
+}
+
+/// A crop that doesn't fit in the frame it was meant for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CropOutsideFrame {
+    pub crop: Rect,
+    pub frame_size: (u32, u32),
+}
+
+impl fmt::Display for CropOutsideFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the crop {} doesn't fit in the {} x {} frame",
+            self.crop, self.frame_size.0, self.frame_size.1
+        )
+    }
+}
+
+impl Error for CropOutsideFrame {}
+
+/// Where a window's client area is in a capture of the window. `frame` is the
+/// window as it appears on screen, which is what a window capture shows, and
+/// `client_origin` and `client_size` describe the client area. All of them are
+/// in screen coordinates, so they can be negative on multi-monitor setups.
+/// Per-monitor DPI aware processes get physical pixels on every monitor, so no
+/// scaling is involved. Fails if the client area isn't entirely in the frame.
+pub fn client_in_frame(
+    frame: &Rect,
+    client_origin: (i32, i32),
+    client_size: (i32, i32),
+) -> Result<Rect, CropOutsideFrame> {
+    Rect::new(
+        client_origin.0,
+        client_origin.1,
+        client_size.0,
+        client_size.1,
+    )
+    .relative_to((frame.x, frame.y))
+    .crop_of((frame.width.max(0) as u32, frame.height.max(0) as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rects_with_negative_origins() {
+        let rect = Rect::from_edges(-1920, -200, -1520, 100);
+        assert_eq!(rect, Rect::new(-1920, -200, 400, 300));
+        assert_eq!((rect.right(), rect.bottom()), (-1520, 100));
+        assert_eq!(rect.area(), 120_000);
+        assert!(rect.contains(-1920, -200));
+        assert!(!rect.contains(-1520, 0));
+        assert_eq!(
+            rect.intersection(&Rect::new(-1600, 0, 1000, 1000)),
+            Some(Rect::new(-1600, 0, 80, 100))
+        );
+        // Touching edges don't overlap.
+        assert_eq!(rect.intersection(&Rect::new(-1520, -200, 10, 10)), None);
+        assert_eq!(rect.relative_to((-2560, -200)), Rect::new(640, 0, 400, 300));
+        assert_eq!(rect.to_string(), "(-1920, -200) 400 x 300");
+    }
+
+    #[test]
+    fn crops_have_to_fit_in_the_frame() {
+        let frame_size = (800, 600);
+        let inside = Rect::new(0, 0, 800, 600);
+        assert_eq!(inside.crop_of(frame_size), Ok(inside));
+        assert_eq!(
+            Rect::new(10, 20, 30, 40).crop_of(frame_size),
+            Ok(Rect::new(10, 20, 30, 40))
+        );
+        for outside in [
+            Rect::new(-1, 0, 10, 10),
+            Rect::new(0, 0, 801, 600),
+            Rect::new(790, 590, 20, 20),
+            Rect::new(900, 0, 10, 10),
+            Rect::new(10, 10, 0, 10),
+        ] {
+            assert_eq!(
+                outside.crop_of(frame_size),
+                Err(CropOutsideFrame {
+                    crop: outside,
+                    frame_size
+                })
+            );
+        }
+        assert_eq!(
+            Rect::new(-1, 0, 10, 10)
+                .crop_of(frame_size)
+                .unwrap_err()
+                .to_string(),
+            "the crop (-1, 0) 10 x 10 doesn't fit in the 800 x 600 frame"
+        );
+    }
+
+    #[test]
+    fn client_area_in_a_window_frame() {
+        // An 800 x 600 client area with an 8 pixel border and a 31 pixel title
+        // bar at 96 DPI.
+        let frame = Rect::new(100, 100, 816, 639);
+        assert_eq!(
+            client_in_frame(&frame, (108, 131), (800, 600)),
+            Ok(Rect::new(8, 31, 800, 600))
+        );
+    }
+
+    #[test]
+    fn client_area_on_a_monitor_left_of_the_primary() {
+        // Monitors left of and above the primary one have negative coordinates,
+        // the offset within the frame is the same.
+        let frame = Rect::new(-1928, -208, 816, 639);
+        assert_eq!(
+            client_in_frame(&frame, (-1920, -177), (800, 600)),
+            Ok(Rect::new(8, 31, 800, 600))
+        );
+        // A window that spans two monitors.
+        let frame = Rect::new(-408, 50, 816, 639);
+        assert_eq!(
+            client_in_frame(&frame, (-400, 81), (800, 600)),
+            Ok(Rect::new(8, 31, 800, 600))
+        );
+    }
+
+    #[test]
+    fn client_area_at_150_percent() {
+        // At 144 DPI the borders and the title bar are 1.5 times as large, and
+        // everything is in physical pixels already.
+        let frame = Rect::new(-2000, 100, 1224, 958);
+        assert_eq!(
+            client_in_frame(&frame, (-1988, 147), (1200, 900)),
+            Ok(Rect::new(12, 47, 1200, 900))
+        );
+        // Bounds in logical units, from a process that isn't DPI aware, don't
+        // fit the client area in physical pixels.
+        assert_eq!(
+            client_in_frame(&Rect::new(-2000, 100, 816, 639), (-1988, 147), (1200, 900)),
+            Err(CropOutsideFrame {
+                crop: Rect::new(12, 47, 1200, 900),
+                frame_size: (816, 639)
+            })
+        );
+    }
+
+    #[test]
+    fn client_area_outside_of_the_frame() {
+        // The frame lags behind a window that grew.
+        let frame = Rect::new(0, 0, 400, 300);
+        let error = client_in_frame(&frame, (8, 31), (800, 600)).unwrap_err();
+        assert_eq!(error.crop, Rect::new(8, 31, 800, 600));
+        assert_eq!(error.frame_size, (400, 300));
+        let error = crate::error::TestError::from(error);
+        assert_eq!(error.kind(), "assertion");
+        assert!(error
+            .to_string()
+            .starts_with("Window placement check failed for \"the crop fits in the frame\"!"));
+        // A bogus frame with a negative size fits nothing.
+        let frame = Rect::new(0, 0, -10, 300);
+        assert_eq!(
+            client_in_frame(&frame, (0, 0), (5, 5))
+                .unwrap_err()
+                .frame_size,
+            (0, 300)
+        );
+    }
+}
@@ -0,0 +1,511 @@
+use std::fs;
+
+use super::{
+    alignment::diagnose_misalignment,
+    assertion::{Artifact, AssertionFailure, AssertionKind, Capture, Detail, Tolerance, Value},
+    cpu_image::CpuImage,
+    diff::generate_diff,
+    error::{AssertionError, TestError, TestResult},
+    mask::Mask,
+    metrics::ImageMetrics,
+    test_runner::TestContext,
+};
+
+/// The result of comparing two images of the same size pixel by pixel.
+pub struct ImageComparison {
+    pub mismatched_pixels: u64,
+    pub max_difference: u8,
+    pub first_mismatch: Option<(u32, u32)>,
+}
+
+/// Compares `actual` against `expected`, counting the pixels where any channel
+/// differs by more than `tolerance`. Returns `None` if the sizes differ.
+pub fn compare_images(
+    expected: &CpuImage,
+    actual: &CpuImage,
+    tolerance: u8,
+) -> Option<ImageComparison> {
+    if expected.width() != actual.width() || expected.height() != actual.height() {
+        return None;
+    }
+
+    let mut comparison = ImageComparison {
+        mismatched_pixels: 0,
+        max_difference: 0,
+        first_mismatch: None,
+    };
+    for y in 0..actual.height() {
+        for x in 0..actual.width() {
+            let expected_pixel = expected.read_pixel(x, y).unwrap();
+            let actual_pixel = actual.read_pixel(x, y).unwrap();
+            let difference = [
+                expected_pixel.B.abs_diff(actual_pixel.B),
+                expected_pixel.G.abs_diff(actual_pixel.G),
+                expected_pixel.R.abs_diff(actual_pixel.R),
+                expected_pixel.A.abs_diff(actual_pixel.A),
+            ]
+            .into_iter()
+            .max()
+            .unwrap();
+            comparison.max_difference = comparison.max_difference.max(difference);
+            if difference > tolerance {
+                comparison.mismatched_pixels += 1;
+                if comparison.first_mismatch.is_none() {
+                    comparison.first_mismatch = Some((x, y));
+                }
+            }
+        }
+    }
+    Some(comparison)
+}
+
+/// Compares `image` against the golden image called `name` (e.g. "alpha/red_circle"),
+/// which is stored as `goldens/<name>.png`. When the runner was started with `--bless`,
+/// the golden is (re)written from `image` instead and the check always passes. A
+/// missing golden fails the check otherwise.
+///
+/// All similarity metrics are recorded in the report whether or not the check passes.
+/// If there is a `goldens/<name>.mask.png` next to the golden, the pixels it marks
+/// in white are ignored.
+pub fn expect_matches_golden<T: Into<Tolerance>>(
+    context: &TestContext,
+    name: &str,
+    image: &CpuImage,
+    tolerance: T,
+) -> TestResult<()> {
+    match_golden(context, name, image, tolerance.into(), None)
+}
+
+/// Like [`expect_matches_golden`], but also ignores the pixels in `mask`.
+pub fn expect_matches_golden_masked<T: Into<Tolerance>>(
+    context: &TestContext,
+    name: &str,
+    image: &CpuImage,
+    tolerance: T,
+    mask: &Mask,
+) -> TestResult<()> {
+    match_golden(context, name, image, tolerance.into(), Some(mask))
+}
+
+fn match_golden(
+    context: &TestContext,
+    name: &str,
+    image: &CpuImage,
+    tolerance: Tolerance,
+    mask: Option<&Mask>,
+) -> TestResult<()> {
+    let path = context.golden_path(name);
+    if context.bless() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        image.save_png(&path)?;
+        return Ok(());
+    }
+
+    let failure = AssertionFailure {
+        subject: Some(name.to_owned()),
+        tolerance: Some(tolerance),
+        ..AssertionFailure::new(AssertionKind::GoldenImage)
+    };
+    if !path.exists() {
+        return golden_error(
+            AssertionFailure {
+                details: vec![Detail {
+                    label: "Missing golden",
+                    value: Value::Text(path.display().to_string()),
+                }],
+                notes: vec!["Rerun with --bless to create it.".to_owned()],
+                ..failure
+            },
+            image,
+            Vec::new(),
+        );
+    }
+
+    let golden = CpuImage::load_png(&path)?;
+    let mut mask = mask.cloned();
+    let mask_path = path.with_extension("mask.png");
+    if mask_path.exists() {
+        let golden_mask = Mask::load_png(&mask_path)?;
+        mask = Some(match mask {
+            Some(mut mask) => {
+                if (mask.width(), mask.height()) != (golden_mask.width(), golden_mask.height()) {
+                    return golden_error(
+                        AssertionFailure {
+                            details: vec![
+                                Detail {
+                                    label: "Golden mask size",
+                                    value: size_of(golden_mask.width(), golden_mask.height()),
+                                },
+                                Detail {
+                                    label: "Mask size",
+                                    value: size_of(mask.width(), mask.height()),
+                                },
+                            ],
+                            notes: vec![
+                                "The golden mask doesn't match the mask passed to the check."
+                                    .to_owned(),
+                            ],
+                            ..failure
+                        },
+                        image,
+                        Vec::new(),
+                    );
+                }
+                mask.combine(&golden_mask);
+                mask
+            }
+            None => golden_mask,
+        });
+    }
+    // Ignored pixels are taken from the golden so that every comparison below skips
+    // them. The failure still reports the unmodified capture.
+    let captured = image;
+    let masked = match mask.as_ref().map(|mask| mask.apply(&golden, image)) {
+        Some(Ok(masked)) => Some(masked),
+        Some(Err(mismatch)) => {
+            return golden_error(
+                AssertionFailure {
+                    details: mismatch.details(),
+                    notes: vec![format!("Nothing was compared, {}.", mismatch)],
+                    ..failure
+                },
+                image,
+                Vec::new(),
+            )
+        }
+        None => None,
+    };
+    let image = masked.as_ref().unwrap_or(image);
+
+    let pixel_tolerance = match tolerance {
+        Tolerance::PerPixel(tolerance) => tolerance,
+        _ => 0,
+    };
+    let artifacts = || {
+        let diff = generate_diff(&golden, image, pixel_tolerance);
+        vec![
+            Artifact {
+                name: "expected".to_owned(),
+                image: golden.clone(),
+            },
+            Artifact {
+                name: "diff".to_owned(),
+                image: diff.heatmap,
+            },
+            Artifact {
+                name: "mask".to_owned(),
+                image: diff.mask,
+            },
+            Artifact {
+                name: "composite".to_owned(),
+                image: diff.composite,
+            },
+        ]
+    };
+
+    let (comparison, metrics) = match (
+        compare_images(&golden, image, pixel_tolerance),
+        ImageMetrics::compute(&golden, image),
+    ) {
+        (Some(comparison), Some(metrics)) => (comparison, metrics),
+        _ => {
+            return golden_error(
+                AssertionFailure {
+                    expected: Some(size_of(golden.width(), golden.height())),
+                    actual: Some(size_of(image.width(), image.height())),
+                    details: diagnosis(&golden, image),
+                    ..failure
+                },
+                captured,
+                artifacts(),
+            )
+        }
+    };
+    context.record_metric(format!("{}.ssim", name), metrics.ssim);
+    context.record_metric(format!("{}.ms_ssim", name), metrics.ms_ssim);
+    context.record_metric(format!("{}.psnr", name), metrics.psnr);
+    context.record_metric(format!("{}.mean_error", name), metrics.mean_error);
+    context.record_metric(format!("{}.max_error", name), metrics.max_error as f64);
+
+    let passed = match tolerance {
+        Tolerance::PerPixel(_) => comparison.first_mismatch.is_none(),
+        Tolerance::Ssim(minimum) => metrics.ssim >= minimum,
+        Tolerance::MsSsim(minimum) => metrics.ms_ssim >= minimum,
+        Tolerance::Psnr(minimum) => metrics.psnr >= minimum,
+    };
+    if passed {
+        return Ok(());
+    }
+
+    let mut details = vec![
+        Detail {
+            label: "Mismatched pixels",
+            value: Value::Count(comparison.mismatched_pixels),
+        },
+        Detail {
+            label: "Max channel difference",
+            value: Value::Count(comparison.max_difference as u64),
+        },
+        Detail {
+            label: "SSIM",
+            value: Value::Number(metrics.ssim),
+        },
+        Detail {
+            label: "MS-SSIM",
+            value: Value::Number(metrics.ms_ssim),
+        },
+        Detail {
+            label: "PSNR (dB)",
+            value: Value::Number(metrics.psnr),
+        },
+        Detail {
+            label: "Mean error",
+            value: Value::Number(metrics.mean_error),
+        },
+    ];
+    details.extend(diagnosis(&golden, image));
+    let (expected, actual) = match comparison.first_mismatch {
+        Some((x, y)) => (
+            golden.read_pixel(x, y).map(Value::Color),
+            image.read_pixel(x, y).map(Value::Color),
+        ),
+        None => (None, None),
+    };
+    golden_error(
+        AssertionFailure {
+            location: comparison.first_mismatch,
+            expected,
+            actual,
+            details,
+            ..failure
+        },
+        captured,
+        artifacts(),
+    )
+}
+
+/// Describes how the content is misaligned, if that explains the failure.
+fn diagnosis(golden: &CpuImage, image: &CpuImage) -> Vec<Detail> {
+    diagnose_misalignment(golden, image)
+        .map(|misalignment| Detail {
+            label: "Diagnosis",
+            value: Value::Text(misalignment.to_string()),
+        })
+        .into_iter()
+        .collect()
+}
+
+fn size_of(width: u32, height: u32) -> Value {
+    Value::Size { width, height }
+}
+
+fn golden_error(
+    failure: AssertionFailure,
+    image: &CpuImage,
+    artifacts: Vec<Artifact>,
+) -> TestResult<()> {
+    TestError::Assertion(Box::new(AssertionError {
+        failure,
+        capture: Some(Capture::Image(image.clone())),
+        artifacts,
+    }))
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{
+        blobs::expect_circle,
+        color::{common_colors, Color},
+        geometry::Rect,
+        test_runner::test_output_dir,
+    };
+
+    fn gradient(width: u32, height: u32) -> CpuImage {
+        CpuImage::from_fn(width, height, |x, y| Color {
+            B: (x * 16) as u8,
+            G: (y * 16) as u8,
+            R: 128,
+            A: 255,
+        })
+    }
+
+    fn assertion(result: TestResult<()>) -> AssertionError {
+        match result {
+            Err(TestError::Assertion(error)) => *error,
+            Err(error) => panic!("expected an assertion, got {}", error),
+            Ok(()) => panic!("expected an assertion, but the check passed"),
+        }
+    }
+
+    /// A context with the golden `name` blessed from `image`.
+    fn blessed(dir: &str, name: &str, image: &CpuImage) -> TestContext {
+        let output_dir = test_output_dir(dir);
+        let blessing = TestContext::new(output_dir.clone()).with_bless();
+        expect_matches_golden(&blessing, name, image, 0).unwrap();
+        TestContext::new(output_dir)
+    }
+
+    #[test]
+    fn bless_writes_the_golden() {
+        let context = TestContext::new(test_output_dir("golden-bless")).with_bless();
+        let image = gradient(8, 8);
+        expect_matches_golden(&context, "synthetic/gradient", &image, 0).unwrap();
+
+        let path = context.golden_path("synthetic/gradient");
+        assert_eq!(CpuImage::load_png(path).unwrap(), image);
+    }
+
+    #[test]
+    fn missing_golden_fails_without_bless() {
+        let context = TestContext::new(test_output_dir("golden-missing"));
+        let image = gradient(8, 8);
+        let error = assertion(expect_matches_golden(
+            &context,
+            "synthetic/gradient",
+            &image,
+            0,
+        ));
+
+        assert_eq!(error.failure.kind, AssertionKind::GoldenImage);
+        assert_eq!(error.failure.subject.as_deref(), Some("synthetic/gradient"));
+        assert_eq!(error.failure.details[0].label, "Missing golden");
+        assert!(matches!(error.capture, Some(Capture::Image(captured)) if captured == image));
+        assert!(!context.golden_path("synthetic/gradient").exists());
+    }
+
+    #[test]
+    fn matching_image_passes() {
+        let image = gradient(8, 8);
+        let context = blessed("golden-match", "synthetic/gradient", &image);
+        expect_matches_golden(&context, "synthetic/gradient", &image, 0).unwrap();
+        expect_matches_golden(
+            &context,
+            "synthetic/gradient",
+            &image,
+            Tolerance::Ssim(0.99),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn mismatch_reports_location_and_artifacts() {
+        let golden = gradient(8, 8);
+        let context = blessed("golden-mismatch", "synthetic/gradient", &golden);
+        let mut image = golden.clone();
+        image.write_pixel(3, 2, common_colors::BLUE);
+        image.write_pixel(5, 6, common_colors::BLUE);
+
+        let error = assertion(expect_matches_golden(
+            &context,
+            "synthetic/gradient",
+            &image,
+            0,
+        ));
+        assert_eq!(error.failure.location, Some((3, 2)));
+        assert!(matches!(
+            error.failure.expected,
+            Some(Value::Color(color)) if color == golden.read_pixel(3, 2).unwrap()
+        ));
+        assert!(matches!(
+            error.failure.actual,
+            Some(Value::Color(color)) if color == common_colors::BLUE
+        ));
+        assert!(matches!(error.failure.details[0].value, Value::Count(2)));
+        let names: Vec<_> = error
+            .artifacts
+            .iter()
+            .map(|artifact| artifact.name.as_str())
+            .collect();
+        assert_eq!(names, ["expected", "diff", "mask", "composite"]);
+        assert_eq!(error.artifacts[0].image, golden);
+        assert!(matches!(error.capture, Some(Capture::Image(captured)) if captured == image));
+    }
+
+    #[test]
+    fn size_mismatch_fails() {
+        let context = blessed("golden-size", "synthetic/gradient", &gradient(8, 8));
+        let error = assertion(expect_matches_golden(
+            &context,
+            "synthetic/gradient",
+            &gradient(8, 6),
+            0,
+        ));
+        assert!(matches!(
+            error.failure.expected,
+            Some(Value::Size {
+                width: 8,
+                height: 8
+            })
+        ));
+        assert!(matches!(
+            error.failure.actual,
+            Some(Value::Size {
+                width: 8,
+                height: 6
+            })
+        ));
+    }
+
+    #[test]
+    fn masked_pixels_are_not_compared() {
+        let golden = gradient(8, 8);
+        let context = blessed("golden-masked", "synthetic/gradient", &golden);
+        let mut image = golden.clone();
+        image.write_pixel(3, 2, common_colors::BLUE);
+        let mut mask = Mask::new(8, 8);
+        mask.ignore_rect(Rect::new(3, 2, 1, 1));
+
+        expect_matches_golden_masked(&context, "synthetic/gradient", &image, 0, &mask).unwrap();
+        let error = assertion(expect_matches_golden(
+            &context,
+            "synthetic/gradient",
+            &image,
+            0,
+        ));
+        assert_eq!(error.failure.location, Some((3, 2)));
+    }
+
+    #[test]
+    fn mask_size_mismatch_fails() {
+        let image = gradient(8, 8);
+        let context = blessed("golden-mask-size", "synthetic/gradient", &image);
+        let error = assertion(expect_matches_golden_masked(
+            &context,
+            "synthetic/gradient",
+            &image,
+            0,
+            &Mask::new(8, 6),
+        ));
+        assert_eq!(error.failure.kind, AssertionKind::GoldenImage);
+        assert_eq!(error.failure.details[0].label, "Mask size");
+        assert!(matches!(
+            error.failure.details[0].value,
+            Value::Size {
+                width: 8,
+                height: 6
+            }
+        ));
+        assert!(matches!(error.capture, Some(Capture::Image(captured)) if captured == image));
+    }
+
+    /// The checked-in golden for `alpha/red_circle` passes the same checks the
+    /// test runs on the capture before comparing against it.
+    #[test]
+    fn red_circle_golden_is_a_red_circle() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../goldens/alpha/red_circle.png");
+        let golden = CpuImage::load_png(path).unwrap();
+        assert_eq!((golden.width(), golden.height()), (100, 100));
+        assert_eq!(golden.read_pixel(50, 50), Some(common_colors::RED));
+        assert_eq!(
+            golden.read_pixel(5, 5),
+            Some(common_colors::TRANSPARENT_BLACK)
+        );
+        expect_circle(&golden, common_colors::RED, 64, (50.0, 50.0), 50.0, 1.0).unwrap();
+    }
+}
@@ -0,0 +1,32 @@
+//! The parts of the test harness that don't depend on Windows: the runner and
+//! its reports, images in system memory and everything that checks them. With
+//! the `windows` feature, Windows errors and colors convert into the types used
+//! here.
+
+pub mod alignment;
+pub mod assertion;
+pub mod async_capture;
+pub mod blobs;
+pub mod capture;
+pub mod color;
+pub mod color_math;
+pub mod cpu_image;
+pub mod desktop;
+pub mod diff;
+pub mod dirty_regions;
+pub mod error;
+pub mod fake_capture;
+pub mod geometry;
+pub mod golden;
+pub mod mask;
+pub mod metrics;
+pub mod neighborhood;
+mod plane;
+pub mod preview;
+pub mod recording;
+pub mod report;
+pub mod resize;
+pub mod scenarios;
+pub mod stats;
+pub mod test_runner;
+pub mod timing;
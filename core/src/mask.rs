@@ -0,0 +1,321 @@
+use std::{error::Error, fmt, io, path::Path};
+
+use super::{
+    assertion::{Detail, Value},
+    cpu_image::CpuImage,
+    geometry::Rect,
+};
+
+/// A set of pixels to leave out of a comparison, such as the cursor or parts
+/// of the image that change from run to run.
+#[derive(Clone, Debug)]
+pub struct Mask {
+    width: u32,
+    height: u32,
+    ignored: Vec<bool>,
+}
+
+impl Mask {
+    /// Creates a mask that doesn't ignore anything.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            ignored: vec![false; (width * height) as usize],
+        }
+    }
+
+    /// Loads a mask from an image where white pixels are ignored and black pixels
+    /// are compared. Transparent pixels are always compared.
+    pub fn load_png<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let image = CpuImage::load_png(path)?;
+        let mut mask = Self::new(image.width(), image.height());
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                let pixel = image.read_pixel(x, y).unwrap();
+                let luma = (pixel.R as u32 + pixel.G as u32 + pixel.B as u32) / 3;
+                if pixel.A >= 128 && luma >= 128 {
+                    mask.ignore_pixel(x, y);
+                }
+            }
+        }
+        Ok(mask)
+    }
+
+    /// Builds a mask that ignores the cursor's bounds. `cursor` is relative to the
+    /// captured image, so it may be partially or entirely outside of it.
+    pub fn for_cursor(width: u32, height: u32, cursor: Option<Rect>) -> Self {
+        let mut mask = Self::new(width, height);
+        if let Some(cursor) = cursor {
+            mask.ignore_rect(cursor);
+        }
+        mask
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Fails unless the mask has the size of the `width` x `height` image it is
+    /// used with. A mask of another size would only cover part of the image.
+    pub fn expect_size(&self, width: u32, height: u32) -> Result<(), MaskSizeMismatch> {
+        if (self.width, self.height) == (width, height) {
+            Ok(())
+        } else {
+            Err(MaskSizeMismatch {
+                mask_size: (self.width, self.height),
+                image_size: (width, height),
+            })
+        }
+    }
+
+    pub fn is_ignored(&self, x: u32, y: u32) -> bool {
+        x < self.width && y < self.height && self.ignored[(y * self.width + x) as usize]
+    }
+
+    pub fn ignored_pixels(&self) -> usize {
+        self.ignored.iter().filter(|ignored| **ignored).count()
+    }
+
+    /// Ignores every pixel inside `rect`. Parts of the rect outside of the mask are dropped.
+    pub fn ignore_rect(&mut self, rect: Rect) -> &mut Self {
+        self.ignore_where(rect, |_, _| true)
+    }
+
+    /// Ignores every pixel whose center is inside the ellipse inscribed in `bounds`.
+    pub fn ignore_ellipse(&mut self, bounds: Rect) -> &mut Self {
+        let radius_x = bounds.width as f64 / 2.0;
+        let radius_y = bounds.height as f64 / 2.0;
+        let center_x = bounds.x as f64 + radius_x;
+        let center_y = bounds.y as f64 + radius_y;
+        self.ignore_where(bounds, |x, y| {
+            let dx = (x as f64 + 0.5 - center_x) / radius_x;
+            let dy = (y as f64 + 0.5 - center_y) / radius_y;
+            dx * dx + dy * dy <= 1.0
+        })
+    }
+
+    /// Ignores a band of `thickness` pixels along each edge, e.g. where the capture
+    /// border is drawn.
+    pub fn ignore_border(&mut self, thickness: u32) -> &mut Self {
+        let (width, height, thickness) = (self.width as i32, self.height as i32, thickness as i32);
+        self.ignore_rect(Rect::new(0, 0, width, thickness))
+            .ignore_rect(Rect::new(0, height - thickness, width, thickness))
+            .ignore_rect(Rect::new(0, 0, thickness, height))
+            .ignore_rect(Rect::new(width - thickness, 0, thickness, height))
+    }
+
+    /// Ignores everything that `other` ignores. Both masks need to be the same size.
+    pub fn combine(&mut self, other: &Mask) -> &mut Self {
+        assert_eq!((self.width, self.height), (other.width, other.height));
+        for (ignored, other) in self.ignored.iter_mut().zip(&other.ignored) {
+            *ignored |= *other;
+        }
+        self
+    }
+
+    /// Returns a copy of `actual` where every ignored pixel has been replaced with
+    /// the corresponding pixel from `expected`, so that any comparison between the
+    /// two skips the ignored pixels. Fails unless the mask has the size of
+    /// `actual`. Pixels that `expected` doesn't have are left alone.
+    pub fn apply(
+        &self,
+        expected: &CpuImage,
+        actual: &CpuImage,
+    ) -> Result<CpuImage, MaskSizeMismatch> {
+        self.expect_size(actual.width(), actual.height())?;
+        let mut result = actual.clone();
+        for y in 0..actual.height() {
+            for x in 0..actual.width() {
+                if self.is_ignored(x, y) {
+                    if let Some(color) = expected.read_pixel(x, y) {
+                        result.write_pixel(x, y, color);
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn ignore_pixel(&mut self, x: u32, y: u32) {
+        self.ignored[(y * self.width + x) as usize] = true;
+    }
+
+    fn ignore_where<F: Fn(i32, i32) -> bool>(&mut self, rect: Rect, predicate: F) -> &mut Self {
+        let left = rect.x.max(0);
+        let top = rect.y.max(0);
+        let right = rect.right().min(self.width as i32);
+        let bottom = rect.bottom().min(self.height as i32);
+        for y in top..bottom {
+            for x in left..right {
+                if predicate(x, y) {
+                    self.ignore_pixel(x as u32, y as u32);
+                }
+            }
+        }
+        self
+    }
+}
+
+/// A mask that doesn't have the size of the image it was used with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaskSizeMismatch {
+    pub mask_size: (u32, u32),
+    pub image_size: (u32, u32),
+}
+
+impl MaskSizeMismatch {
+    /// The sizes, for an assertion failure.
+    pub fn details(&self) -> Vec<Detail> {
+        vec![
+            Detail {
+                label: "Mask size",
+                value: Value::Size {
+                    width: self.mask_size.0,
+                    height: self.mask_size.1,
+                },
+            },
+            Detail {
+                label: "Image size",
+                value: Value::Size {
+                    width: self.image_size.0,
+                    height: self.image_size.1,
+                },
+            },
+        ]
+    }
+}
+
+impl fmt::Display for MaskSizeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the {} x {} mask doesn't match the {} x {} image",
+            self.mask_size.0, self.mask_size.1, self.image_size.0, self.image_size.1
+        )
+    }
+}
+
+impl Error for MaskSizeMismatch {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color::{common_colors, Color},
+        test_runner::test_output_dir,
+    };
+
+    /// The ignored pixels, row by row, as `#` and `.`.
+    fn picture(mask: &Mask) -> Vec<String> {
+        (0..mask.height())
+            .map(|y| {
+                (0..mask.width())
+                    .map(|x| if mask.is_ignored(x, y) { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rects_are_clipped_to_the_mask() {
+        let mut mask = Mask::new(5, 4);
+        mask.ignore_rect(Rect::new(1, 1, 2, 2))
+            .ignore_rect(Rect::new(-3, 3, 5, 4));
+        assert_eq!(picture(&mask), [".....", ".##..", ".##..", "##..."]);
+        assert_eq!(mask.ignored_pixels(), 6);
+        assert!(!mask.is_ignored(5, 0));
+    }
+
+    #[test]
+    fn ellipses_cover_the_pixels_whose_center_is_inside() {
+        let mut mask = Mask::new(7, 5);
+        mask.ignore_ellipse(Rect::new(0, 0, 7, 5));
+        assert_eq!(
+            picture(&mask),
+            [".#####.", "#######", "#######", "#######", ".#####."]
+        );
+    }
+
+    #[test]
+    fn borders_cover_every_edge() {
+        let mut mask = Mask::new(5, 4);
+        mask.ignore_border(1);
+        assert_eq!(picture(&mask), ["#####", "#...#", "#...#", "#####"]);
+    }
+
+    #[test]
+    fn cursors_partly_off_the_image_are_clipped() {
+        let mask = Mask::for_cursor(4, 4, Some(Rect::new(2, -1, 4, 3)));
+        assert_eq!(picture(&mask), ["..##", "..##", "....", "...."]);
+        assert_eq!(Mask::for_cursor(4, 4, None).ignored_pixels(), 0);
+        // A cursor entirely off the image doesn't ignore anything.
+        let off_image = Mask::for_cursor(4, 4, Some(Rect::new(-10, -10, 4, 4)));
+        assert_eq!(off_image.ignored_pixels(), 0);
+    }
+
+    #[test]
+    fn white_opaque_pixels_of_a_png_are_ignored() {
+        let image = CpuImage::from_fn(4, 1, |x, _| match x {
+            0 => Color {
+                A: 255,
+                R: 255,
+                G: 255,
+                B: 255,
+            },
+            1 => Color {
+                A: 0,
+                R: 255,
+                G: 255,
+                B: 255,
+            },
+            2 => common_colors::TRANSPARENT_BLACK,
+            _ => Color {
+                A: 255,
+                R: 200,
+                G: 200,
+                B: 200,
+            },
+        });
+        let path = test_output_dir("mask-png").join("mask.png");
+        image.save_png(&path).unwrap();
+        let mask = Mask::load_png(&path).unwrap();
+        assert_eq!(picture(&mask), ["#..#"]);
+    }
+
+    #[test]
+    fn apply_takes_the_ignored_pixels_from_expected() {
+        let expected = CpuImage::from_fn(3, 2, |_, _| common_colors::GREEN);
+        let actual = CpuImage::from_fn(3, 2, |_, _| common_colors::RED);
+        let mut mask = Mask::new(3, 2);
+        mask.ignore_rect(Rect::new(1, 0, 1, 2));
+
+        let masked = mask.apply(&expected, &actual).unwrap();
+        for y in 0..2 {
+            assert_eq!(masked.read_pixel(0, y), Some(common_colors::RED));
+            assert_eq!(masked.read_pixel(1, y), Some(common_colors::GREEN));
+            assert_eq!(masked.read_pixel(2, y), Some(common_colors::RED));
+        }
+    }
+
+    #[test]
+    fn masks_of_another_size_are_rejected() {
+        let mask = Mask::new(3, 2);
+        let image = CpuImage::new(4, 2);
+        let mismatch = MaskSizeMismatch {
+            mask_size: (3, 2),
+            image_size: (4, 2),
+        };
+        assert_eq!(mask.expect_size(3, 2), Ok(()));
+        assert_eq!(mask.expect_size(4, 2), Err(mismatch));
+        assert_eq!(mask.apply(&image, &image), Err(mismatch));
+        assert_eq!(
+            mismatch.to_string(),
+            "the 3 x 2 mask doesn't match the 4 x 2 image"
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use super::{cpu_image::CpuImage, plane::Plane};
+
+// Constants from Wang et al., "Image Quality Assessment: From Error Visibility to
+// Structural Similarity", for 8-bit channels.
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+const SSIM_WINDOW: u32 = 8;
+const SSIM_STRIDE: u32 = 4;
+// Per-scale weights from Wang et al., "Multi-scale Structural Similarity for Image
+// Quality Assessment".
+const MS_SSIM_WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+
+/// Similarity metrics between two images of the same size. SSIM values are
+/// averaged over the B, G, R and A channels.
+#[derive(Clone, Copy, Debug)]
+pub struct ImageMetrics {
+    /// Structural similarity, 1.0 for identical images.
+    pub ssim: f64,
+    /// Multi-scale structural similarity, 1.0 for identical images.
+    pub ms_ssim: f64,
+    /// Peak signal-to-noise ratio in dB, infinite for identical images.
+    pub psnr: f64,
+    /// Mean absolute difference over all channels of all pixels.
+    pub mean_error: f64,
+    /// Largest absolute difference of any channel of any pixel.
+    pub max_error: u8,
+}
+
+impl ImageMetrics {
+    /// Returns `None` if the images differ in size.
+    pub fn compute(expected: &CpuImage, actual: &CpuImage) -> Option<Self> {
+        if expected.width() != actual.width() || expected.height() != actual.height() {
+            return None;
+        }
+
+        let mut squared_error = 0.0;
+        let mut total_error = 0.0;
+        let mut max_error = 0;
+        for (expected, actual) in expected.bytes().iter().zip(actual.bytes()) {
+            let error = expected.abs_diff(*actual);
+            squared_error += (error as f64) * (error as f64);
+            total_error += error as f64;
+            max_error = max_error.max(error);
+        }
+        let samples = expected.bytes().len().max(1) as f64;
+        let mse = squared_error / samples;
+        let psnr = if mse == 0.0 {
+            f64::INFINITY
+        } else {
+            10.0 * (255.0 * 255.0 / mse).log10()
+        };
+
+        let mut ssim = 0.0;
+        let mut ms_ssim = 0.0;
+        for channel in 0..4 {
+            let expected = Plane::from_channel(expected, channel);
+            let actual = Plane::from_channel(actual, channel);
+            ssim += ssim_components(&expected, &actual).0;
+            ms_ssim += multi_scale_ssim(expected, actual);
+        }
+
+        Some(Self {
+            ssim: ssim / 4.0,
+            ms_ssim: ms_ssim / 4.0,
+            psnr,
+            mean_error: total_error / samples,
+            max_error,
+        })
+    }
+}
+
+/// Returns the mean SSIM and the mean contrast-structure term over all windows.
+fn ssim_components(expected: &Plane, actual: &Plane) -> (f64, f64) {
+    // Images smaller than a window are treated as a single window.
+    let window_width = SSIM_WINDOW.min(expected.width);
+    let window_height = SSIM_WINDOW.min(expected.height);
+    if window_width == 0 || window_height == 0 {
+        return (1.0, 1.0);
+    }
+
+    let mut ssim_sum = 0.0;
+    let mut cs_sum = 0.0;
+    let mut windows = 0;
+    let mut top = 0;
+    while top + window_height <= expected.height {
+        let mut left = 0;
+        while left + window_width <= expected.width {
+            let count = (window_width * window_height) as f64;
+            let (mut sum_x, mut sum_y) = (0.0, 0.0);
+            for y in top..top + window_height {
+                for x in left..left + window_width {
+                    sum_x += expected.value(x, y);
+                    sum_y += actual.value(x, y);
+                }
+            }
+            let (mean_x, mean_y) = (sum_x / count, sum_y / count);
+            let (mut var_x, mut var_y, mut covariance) = (0.0, 0.0, 0.0);
+            for y in top..top + window_height {
+                for x in left..left + window_width {
+                    let dx = expected.value(x, y) - mean_x;
+                    let dy = actual.value(x, y) - mean_y;
+                    var_x += dx * dx;
+                    var_y += dy * dy;
+                    covariance += dx * dy;
+                }
+            }
+            let (var_x, var_y, covariance) = (var_x / count, var_y / count, covariance / count);
+
+            let luminance =
+                (2.0 * mean_x * mean_y + SSIM_C1) / (mean_x * mean_x + mean_y * mean_y + SSIM_C1);
+            let contrast_structure = (2.0 * covariance + SSIM_C2) / (var_x + var_y + SSIM_C2);
+            ssim_sum += luminance * contrast_structure;
+            cs_sum += contrast_structure;
+            windows += 1;
+
+            left += SSIM_STRIDE;
+        }
+        top += SSIM_STRIDE;
+    }
+
+    (ssim_sum / windows as f64, cs_sum / windows as f64)
+}
+
+fn multi_scale_ssim(mut expected: Plane, mut actual: Plane) -> f64 {
+    // Only use as many scales as the image size allows and renormalize the weights.
+    let mut scales = 1;
+    let (mut width, mut height) = (expected.width / 2, expected.height / 2);
+    while scales < MS_SSIM_WEIGHTS.len() && width >= SSIM_WINDOW && height >= SSIM_WINDOW {
+        scales += 1;
+        width /= 2;
+        height /= 2;
+    }
+    let weights = &MS_SSIM_WEIGHTS[..scales];
+    let total_weight: f64 = weights.iter().sum();
+
+    let mut result = 1.0;
+    for (scale, weight) in weights.iter().enumerate() {
+        let (ssim, contrast_structure) = ssim_components(&expected, &actual);
+        // The luminance term only contributes at the coarsest scale.
+        let term = if scale + 1 == scales {
+            ssim
+        } else {
+            contrast_structure
+        };
+        result *= term.max(0.0).powf(weight / total_weight);
+        expected = expected.downsample();
+        actual = actual.downsample();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    fn gray(value: u8) -> Color {
+        Color {
+            A: 255,
+            R: value,
+            G: value,
+            B: value,
+        }
+    }
+
+    /// A diagonal gradient with enough structure for every SSIM window.
+    fn gradient(width: u32, height: u32) -> CpuImage {
+        CpuImage::from_fn(width, height, |x, y| gray(((x + y) * 4 % 256) as u8))
+    }
+
+    #[test]
+    fn identical_images_are_a_perfect_match() {
+        let image = gradient(64, 64);
+        let metrics = ImageMetrics::compute(&image, &image).unwrap();
+        assert!((metrics.ssim - 1.0).abs() < 1e-9);
+        assert!((metrics.ms_ssim - 1.0).abs() < 1e-9);
+        assert_eq!(metrics.psnr, f64::INFINITY);
+        assert_eq!(metrics.mean_error, 0.0);
+        assert_eq!(metrics.max_error, 0);
+    }
+
+    #[test]
+    fn different_sizes_have_no_metrics() {
+        assert!(ImageMetrics::compute(&gradient(16, 16), &gradient(16, 8)).is_none());
+    }
+
+    #[test]
+    fn uniform_offset_gives_known_errors() {
+        let expected = CpuImage::from_fn(16, 16, |_, _| gray(100));
+        let actual = CpuImage::from_fn(16, 16, |_, _| gray(110));
+        let metrics = ImageMetrics::compute(&expected, &actual).unwrap();
+        // B, G and R are off by 10, alpha matches.
+        assert_eq!(metrics.max_error, 10);
+        assert!((metrics.mean_error - 7.5).abs() < 1e-9);
+        let mse: f64 = 300.0 / 4.0;
+        assert!((metrics.psnr - 10.0 * (255.0 * 255.0 / mse).log10()).abs() < 1e-9);
+        // Only the luminance term drops, and only a little.
+        assert!(metrics.ssim < 1.0 && metrics.ssim > 0.95);
+    }
+
+    #[test]
+    fn inverted_structure_scores_low() {
+        let expected =
+            CpuImage::from_fn(64, 64, |x, y| gray(if (x + y) % 2 == 0 { 0 } else { 255 }));
+        let actual = CpuImage::from_fn(64, 64, |x, y| gray(if (x + y) % 2 == 0 { 255 } else { 0 }));
+        let metrics = ImageMetrics::compute(&expected, &actual).unwrap();
+        assert!(metrics.ssim < 0.3, "ssim was {}", metrics.ssim);
+        assert!(metrics.ms_ssim < 0.5, "ms_ssim was {}", metrics.ms_ssim);
+        assert_eq!(metrics.max_error, 255);
+        assert!(metrics.psnr < 5.0);
+    }
+
+    #[test]
+    fn images_smaller_than_a_window_still_compare() {
+        let image = gradient(3, 5);
+        let metrics = ImageMetrics::compute(&image, &image).unwrap();
+        assert!((metrics.ssim - 1.0).abs() < 1e-9);
+        assert!((metrics.ms_ssim - 1.0).abs() < 1e-9);
+    }
+}
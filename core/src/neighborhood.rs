@@ -0,0 +1,86 @@
+use std::fmt::{self, Write as _};
+
+use super::color::Color;
+
+/// The pixels in a square around a probed pixel, kept so that failure messages
+/// can show what surrounds it. Mostly useful when the probe lands on an edge.
+#[derive(Clone, Debug)]
+pub struct Neighborhood {
+    center: (u32, u32),
+    size: u32,
+    /// Row by row, `None` where the neighborhood extends past the image.
+    pixels: Vec<Option<Color>>,
+}
+
+impl Neighborhood {
+    /// Reads the `size` x `size` square centered on `center` using `read_pixel`,
+    /// which should return `None` for pixels outside of the image. Even sizes are
+    /// rounded up so that there is a center pixel.
+    pub fn sample<F: Fn(u32, u32) -> Option<Color>>(
+        center: (u32, u32),
+        size: u32,
+        read_pixel: F,
+    ) -> Self {
+        let size = size | 1;
+        let radius = (size / 2) as i64;
+        let mut pixels = Vec::with_capacity((size * size) as usize);
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let x = u32::try_from(center.0 as i64 + dx);
+                let y = u32::try_from(center.1 as i64 + dy);
+                pixels.push(match (x, y) {
+                    (Ok(x), Ok(y)) => read_pixel(x, y),
+                    _ => None,
+                });
+            }
+        }
+        Self {
+            center,
+            size,
+            pixels,
+        }
+    }
+}
+
+/// Draws the neighborhood as a grid of BGRA hex values with the coordinates along
+/// the edges. The probed pixel is bracketed and pixels outside of the image are
+/// drawn as dashes:
+///
+/// ```text
+///                49          50          51
+///     49   0000FFFF    0000FFFF    0000FFFF
+///     50   0000FFFF   [0000FFFF]   0000FFFF
+///     51   --------    --------    --------
+/// ```
+impl fmt::Display for Neighborhood {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let radius = (self.size / 2) as i64;
+        let (center_x, center_y) = (self.center.0 as i64, self.center.1 as i64);
+
+        let mut header = " ".repeat(6);
+        for dx in -radius..=radius {
+            write!(header, "{:>11} ", center_x + dx)?;
+        }
+        writeln!(f, "{}", header.trim_end())?;
+
+        for (row, dy) in (-radius..=radius).enumerate() {
+            let mut line = format!("{:>6}", center_y + dy);
+            for (column, dx) in (-radius..=radius).enumerate() {
+                let cell = match self.pixels[row * self.size as usize + column] {
+                    Some(color) => format!(
+                        "{:02X}{:02X}{:02X}{:02X}",
+                        color.B, color.G, color.R, color.A
+                    ),
+                    None => "--------".to_owned(),
+                };
+                if dx == 0 && dy == 0 {
+                    write!(line, "  [{}]", cell)?;
+                } else {
+                    write!(line, "   {} ", cell)?;
+                }
+            }
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
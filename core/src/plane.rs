@@ -0,0 +1,86 @@
+//! Single channel images of floating point values, which the image metrics and
+//! the misalignment search work on.
+
+use super::cpu_image::CpuImage;
+
+#[derive(Clone)]
+pub struct Plane {
+    pub width: u32,
+    pub height: u32,
+    pub values: Vec<f64>,
+}
+
+impl Plane {
+    /// Takes one channel of `image`, 0 to 3 for B, G, R and A.
+    pub fn from_channel(image: &CpuImage, channel: usize) -> Self {
+        Self {
+            width: image.width(),
+            height: image.height(),
+            values: image
+                .bytes()
+                .chunks_exact(4)
+                .map(|pixel| pixel[channel] as f64)
+                .collect(),
+        }
+    }
+
+    /// Combines the color and alpha of each pixel into a single value.
+    pub fn from_image(image: &CpuImage) -> Self {
+        let values = image
+            .bytes()
+            .chunks_exact(4)
+            .map(|pixel| {
+                let luma =
+                    0.114 * pixel[0] as f64 + 0.587 * pixel[1] as f64 + 0.299 * pixel[2] as f64;
+                (luma + pixel[3] as f64) / 2.0
+            })
+            .collect();
+        Self {
+            width: image.width(),
+            height: image.height(),
+            values,
+        }
+    }
+
+    /// The value at (`x`, `y`), which has to be inside the plane.
+    pub fn value(&self, x: u32, y: u32) -> f64 {
+        self.values[(y * self.width + x) as usize]
+    }
+
+    /// The value at (`x`, `y`), or `None` outside of the plane.
+    pub fn get(&self, x: i64, y: i64) -> Option<f64> {
+        if x >= 0 && y >= 0 && (x as u32) < self.width && (y as u32) < self.height {
+            Some(self.value(x as u32, y as u32))
+        } else {
+            None
+        }
+    }
+
+    /// Halves the plane in both dimensions by averaging 2x2 blocks. A plane
+    /// that is a single pixel across stays one pixel across.
+    pub fn downsample(&self) -> Self {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut values = Vec::with_capacity((width * height) as usize);
+        for y in 0..height as i64 {
+            for x in 0..width as i64 {
+                let samples = [
+                    self.get(2 * x, 2 * y),
+                    self.get(2 * x + 1, 2 * y),
+                    self.get(2 * x, 2 * y + 1),
+                    self.get(2 * x + 1, 2 * y + 1),
+                ];
+                let (sum, count) = samples
+                    .iter()
+                    .flatten()
+                    .fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+                values.push(sum / count as f64);
+            }
+        }
+        Self {
+            width,
+            height,
+            values,
+        }
+    }
+}
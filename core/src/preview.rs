@@ -0,0 +1,193 @@
+use std::fmt::Write as _;
+
+use super::cpu_image::CpuImage;
+
+// From darkest to lightest on a dark terminal background, where denser
+// characters light up more of the cell.
+const ASCII_RAMP: &[u8] = b" .:-=+*#%@";
+// Transparent areas are drawn over a checkerboard of these two grays.
+const CHECKER_LIGHT: [f64; 3] = [96.0, 96.0, 96.0];
+const CHECKER_DARK: [f64; 3] = [64.0, 64.0, 64.0];
+const MARKER_ASCII: char = 'X';
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PreviewStyle {
+    /// Truecolor ANSI escapes with two pixels per cell, using the upper half block.
+    Ansi,
+    /// Plain characters picked by luminance, for logs that don't keep escapes.
+    Ascii,
+}
+
+/// Renders a downscaled preview of `image` that is at most `columns` characters
+/// wide. Cells containing any of the `markers` (in image coordinates) are
+/// highlighted. The output only depends on the inputs, so it can be compared
+/// against a known good rendering.
+pub fn render_preview(
+    image: &CpuImage,
+    columns: u32,
+    style: PreviewStyle,
+    markers: &[(u32, u32)],
+) -> String {
+    if image.width() == 0 || image.height() == 0 || columns == 0 {
+        return String::new();
+    }
+
+    // Terminal cells are about twice as tall as they are wide, so each cell covers
+    // a block that is twice as tall as it is wide. In ANSI mode that block is split
+    // into the upper and lower half of the cell.
+    let columns = columns.min(image.width());
+    let block_width = image.width() as f64 / columns as f64;
+    let block_height = block_width * 2.0;
+    let rows = ((image.height() as f64 / block_height).ceil() as u32).max(1);
+
+    let mut output = String::new();
+    for row in 0..rows {
+        for column in 0..columns {
+            let left = column as f64 * block_width;
+            let top = row as f64 * block_height;
+            let marked = markers.iter().any(|(x, y)| {
+                let (x, y) = (*x as f64, *y as f64);
+                x >= left && x < left + block_width && y >= top && y < top + block_height
+            });
+            let checker = if (row + column) % 2 == 0 {
+                CHECKER_LIGHT
+            } else {
+                CHECKER_DARK
+            };
+
+            match style {
+                PreviewStyle::Ansi => {
+                    if marked {
+                        output.push_str("\x1b[38;2;255;255;255m\x1b[48;2;255;0;255mX");
+                        continue;
+                    }
+                    let half = block_height / 2.0;
+                    let upper = average(image, left, top, block_width, half, checker);
+                    let lower = average(image, left, top + half, block_width, half, checker);
+                    write!(
+                        output,
+                        "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                        upper[0], upper[1], upper[2], lower[0], lower[1], lower[2]
+                    )
+                    .unwrap();
+                }
+                PreviewStyle::Ascii => {
+                    if marked {
+                        output.push(MARKER_ASCII);
+                        continue;
+                    }
+                    let [r, g, b] = average(image, left, top, block_width, block_height, checker);
+                    let luma = 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64;
+                    let level = (luma / 255.0 * (ASCII_RAMP.len() - 1) as f64).round() as usize;
+                    output.push(ASCII_RAMP[level] as char);
+                }
+            }
+        }
+        if style == PreviewStyle::Ansi {
+            output.push_str("\x1b[0m");
+        }
+        output.push('\n');
+    }
+    output
+}
+
+/// Averages the pixels whose centers fall inside the block, composited over the
+/// checkerboard color. Returns RGB.
+fn average(
+    image: &CpuImage,
+    left: f64,
+    top: f64,
+    width: f64,
+    height: f64,
+    background: [f64; 3],
+) -> [u8; 3] {
+    let x_range = (left.round() as u32)..((left + width).round() as u32).min(image.width());
+    let y_range = (top.round() as u32)..((top + height).round() as u32).min(image.height());
+    let mut sum = [0.0; 3];
+    let mut count = 0;
+    for y in y_range {
+        for x in x_range.clone() {
+            let pixel = image.read_pixel(x, y).unwrap();
+            // Captures are premultiplied, so the background only needs to be scaled.
+            let coverage = 1.0 - pixel.A as f64 / 255.0;
+            sum[0] += pixel.R as f64 + background[0] * coverage;
+            sum[1] += pixel.G as f64 + background[1] * coverage;
+            sum[2] += pixel.B as f64 + background[2] * coverage;
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return [
+            background[0] as u8,
+            background[1] as u8,
+            background[2] as u8,
+        ];
+    }
+    sum.map(|channel| (channel / count as f64).round().min(255.0) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{common_colors, Color};
+
+    const WHITE: Color = Color {
+        A: 255,
+        R: 255,
+        G: 255,
+        B: 255,
+    };
+    const BLACK: Color = Color {
+        A: 255,
+        R: 0,
+        G: 0,
+        B: 0,
+    };
+
+    #[test]
+    fn ascii_preview_matches_snapshot() {
+        // White on the left, black on the right, transparent along the bottom.
+        let image = CpuImage::from_fn(8, 12, |x, y| match (x, y) {
+            (_, 8..) => common_colors::TRANSPARENT_BLACK,
+            (0..=3, _) => WHITE,
+            _ => BLACK,
+        });
+        assert_eq!(
+            render_preview(&image, 4, PreviewStyle::Ascii, &[(7, 7)]),
+            "@@  \n@@ X\n-:-:\n"
+        );
+    }
+
+    #[test]
+    fn ansi_preview_matches_snapshot() {
+        let image = CpuImage::from_fn(2, 2, |x, y| match (x, y) {
+            (0, 0) => common_colors::RED,
+            (0, 1) => common_colors::BLUE,
+            (1, 0) => common_colors::TRANSPARENT_BLACK,
+            _ => common_colors::GREEN,
+        });
+        assert_eq!(
+            render_preview(&image, 2, PreviewStyle::Ansi, &[]),
+            "\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m\u{2580}\
+             \x1b[38;2;64;64;64m\x1b[48;2;0;255;0m\u{2580}\x1b[0m\n"
+        );
+        assert_eq!(
+            render_preview(&image, 2, PreviewStyle::Ansi, &[(1, 1)]),
+            "\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m\u{2580}\
+             \x1b[38;2;255;255;255m\x1b[48;2;255;0;255mX\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn preview_is_never_wider_than_the_image() {
+        let image = CpuImage::from_fn(3, 6, |_, _| WHITE);
+        assert_eq!(
+            render_preview(&image, 80, PreviewStyle::Ascii, &[]),
+            "@@@\n@@@\n@@@\n"
+        );
+        assert_eq!(
+            render_preview(&CpuImage::new(0, 0), 80, PreviewStyle::Ascii, &[]),
+            ""
+        );
+    }
+}
@@ -0,0 +1,278 @@
+//! A file format for the frames a capture session delivered, so that a run can
+//! be analyzed again later, on any machine.
+//!
+//! A recording starts with the magic bytes `WGCREC`, followed by the format
+//! version as a `u16`. After that come the frames, one record each, until the
+//! end of the file. A record starts with its length in bytes as a `u32`, not
+//! counting the length itself, followed by:
+//!
+//! | Field                 | Type                                     |
+//! |-----------------------|------------------------------------------|
+//! | Pixel format          | `u32`, a `DirectXPixelFormat` value      |
+//! | Width, height         | `u32` each                               |
+//! | Content size          | `u32` width, `u32` height                |
+//! | System relative time  | `u64`, in 100ns ticks                    |
+//! | Dirty region count    | `u32`, `u32::MAX` if they weren't known  |
+//! | Dirty regions         | `i32` x, y, width, height for each       |
+//! | Pixels                | `width * height` pixels, row by row      |
+//!
+//! Everything is little endian. Readers skip whatever is left of a record
+//! after the pixels, which leaves room for new fields in later versions.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    time::Duration,
+};
+
+use super::{
+    capture::{CaptureFrame, PixelFormat},
+    cpu_image::CpuImage,
+    geometry::Rect,
+};
+
+const MAGIC: &[u8; 6] = b"WGCREC";
+const VERSION: u16 = 1;
+const NO_DIRTY_REGIONS: u32 = u32::MAX;
+
+/// Writes frames to a recording as they come in.
+pub struct FrameRecorder<W: Write> {
+    writer: W,
+}
+
+impl FrameRecorder<BufWriter<File>> {
+    /// Creates a recording at `path`, replacing any file that is already there.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> FrameRecorder<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        Ok(Self { writer })
+    }
+
+    /// Appends a frame. The recording is flushed after every frame, so that it
+    /// is complete up to the last frame even if the test crashes.
+    pub fn write_frame(&mut self, frame: &CaptureFrame) -> io::Result<()> {
+        let mut record = Vec::with_capacity(frame.image.bytes().len() + 64);
+        let format = PixelFormat::B8G8R8A8UIntNormalized;
+        record.extend_from_slice(&(format as u32).to_le_bytes());
+        record.extend_from_slice(&frame.image.width().to_le_bytes());
+        record.extend_from_slice(&frame.image.height().to_le_bytes());
+        record.extend_from_slice(&frame.content_size.0.to_le_bytes());
+        record.extend_from_slice(&frame.content_size.1.to_le_bytes());
+        let ticks = (frame.timestamp.as_nanos() / 100) as u64;
+        record.extend_from_slice(&ticks.to_le_bytes());
+        match &frame.dirty_regions {
+            Some(regions) => {
+                record.extend_from_slice(&(regions.len() as u32).to_le_bytes());
+                for region in regions {
+                    for value in [region.x, region.y, region.width, region.height] {
+                        record.extend_from_slice(&value.to_le_bytes());
+                    }
+                }
+            }
+            None => record.extend_from_slice(&NO_DIRTY_REGIONS.to_le_bytes()),
+        }
+        record.extend_from_slice(frame.image.bytes());
+
+        self.writer
+            .write_all(&(record.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&record)?;
+        self.writer.flush()
+    }
+}
+
+/// Reads every frame of the recording at `path`.
+pub fn read_recording<P: AsRef<Path>>(path: P) -> io::Result<Vec<CaptureFrame>> {
+    read_frames(BufReader::new(File::open(path)?))
+}
+
+/// Reads every frame of a recording. A record that is cut short, e.g. because
+/// the test crashed while writing it, ends the recording, so the frames before
+/// it are still returned.
+pub fn read_frames<R: Read>(mut reader: R) -> io::Result<Vec<CaptureFrame>> {
+    let mut magic = [0u8; 6];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a frame recording"));
+    }
+    let version = u16::from_le_bytes(read_array(&mut reader)?);
+    if version > VERSION {
+        return Err(invalid_data(format!(
+            "recording version {} is newer than the supported version {}",
+            version, VERSION
+        )));
+    }
+
+    let mut frames = Vec::new();
+    loop {
+        let mut length = [0u8; 4];
+        match reader.read_exact(&mut length) {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(error) => return Err(error),
+        }
+        let length = u32::from_le_bytes(length);
+        let mut record = Vec::new();
+        reader
+            .by_ref()
+            .take(length as u64)
+            .read_to_end(&mut record)?;
+        if record.len() < length as usize {
+            break;
+        }
+        frames.push(parse_record(&record)?);
+    }
+    Ok(frames)
+}
+
+fn parse_record(mut record: &[u8]) -> io::Result<CaptureFrame> {
+    let record = &mut record;
+    let format_id = read_u32(record)?;
+    let format = PixelFormat::from_id(format_id)
+        .filter(|format| *format == PixelFormat::B8G8R8A8UIntNormalized)
+        .ok_or_else(|| invalid_data(format!("unsupported pixel format {}", format_id)))?;
+    let width = read_u32(record)?;
+    let height = read_u32(record)?;
+    let content_size = (read_u32(record)?, read_u32(record)?);
+    let ticks = u64::from_le_bytes(read_array(record)?);
+    let dirty_regions = match read_u32(record)? {
+        NO_DIRTY_REGIONS => None,
+        count => {
+            let mut regions = Vec::with_capacity(count.min(1024) as usize);
+            for _ in 0..count {
+                let mut values = [0i32; 4];
+                for value in &mut values {
+                    *value = i32::from_le_bytes(read_array(record)?);
+                }
+                regions.push(Rect::new(values[0], values[1], values[2], values[3]));
+            }
+            Some(regions)
+        }
+    };
+    let size = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(format.bytes_per_pixel()))
+        .ok_or_else(|| invalid_data(format!("frame size {}x{} is too large", width, height)))?;
+    if record.len() < size {
+        return Err(invalid_data("frame record is shorter than its pixels"));
+    }
+    let nanos = ticks
+        .checked_mul(100)
+        .ok_or_else(|| invalid_data(format!("timestamp of {} ticks is too large", ticks)))?;
+    let image = CpuImage::from_bgra8(width, height, record[..size].to_vec());
+    Ok(CaptureFrame {
+        image,
+        timestamp: Duration::from_nanos(nanos),
+        content_size,
+        dirty_regions,
+    })
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(read_array(reader)?))
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::common_colors;
+
+    fn frame(width: u32, height: u32, timestamp: Duration) -> CaptureFrame {
+        CaptureFrame {
+            image: CpuImage::from_fn(width, height, |x, y| {
+                if (x + y) % 2 == 0 {
+                    common_colors::RED
+                } else {
+                    common_colors::BLUE
+                }
+            }),
+            timestamp,
+            content_size: (width + 1, height),
+            dirty_regions: None,
+        }
+    }
+
+    fn record(frames: &[CaptureFrame]) -> Vec<u8> {
+        let mut recorder = FrameRecorder::new(Vec::new()).unwrap();
+        for frame in frames {
+            recorder.write_frame(frame).unwrap();
+        }
+        recorder.writer
+    }
+
+    fn assert_same_frame(actual: &CaptureFrame, expected: &CaptureFrame) {
+        assert_eq!(actual.image.width(), expected.image.width());
+        assert_eq!(actual.image.height(), expected.image.height());
+        assert_eq!(actual.image.bytes(), expected.image.bytes());
+        assert_eq!(actual.timestamp, expected.timestamp);
+        assert_eq!(actual.content_size, expected.content_size);
+        assert_eq!(actual.dirty_regions, expected.dirty_regions);
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut with_regions = frame(3, 2, Duration::from_micros(16_700));
+        with_regions.dirty_regions = Some(vec![Rect::new(-1, 0, 2, 2), Rect::new(1, 1, 2, 1)]);
+        let frames = [frame(4, 4, Duration::ZERO), with_regions];
+        let read = read_frames(record(&frames).as_slice()).unwrap();
+        assert_eq!(read.len(), 2);
+        for (actual, expected) in read.iter().zip(&frames) {
+            assert_same_frame(actual, expected);
+        }
+    }
+
+    #[test]
+    fn truncated_record_keeps_the_frames_before_it() {
+        let frames = [
+            frame(4, 4, Duration::ZERO),
+            frame(4, 4, Duration::from_millis(16)),
+        ];
+        let bytes = record(&frames);
+        let read = read_frames(&bytes[..bytes.len() - 5]).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_same_frame(&read[0], &frames[0]);
+        // A length that was cut short as well.
+        let first_record_end = bytes.len() - (bytes.len() - 8) / 2;
+        let read = read_frames(&bytes[..first_record_end + 2]).unwrap();
+        assert_eq!(read.len(), 1);
+    }
+
+    #[test]
+    fn oversized_values_are_invalid_data() {
+        let mut bytes = record(&[frame(1, 1, Duration::ZERO)]);
+        // The timestamp comes after the length, format, size and content size.
+        let ticks_offset = 8 + 4 + 4 * 5;
+        bytes[ticks_offset..ticks_offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        let error = read_frames(bytes.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut bytes = record(&[frame(1, 1, Duration::ZERO)]);
+        for offset in [8 + 8, 8 + 12] {
+            bytes[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        }
+        let error = read_frames(bytes.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let error = read_frames(&b"PNG\0\0\0\0\0"[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
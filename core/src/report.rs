@@ -0,0 +1,558 @@
+use std::{fmt::Write as _, fs, io, path::Path};
+
+use super::assertion::{AssertionFailure, Tolerance, Value};
+
+/// The outcome of a single test, as it appears in the report.
+pub struct TestRecord {
+    pub name: String,
+    pub outcome: Outcome,
+    /// File names of the images saved for this test, relative to the report.
+    pub artifacts: Vec<String>,
+    pub metrics: Vec<Metric>,
+}
+
+pub enum Outcome {
+    Passed,
+    /// The test couldn't run on this machine, for the given reason.
+    Skipped(String),
+    Failed(Box<Failure>),
+}
+
+impl Outcome {
+    fn id(&self) -> &'static str {
+        match self {
+            Outcome::Passed => "passed",
+            Outcome::Skipped(_) => "skipped",
+            Outcome::Failed(_) => "failed",
+        }
+    }
+}
+
+/// Why a test failed.
+pub struct Failure {
+    /// The kind of error the test ran into, e.g. "setup" or "assertion".
+    pub kind: &'static str,
+    /// The message printed to the console, including any context the error carries.
+    pub message: String,
+    /// What the test was doing when it failed, outermost first.
+    pub context: Vec<String>,
+    /// The check that failed, or `None` if the test ran into an error instead.
+    pub assertion: Option<AssertionFailure>,
+    /// A summary of what the failing capture contains, if there was one.
+    pub image_summary: Option<String>,
+}
+
+/// A named measurement taken while a test ran, e.g. the SSIM of a golden comparison.
+pub struct Metric {
+    pub name: String,
+    pub value: f64,
+}
+
+#[derive(Default)]
+pub struct TestReport {
+    records: Vec<TestRecord>,
+}
+
+impl TestReport {
+    pub fn add(&mut self, record: TestRecord) {
+        self.records.push(record);
+    }
+
+    pub fn records(&self) -> &[TestRecord] {
+        &self.records
+    }
+
+    fn count(&self, id: &str) -> usize {
+        self.records
+            .iter()
+            .filter(|record| record.outcome.id() == id)
+            .count()
+    }
+
+    /// Writes a self-contained HTML page listing every test. Artifacts are
+    /// embedded by reference, so the report should live next to them.
+    pub fn write_html<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut html = String::new();
+        html.push_str(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>wgctest report</title>
+<style>
+body { font-family: sans-serif; }
+.passed { color: green; }
+.failed { color: red; }
+.skipped { color: gray; }
+th { text-align: left; padding-right: 1em; }
+.swatch { display: inline-block; width: 1em; height: 1em; border: 1px solid gray; vertical-align: middle; }
+img { border: 1px solid gray; margin: 4px; image-rendering: pixelated; }
+</style>
+</head>
+<body>
+"#,
+        );
+        writeln!(
+            html,
+            "<h1>wgctest report</h1>\n<p>{} of {} tests passed, {} skipped</p>",
+            self.count("passed"),
+            self.records.len(),
+            self.count("skipped")
+        )
+        .unwrap();
+        for record in &self.records {
+            let class = record.outcome.id();
+            writeln!(
+                html,
+                "<h2>{} - <span class=\"{}\">{}</span></h2>",
+                escape_html(&record.name),
+                class,
+                class.to_uppercase()
+            )
+            .unwrap();
+            match &record.outcome {
+                Outcome::Passed => {}
+                Outcome::Skipped(reason) => {
+                    writeln!(html, "<p>{}</p>", escape_html(reason)).unwrap()
+                }
+                Outcome::Failed(failure) => {
+                    if !failure.context.is_empty() {
+                        writeln!(
+                            html,
+                            "<p>While {}:</p>",
+                            escape_html(&failure.context.join(", while "))
+                        )
+                        .unwrap();
+                    }
+                    match &failure.assertion {
+                        Some(assertion) => write_assertion_html(&mut html, assertion),
+                        None => {
+                            writeln!(html, "<pre>{}</pre>", escape_html(&failure.message)).unwrap()
+                        }
+                    }
+                    if let Some(summary) = &failure.image_summary {
+                        writeln!(html, "<pre>{}</pre>", escape_html(summary)).unwrap();
+                    }
+                }
+            }
+            if !record.metrics.is_empty() {
+                html.push_str("<table>\n");
+                for metric in &record.metrics {
+                    writeln!(
+                        html,
+                        "<tr><td>{}</td><td>{}</td></tr>",
+                        escape_html(&metric.name),
+                        metric.value
+                    )
+                    .unwrap();
+                }
+                html.push_str("</table>\n");
+            }
+            for artifact in &record.artifacts {
+                let artifact = escape_html(artifact);
+                writeln!(
+                    html,
+                    "<figure><img src=\"{0}\"><figcaption>{0}</figcaption></figure>",
+                    artifact
+                )
+                .unwrap();
+            }
+        }
+        html.push_str("</body>\n</html>\n");
+
+        fs::write(path, html)
+    }
+
+    /// Writes every test as JSON, for tools that want to dig into failures.
+    /// Numbers that JSON can't represent, like an infinite PSNR, are written as null.
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut json = String::new();
+        writeln!(
+            json,
+            "{{\n  \"passed\": {},\n  \"skipped\": {},\n  \"total\": {},\n  \"tests\": [",
+            self.count("passed"),
+            self.count("skipped"),
+            self.records.len()
+        )
+        .unwrap();
+        for (i, record) in self.records.iter().enumerate() {
+            let mut fields = vec![
+                format!("\"name\": {}", json_string(&record.name)),
+                format!("\"status\": \"{}\"", record.outcome.id()),
+            ];
+            if let Outcome::Skipped(reason) = &record.outcome {
+                fields.push(format!("\"reason\": {}", json_string(reason)));
+            }
+            if let Outcome::Failed(failure) = &record.outcome {
+                fields.push(format!("\"kind\": \"{}\"", failure.kind));
+                fields.push(format!("\"message\": {}", json_string(&failure.message)));
+                fields.push(format!(
+                    "\"context\": [{}]",
+                    failure
+                        .context
+                        .iter()
+                        .map(|context| json_string(context))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+                if let Some(assertion) = &failure.assertion {
+                    fields.push(format!("\"assertion\": {}", assertion_json(assertion)));
+                }
+                if let Some(summary) = &failure.image_summary {
+                    fields.push(format!("\"image_summary\": {}", json_string(summary)));
+                }
+            }
+            fields.push(format!(
+                "\"artifacts\": [{}]",
+                record
+                    .artifacts
+                    .iter()
+                    .map(|artifact| json_string(artifact))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+            fields.push(format!(
+                "\"metrics\": {{{}}}",
+                record
+                    .metrics
+                    .iter()
+                    .map(|metric| format!(
+                        "{}: {}",
+                        json_string(&metric.name),
+                        json_number(metric.value)
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+            write!(json, "    {{\n      {}\n    }}", fields.join(",\n      ")).unwrap();
+            json.push_str(if i + 1 < self.records.len() {
+                ",\n"
+            } else {
+                "\n"
+            });
+        }
+        json.push_str("  ]\n}\n");
+
+        fs::write(path, json)
+    }
+
+    /// Writes the results in the JUnit XML format that CI systems understand.
+    /// The fields of a failed assertion are attached as test case properties.
+    pub fn write_junit<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        // JUnit tells failed checks apart from tests that couldn't run because of an error.
+        let failures = self
+            .records
+            .iter()
+            .filter(|record| match &record.outcome {
+                Outcome::Failed(failure) => failure.assertion.is_some(),
+                _ => false,
+            })
+            .count();
+        let errors = self.count("failed") - failures;
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        writeln!(
+            xml,
+            "<testsuites tests=\"{0}\" failures=\"{1}\" errors=\"{2}\" skipped=\"{3}\">\n<testsuite name=\"wgctest\" tests=\"{0}\" failures=\"{1}\" errors=\"{2}\" skipped=\"{3}\">",
+            self.records.len(),
+            failures,
+            errors,
+            self.count("skipped")
+        )
+        .unwrap();
+        for record in &self.records {
+            writeln!(
+                xml,
+                "<testcase name=\"{}\" classname=\"wgctest\">",
+                escape_html(&record.name)
+            )
+            .unwrap();
+
+            let mut properties: Vec<(String, String)> = record
+                .metrics
+                .iter()
+                .map(|metric| (metric.name.clone(), metric.value.to_string()))
+                .collect();
+            if let Outcome::Failed(failure) = &record.outcome {
+                properties.push(("error.kind".to_owned(), failure.kind.to_owned()));
+                for (i, context) in failure.context.iter().enumerate() {
+                    properties.push((format!("error.context.{}", i), context.clone()));
+                }
+                if let Some(assertion) = &failure.assertion {
+                    properties.extend(assertion_properties(assertion));
+                }
+            }
+            if !properties.is_empty() {
+                xml.push_str("<properties>\n");
+                for (name, value) in properties {
+                    writeln!(
+                        xml,
+                        "<property name=\"{}\" value=\"{}\"/>",
+                        escape_html(&name),
+                        escape_html(&value)
+                    )
+                    .unwrap();
+                }
+                xml.push_str("</properties>\n");
+            }
+            match &record.outcome {
+                Outcome::Passed => {}
+                Outcome::Skipped(reason) => {
+                    writeln!(xml, "<skipped message=\"{}\"/>", escape_html(reason)).unwrap();
+                }
+                Outcome::Failed(failure) => match &failure.assertion {
+                    Some(assertion) => {
+                        writeln!(
+                            xml,
+                            "<failure type=\"{}\" message=\"{}\">{}</failure>",
+                            assertion.kind.id(),
+                            escape_html(&assertion.headline()),
+                            escape_html(&failure.message)
+                        )
+                        .unwrap();
+                    }
+                    None => {
+                        let first_line = failure.message.lines().next().unwrap_or_default();
+                        writeln!(
+                            xml,
+                            "<error type=\"{}\" message=\"{}\">{}</error>",
+                            failure.kind,
+                            escape_html(first_line),
+                            escape_html(&failure.message)
+                        )
+                        .unwrap();
+                    }
+                },
+            }
+            if !record.artifacts.is_empty() {
+                xml.push_str("<system-out>");
+                for artifact in &record.artifacts {
+                    writeln!(xml, "[[ATTACHMENT|{}]]", escape_html(artifact)).unwrap();
+                }
+                xml.push_str("</system-out>\n");
+            }
+            xml.push_str("</testcase>\n");
+        }
+        xml.push_str("</testsuite>\n</testsuites>\n");
+
+        fs::write(path, xml)
+    }
+}
+
+fn write_assertion_html(html: &mut String, assertion: &AssertionFailure) {
+    writeln!(
+        html,
+        "<p>{}</p>\n<table>",
+        escape_html(&assertion.headline())
+    )
+    .unwrap();
+    let mut row = |label: &str, value: String| {
+        writeln!(
+            html,
+            "<tr><th>{}</th><td>{}</td></tr>",
+            escape_html(label),
+            value
+        )
+        .unwrap();
+    };
+    if let Some(region) = &assertion.region {
+        row(
+            "Region",
+            format!(
+                "( X: {}, Y: {}, Width: {}, Height: {} )",
+                region.x, region.y, region.width, region.height
+            ),
+        );
+    }
+    if let Some((x, y)) = assertion.location {
+        row("Location", format!("({}, {})", x, y));
+    }
+    if let Some(actual) = &assertion.actual {
+        row("Actual", value_html(actual));
+    }
+    if let Some(expected) = &assertion.expected {
+        row("Expected", value_html(expected));
+    }
+    if let Some(tolerance) = &assertion.tolerance {
+        row("Tolerance", escape_html(&tolerance.to_string()));
+    }
+    for detail in &assertion.details {
+        row(detail.label, value_html(&detail.value));
+    }
+    html.push_str("</table>\n");
+    for note in &assertion.notes {
+        writeln!(html, "<pre>{}</pre>", escape_html(note)).unwrap();
+    }
+}
+
+/// Formats a value for the HTML report. Colors get a swatch next to them.
+fn value_html(value: &Value) -> String {
+    match value {
+        Value::Color(color) => format!(
+            "<span class=\"swatch\" style=\"background: rgba({}, {}, {}, {:.3})\"></span> {}",
+            color.R,
+            color.G,
+            color.B,
+            color.A as f64 / 255.0,
+            escape_html(&value.to_string())
+        ),
+        _ => escape_html(&value.to_string()),
+    }
+}
+
+fn assertion_json(assertion: &AssertionFailure) -> String {
+    let mut fields = vec![
+        format!("\"kind\": \"{}\"", assertion.kind.id()),
+        format!("\"headline\": {}", json_string(&assertion.headline())),
+    ];
+    if let Some(subject) = &assertion.subject {
+        fields.push(format!("\"subject\": {}", json_string(subject)));
+    }
+    if let Some((x, y)) = assertion.location {
+        fields.push(format!("\"location\": {{\"x\": {}, \"y\": {}}}", x, y));
+    }
+    if let Some(region) = &assertion.region {
+        fields.push(format!(
+            "\"region\": {{\"x\": {}, \"y\": {}, \"width\": {}, \"height\": {}}}",
+            region.x, region.y, region.width, region.height
+        ));
+    }
+    if let Some(expected) = &assertion.expected {
+        fields.push(format!("\"expected\": {}", value_json(expected)));
+    }
+    if let Some(actual) = &assertion.actual {
+        fields.push(format!("\"actual\": {}", value_json(actual)));
+    }
+    if let Some(tolerance) = &assertion.tolerance {
+        let (kind, value) = match tolerance {
+            Tolerance::PerPixel(tolerance) => ("per_pixel", *tolerance as f64),
+            Tolerance::Ssim(minimum) => ("ssim", *minimum),
+            Tolerance::MsSsim(minimum) => ("ms_ssim", *minimum),
+            Tolerance::Psnr(minimum) => ("psnr", *minimum),
+        };
+        fields.push(format!(
+            "\"tolerance\": {{\"kind\": \"{}\", \"value\": {}}}",
+            kind,
+            json_number(value)
+        ));
+    }
+    fields.push(format!(
+        "\"details\": {{{}}}",
+        assertion
+            .details
+            .iter()
+            .map(|detail| format!(
+                "{}: {}",
+                json_string(detail.label),
+                value_json(&detail.value)
+            ))
+            .collect::<Vec<_>>()
+            .join(", ")
+    ));
+    fields.push(format!(
+        "\"notes\": [{}]",
+        assertion
+            .notes
+            .iter()
+            .map(|note| json_string(note))
+            .collect::<Vec<_>>()
+            .join(", ")
+    ));
+    format!("{{{}}}", fields.join(", "))
+}
+
+fn value_json(value: &Value) -> String {
+    match value {
+        Value::Color(color) => format!(
+            "{{\"b\": {}, \"g\": {}, \"r\": {}, \"a\": {}}}",
+            color.B, color.G, color.R, color.A
+        ),
+        Value::Size { width, height } => {
+            format!("{{\"width\": {}, \"height\": {}}}", width, height)
+        }
+        Value::Point { x, y } => {
+            format!("{{\"x\": {}, \"y\": {}}}", json_number(*x), json_number(*y))
+        }
+        Value::Count(count) => count.to_string(),
+        Value::Number(number) => json_number(*number),
+        Value::Text(text) => json_string(text),
+    }
+}
+
+/// Flattens an assertion into name/value pairs, e.g. "expected" = "( B: 0, ... )".
+fn assertion_properties(assertion: &AssertionFailure) -> Vec<(String, String)> {
+    let mut properties = vec![("assertion.kind".to_owned(), assertion.kind.id().to_owned())];
+    if let Some(subject) = &assertion.subject {
+        properties.push(("assertion.subject".to_owned(), subject.clone()));
+    }
+    if let Some((x, y)) = assertion.location {
+        properties.push(("assertion.location".to_owned(), format!("{},{}", x, y)));
+    }
+    if let Some(region) = &assertion.region {
+        properties.push((
+            "assertion.region".to_owned(),
+            format!(
+                "{},{},{},{}",
+                region.x, region.y, region.width, region.height
+            ),
+        ));
+    }
+    if let Some(expected) = &assertion.expected {
+        properties.push(("assertion.expected".to_owned(), expected.to_string()));
+    }
+    if let Some(actual) = &assertion.actual {
+        properties.push(("assertion.actual".to_owned(), actual.to_string()));
+    }
+    if let Some(tolerance) = &assertion.tolerance {
+        properties.push(("assertion.tolerance".to_owned(), tolerance.to_string()));
+    }
+    for detail in &assertion.details {
+        properties.push((
+            format!("assertion.{}", detail.label),
+            detail.value.to_string(),
+        ));
+    }
+    properties
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn json_number(number: f64) -> String {
+    if number.is_finite() {
+        number.to_string()
+    } else {
+        "null".to_owned()
+    }
+}
+
+/// Escapes text for use in HTML or XML, both in element content and in
+/// double-quoted attributes.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,225 @@
+//! Keeping a frame pool in step with the size of the item it captures. A frame
+//! pool hands out buffers of the size it was created with, so when the item
+//! grows, frames are cropped until the pool is recreated with the new size, and
+//! frames that were already in flight keep the old size.
+
+use super::{
+    assertion::{AssertionFailure, AssertionKind, Capture, Detail, Value},
+    error::{AssertionError, TestError},
+};
+
+/// The captured item changed size and the frame pool was recreated to match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizeChange {
+    /// The index of the frame that showed the new size, counting from zero.
+    pub frame: u32,
+    pub from: (u32, u32),
+    pub to: (u32, u32),
+}
+
+/// What a frame says about the frame pool's size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameSize {
+    /// The frame fits the pool and the item.
+    Current,
+    /// The item changed size, the pool has to be recreated with `to`.
+    Resized(SizeChange),
+    /// The frame was allocated before the pool was last recreated, so its size
+    /// is out of date.
+    Stale {
+        frame_size: (u32, u32),
+        pool_size: (u32, u32),
+    },
+}
+
+/// Follows the size of a frame pool across the frames it delivers, and tells
+/// when it needs to be recreated.
+#[derive(Clone, Debug)]
+pub struct ResizeTracker {
+    pool_size: (u32, u32),
+    frames: u32,
+    stale_frames: u32,
+    size_changes: Vec<SizeChange>,
+}
+
+impl ResizeTracker {
+    /// Starts tracking a pool created with `pool_size`.
+    pub fn new(pool_size: (u32, u32)) -> Self {
+        Self {
+            pool_size,
+            frames: 0,
+            stale_frames: 0,
+            size_changes: Vec::new(),
+        }
+    }
+
+    /// Looks at the next frame. `frame_size` is the size of the frame's buffer,
+    /// `content_size` the size of the item when the frame was produced. On
+    /// `FrameSize::Resized` the pool is assumed to be recreated with the new
+    /// size before the next frame.
+    pub fn on_frame(&mut self, frame_size: (u32, u32), content_size: (u32, u32)) -> FrameSize {
+        let frame = self.frames;
+        self.frames += 1;
+        if frame_size != self.pool_size {
+            self.stale_frames += 1;
+            return FrameSize::Stale {
+                frame_size,
+                pool_size: self.pool_size,
+            };
+        }
+        if content_size == self.pool_size {
+            return FrameSize::Current;
+        }
+        let change = SizeChange {
+            frame,
+            from: self.pool_size,
+            to: content_size,
+        };
+        self.pool_size = content_size;
+        self.size_changes.push(change);
+        FrameSize::Resized(change)
+    }
+
+    /// The size the pool has, or will have once it has been recreated.
+    pub fn pool_size(&self) -> (u32, u32) {
+        self.pool_size
+    }
+
+    /// Every size change so far, oldest first.
+    pub fn size_changes(&self) -> &[SizeChange] {
+        &self.size_changes
+    }
+
+    /// How many frames had an out of date size.
+    pub fn stale_frames(&self) -> u32 {
+        self.stale_frames
+    }
+}
+
+/// The error for a frame whose size was out of date, for sessions that were
+/// asked to fail on them.
+pub fn stale_frame_error(
+    frame_size: (u32, u32),
+    pool_size: (u32, u32),
+    capture: Option<Capture>,
+) -> TestError {
+    let size = |(width, height)| Value::Size { width, height };
+    TestError::Assertion(Box::new(AssertionError {
+        failure: AssertionFailure {
+            subject: Some("frames match the frame pool's size".to_owned()),
+            details: vec![
+                Detail {
+                    label: "Frame size",
+                    value: size(frame_size),
+                },
+                Detail {
+                    label: "Frame pool size",
+                    value: size(pool_size),
+                },
+            ],
+            ..AssertionFailure::new(AssertionKind::StaleFrame)
+        },
+        capture,
+        artifacts: Vec::new(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        capture::{CaptureBackend, CaptureFrame, CaptureOptions, CaptureSession},
+        color::common_colors,
+        cpu_image::CpuImage,
+        fake_capture::FakeBackend,
+    };
+
+    #[test]
+    fn tracker_follows_the_pool_size() {
+        let mut tracker = ResizeTracker::new((4, 4));
+        assert_eq!(tracker.on_frame((4, 4), (4, 4)), FrameSize::Current);
+        let change = SizeChange {
+            frame: 1,
+            from: (4, 4),
+            to: (8, 6),
+        };
+        assert_eq!(tracker.on_frame((4, 4), (8, 6)), FrameSize::Resized(change));
+        assert_eq!(tracker.pool_size(), (8, 6));
+        assert_eq!(
+            tracker.on_frame((4, 4), (8, 6)),
+            FrameSize::Stale {
+                frame_size: (4, 4),
+                pool_size: (8, 6)
+            }
+        );
+        assert_eq!(tracker.on_frame((8, 6), (8, 6)), FrameSize::Current);
+        assert_eq!(tracker.size_changes(), [change]);
+        assert_eq!(tracker.stale_frames(), 1);
+    }
+
+    /// A 4 x 4 item that grows to 8 x 6: the frame showing the new size still
+    /// comes in the old buffer, and so does the one after it, which was already
+    /// in flight when the pool was recreated.
+    fn growing_item() -> FakeBackend {
+        let frame = |size: (u32, u32), content_size, timestamp| CaptureFrame {
+            image: CpuImage::from_fn(size.0, size.1, |_, _| common_colors::RED),
+            timestamp: Duration::from_millis(timestamp),
+            content_size,
+            dirty_regions: None,
+        };
+        FakeBackend::new()
+            .with_captured_frame(frame((4, 4), (4, 4), 0))
+            .with_captured_frame(frame((4, 4), (8, 6), 10))
+            .with_captured_frame(frame((4, 4), (8, 6), 20))
+            .with_captured_frame(frame((8, 6), (8, 6), 30))
+    }
+
+    #[test]
+    fn sessions_hand_out_stale_frames_by_default() {
+        let mut session = growing_item()
+            .start(&(), CaptureOptions::default())
+            .unwrap();
+        let timeout = Duration::from_millis(100);
+        let sizes: Vec<_> = (0..4)
+            .map(|_| {
+                let frame = session.next_frame(timeout).unwrap();
+                (frame.image.width(), frame.image.height())
+            })
+            .collect();
+        assert_eq!(sizes, [(4, 4), (4, 4), (4, 4), (8, 6)]);
+        assert_eq!(session.content_size().unwrap(), (8, 6));
+        assert_eq!(
+            session.size_changes(),
+            [SizeChange {
+                frame: 1,
+                from: (4, 4),
+                to: (8, 6)
+            }]
+        );
+    }
+
+    #[test]
+    fn sessions_can_fail_on_stale_frames() {
+        let options = CaptureOptions::default().with_fail_on_stale_frames(true);
+        let mut session = growing_item().start(&(), options).unwrap();
+        let timeout = Duration::from_millis(100);
+        session.next_frame(timeout).unwrap();
+        // The frame that shows the resize fits the pool it came from.
+        session.next_frame(timeout).unwrap();
+
+        let TestError::Assertion(error) = session.next_frame(timeout).unwrap_err() else {
+            panic!("expected an assertion");
+        };
+        assert_eq!(error.failure.kind, AssertionKind::StaleFrame);
+        let Some(Capture::Image(image)) = &error.capture else {
+            panic!("expected the stale frame as the capture");
+        };
+        assert_eq!((image.width(), image.height()), (4, 4));
+
+        // The session keeps going after a stale frame.
+        let frame = session.next_frame(timeout).unwrap();
+        assert_eq!((frame.image.width(), frame.image.height()), (8, 6));
+    }
+}
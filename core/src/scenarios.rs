@@ -0,0 +1,391 @@
+//! Checks written against `CaptureSession` instead of a particular backend, so
+//! that they run the same on a live capture, on a scripted `FakeBackend` and on
+//! a replayed recording.
+
+use std::{path::Path, time::Duration};
+
+use super::{
+    capture::{center_is, CaptureBackend, CaptureOptions, CaptureSession},
+    color::{common_colors, Color},
+    dirty_regions::DirtyRegionVerifier,
+    error::{Context, TestError, TestResult},
+    fake_capture::FakeBackend,
+    test_runner::TestContext,
+};
+
+/// How long replays wait for each frame on the recording's clock, the same as
+/// live captures do.
+const REPLAY_FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A change a test makes to the captured item, and what the capture should show
+/// once it has happened.
+#[derive(Clone, Copy, Debug)]
+pub struct Transition {
+    /// What the test does, e.g. "switching to fullscreen". Failures get it as
+    /// context.
+    pub action: &'static str,
+    /// What the capture waits for, e.g. "the center to turn green".
+    pub condition: &'static str,
+    pub center: Color,
+}
+
+/// A window that is red once it's shown, green in fullscreen and blue once it's
+/// back to windowed.
+pub const FULLSCREEN_TRANSITIONS: [Transition; 3] = [
+    Transition {
+        action: "showing the window",
+        condition: "the center to turn red",
+        center: common_colors::RED,
+    },
+    Transition {
+        action: "switching to fullscreen",
+        condition: "the center to turn green",
+        center: common_colors::GREEN,
+    },
+    Transition {
+        action: "switching back to windowed",
+        condition: "the center to turn blue",
+        center: common_colors::BLUE,
+    },
+];
+
+/// Makes each transition happen with `perform`, which gets its index, and waits
+/// up to `timeout` for the center of the capture to show its color. Afterwards
+/// the frame rate and the number of size changes are recorded as metrics, and
+/// the frame timestamps have to strictly increase.
+///
+/// Frames that carry dirty regions are checked against the frame before them
+/// along the way. The first one that misses a change fails the check once the
+/// transitions are done, and the missed pixels and the over-reporting are
+/// recorded as metrics.
+pub fn expect_transitions<S, F>(
+    context: &TestContext,
+    session: &mut S,
+    transitions: &[Transition],
+    timeout: Duration,
+    mut perform: F,
+) -> TestResult<()>
+where
+    S: CaptureSession,
+    F: FnMut(usize) -> TestResult<()>,
+{
+    let mut dirty_regions = DirtyRegionVerifier::new(0);
+    let mut missed_update = None;
+    for (index, transition) in transitions.iter().enumerate() {
+        perform(index).context(transition.action)?;
+        let center_matches = center_is(transition.center, 0);
+        session
+            .wait_for_frame(
+                transition.condition,
+                |frame| {
+                    if frame.dirty_regions.is_some() {
+                        if let Err(error) = dirty_regions.check(frame) {
+                            missed_update.get_or_insert(error);
+                        }
+                    }
+                    center_matches(frame)
+                },
+                timeout,
+            )
+            .context(transition.action)?;
+    }
+    dirty_regions.record_metrics(context);
+
+    // The item only changes a few times, so long gaps between frames are fine.
+    // Frames should never share a timestamp or go back in time, though.
+    let timing = session.timing().analyze(timeout);
+    context.record_metric("frames.effective_fps".to_owned(), timing.effective_fps);
+    // The item resizes with each transition, which the capture should follow.
+    context.record_metric(
+        "capture.size_changes".to_owned(),
+        session.size_changes().len() as f64,
+    );
+    if let Some(error) = missed_update {
+        return Err(error).context("checking the dirty regions");
+    }
+    timing.expect_strictly_increasing()
+}
+
+/// Plays the recording at `path` back through the checks of the test that made
+/// it, which is named by the file, e.g. `fullscreen_transition_test.wgcrec`.
+/// Recordings end with their last frame, so checks on the item closing are left
+/// out. Recordings of tests without checks to replay are skipped.
+pub fn replay_recording(context: &TestContext, path: &Path) -> TestResult<()> {
+    let test_name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let backend = FakeBackend::from_recording(path)
+        .map_err(TestError::from)
+        .context("reading the recording")?;
+    let mut session = backend.start(&(), CaptureOptions::default())?;
+    match test_name.as_ref() {
+        "fullscreen_transition_test" => expect_transitions(
+            context,
+            &mut session,
+            &FULLSCREEN_TRANSITIONS,
+            REPLAY_FRAME_TIMEOUT,
+            |_| Ok(()),
+        ),
+        _ => Err(TestError::Skipped(format!(
+            "there are no checks to replay for {}",
+            test_name
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assertion::AssertionKind, capture::CaptureFrame, cpu_image::CpuImage, geometry::Rect,
+        recording::FrameRecorder, report::Outcome, run_test, test_runner::test_output_dir,
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn frame(color: Color, size: (u32, u32)) -> CpuImage {
+        CpuImage::from_fn(size.0, size.1, |_, _| color)
+    }
+
+    /// The capture side of `fullscreen_transition_test`, for any backend.
+    fn fullscreen_transition_test<B: CaptureBackend>(
+        context: &TestContext,
+        backend: &B,
+        item: &B::Item,
+    ) -> TestResult<()> {
+        let mut session = backend.start(item, CaptureOptions::default())?;
+        expect_transitions(
+            context,
+            &mut session,
+            &FULLSCREEN_TRANSITIONS,
+            TIMEOUT,
+            |_| Ok(()),
+        )?;
+        session
+            .wait_for_item_closed(TIMEOUT)
+            .context("closing the window")
+    }
+
+    /// A frame produced right after the item resized to `content_size`, which
+    /// still comes in a buffer of the old size.
+    fn resized_frame(
+        color: Color,
+        old_size: (u32, u32),
+        content_size: (u32, u32),
+        timestamp: Duration,
+    ) -> CaptureFrame {
+        CaptureFrame {
+            image: frame(color, old_size),
+            timestamp,
+            content_size,
+            dirty_regions: None,
+        }
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn scripted_transitions_pass_through_the_runner() -> TestResult<()> {
+        let backend = FakeBackend::new()
+            .with_frame(frame(common_colors::RED, (80, 60)), ms(0))
+            .with_frame(frame(common_colors::RED, (80, 60)), ms(16))
+            .with_captured_frame(resized_frame(
+                common_colors::GREEN,
+                (80, 60),
+                (160, 120),
+                ms(100),
+            ))
+            .with_frame(frame(common_colors::GREEN, (160, 120)), ms(116))
+            .with_captured_frame(resized_frame(
+                common_colors::BLUE,
+                (160, 120),
+                (80, 60),
+                ms(200),
+            ))
+            .with_frame(frame(common_colors::BLUE, (80, 60)), ms(216))
+            .with_item_closed_at(ms(300));
+        let context = TestContext::new(test_output_dir("scripted-transitions"));
+        run_test!(context, fullscreen_transition_test, &context, &backend, &());
+
+        let report = context.report();
+        let record = &report.records()[0];
+        assert_eq!(record.name, "fullscreen_transition_test");
+        assert!(matches!(record.outcome, Outcome::Passed));
+        let metric = |name: &str| {
+            record
+                .metrics
+                .iter()
+                .find(|metric| metric.name == name)
+                .unwrap()
+                .value
+        };
+        assert_eq!(metric("capture.size_changes"), 2.0);
+        // Five frames up to the blue one at 200ms.
+        assert_eq!(metric("frames.effective_fps"), 20.0);
+        Ok(())
+    }
+
+    #[test]
+    fn recordings_replay_through_the_same_checks() {
+        let output_dir = test_output_dir("replay");
+        let path = output_dir.join("fullscreen_transition_test.wgcrec");
+        let mut recorder = FrameRecorder::create(&path).unwrap();
+        let backend = FakeBackend::new()
+            .with_frame(frame(common_colors::RED, (8, 8)), ms(1000))
+            .with_frame(frame(common_colors::GREEN, (8, 8)), ms(1100))
+            .with_frame(frame(common_colors::BLUE, (8, 8)), ms(1200));
+        let mut session = backend.start(&(), CaptureOptions::default()).unwrap();
+        while let Ok(frame) = session.next_frame(TIMEOUT) {
+            recorder.write_frame(&frame).unwrap();
+        }
+        drop(recorder);
+
+        let context = TestContext::new(output_dir);
+        replay_recording(&context, &path).unwrap();
+
+        let other = path.with_file_name("basic_window_test.wgcrec");
+        std::fs::copy(&path, &other).unwrap();
+        let error = replay_recording(&context, &other).unwrap_err();
+        assert_eq!(
+            error.skip_reason(),
+            Some("there are no checks to replay for basic_window_test")
+        );
+    }
+
+    /// The value of the metric `name` recorded for the only test in the report.
+    fn metric(context: &TestContext, name: &str) -> f64 {
+        context.report().records()[0]
+            .metrics
+            .iter()
+            .find(|metric| metric.name == name)
+            .unwrap()
+            .value
+    }
+
+    /// An 8 x 8 frame of `color` that reports `regions` as dirty.
+    fn frame_with_regions(color: Color, regions: Vec<Rect>, timestamp: Duration) -> CaptureFrame {
+        CaptureFrame {
+            image: frame(color, (8, 8)),
+            timestamp,
+            content_size: (8, 8),
+            dirty_regions: Some(regions),
+        }
+    }
+
+    #[test]
+    fn dirty_regions_are_checked_along_the_way() {
+        let everything = Rect::new(0, 0, 8, 8);
+        let backend = FakeBackend::new()
+            .with_captured_frame(frame_with_regions(
+                common_colors::RED,
+                vec![everything],
+                ms(0),
+            ))
+            .with_captured_frame(frame_with_regions(
+                common_colors::GREEN,
+                vec![everything, Rect::new(0, 0, 16, 16)],
+                ms(10),
+            ))
+            .with_captured_frame(frame_with_regions(
+                common_colors::BLUE,
+                vec![everything],
+                ms(20),
+            ));
+        let context = TestContext::new(test_output_dir("dirty-regions-checked"));
+        let mut session = backend.start(&(), CaptureOptions::default()).unwrap();
+        let result = expect_transitions(
+            &context,
+            &mut session,
+            &FULLSCREEN_TRANSITIONS,
+            TIMEOUT,
+            |_| Ok(()),
+        );
+        assert!(result.is_ok());
+        context.record_result("dirty_regions", result);
+
+        assert_eq!(metric(&context, "dirty_regions.frames_checked"), 2.0);
+        assert_eq!(metric(&context, "dirty_regions.missed_pixels"), 0.0);
+        assert_eq!(metric(&context, "dirty_regions.over_reporting"), 0.0);
+    }
+
+    #[test]
+    fn missed_updates_fail_replays() {
+        let output_dir = test_output_dir("dirty-regions-missed");
+        let path = output_dir.join("fullscreen_transition_test.wgcrec");
+        let mut recorder = FrameRecorder::create(&path).unwrap();
+        for frame in [
+            frame_with_regions(common_colors::RED, vec![Rect::new(0, 0, 8, 8)], ms(0)),
+            // Only the top half is reported, so the bottom half's update is missed.
+            frame_with_regions(common_colors::GREEN, vec![Rect::new(0, 0, 8, 4)], ms(10)),
+            frame_with_regions(common_colors::BLUE, vec![Rect::new(0, 0, 8, 8)], ms(20)),
+        ] {
+            recorder.write_frame(&frame).unwrap();
+        }
+        drop(recorder);
+
+        let context = TestContext::new(output_dir);
+        let error = replay_recording(&context, &path).unwrap_err();
+        let TestError::Context {
+            context: step,
+            source,
+        } = &error
+        else {
+            panic!("expected context, got {}", error);
+        };
+        assert_eq!(step, "checking the dirty regions");
+        let TestError::Assertion(assertion) = source.as_ref() else {
+            panic!("expected an assertion, got {}", source);
+        };
+        assert_eq!(assertion.failure.kind, AssertionKind::DirtyRegions);
+        context.record_result("fullscreen_transition_test", Err(error));
+        assert_eq!(metric(&context, "dirty_regions.missed_pixels"), 32.0);
+    }
+
+    #[test]
+    fn missing_transition_fails_with_context() {
+        let backend = FakeBackend::new()
+            .with_frame(frame(common_colors::RED, (8, 8)), ms(0))
+            .with_frame(frame(common_colors::RED, (8, 8)), ms(16));
+        let context = TestContext::new(test_output_dir("missing-transition"));
+        let mut session = backend.start(&(), CaptureOptions::default()).unwrap();
+        let error = expect_transitions(
+            &context,
+            &mut session,
+            &FULLSCREEN_TRANSITIONS,
+            TIMEOUT,
+            |_| Ok(()),
+        )
+        .unwrap_err();
+        let TestError::Context { context, .. } = &error else {
+            panic!("expected context, got {}", error);
+        };
+        assert_eq!(context, "switching to fullscreen");
+        assert_eq!(error.kind(), "assertion");
+        // The whole timeout passed on the virtual clock, without sleeping.
+        assert_eq!(session.now(), TIMEOUT);
+    }
+
+    #[test]
+    fn duplicate_timestamps_fail_the_timing_check() {
+        let backend = FakeBackend::new()
+            .with_frame(frame(common_colors::RED, (8, 8)), ms(0))
+            .with_frame(frame(common_colors::GREEN, (8, 8)), ms(10))
+            .with_frame(frame(common_colors::BLUE, (8, 8)), ms(10));
+        let context = TestContext::new(test_output_dir("duplicate-timestamps"));
+        let mut session = backend.start(&(), CaptureOptions::default()).unwrap();
+        let error = expect_transitions(
+            &context,
+            &mut session,
+            &FULLSCREEN_TRANSITIONS,
+            TIMEOUT,
+            |_| Ok(()),
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Frame timing check failed for \"timestamps strictly increase\"!"));
+    }
+}
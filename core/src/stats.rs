@@ -0,0 +1,221 @@
+use std::{collections::HashMap, fmt::Write as _};
+
+use super::{color::Color, cpu_image::CpuImage};
+
+const CHANNEL_NAMES: [&str; 4] = ["B", "G", "R", "A"];
+const TOP_COLOR_COUNT: usize = 5;
+const HISTOGRAM_BUCKETS: usize = 16;
+// Each histogram bucket is drawn as one of these, from empty to the fullest bucket.
+const HISTOGRAM_LEVELS: &[u8] = b" .:-=+*#%@";
+
+/// A summary of what an image contains, to help tell apart failures like an all
+/// black capture, a stale frame or garbage.
+pub struct ImageStats {
+    pub pixel_count: u64,
+    /// Per-channel histograms in B, G, R, A order.
+    pub histograms: [[u64; 256]; 4],
+    pub mean: [f64; 4],
+    pub std_dev: [f64; 4],
+    /// The most frequent colors and how many pixels have them, most frequent first.
+    pub top_colors: Vec<(Color, u64)>,
+    pub fully_transparent: bool,
+    pub uniform: bool,
+}
+
+impl ImageStats {
+    pub fn compute(image: &CpuImage) -> Self {
+        let mut histograms = [[0u64; 256]; 4];
+        let mut color_counts: HashMap<[u8; 4], u64> = HashMap::new();
+        for pixel in image.bytes().chunks_exact(4) {
+            for (channel, value) in pixel.iter().enumerate() {
+                histograms[channel][*value as usize] += 1;
+            }
+            *color_counts
+                .entry([pixel[0], pixel[1], pixel[2], pixel[3]])
+                .or_default() += 1;
+        }
+
+        let pixel_count = image.width() as u64 * image.height() as u64;
+        let mut mean = [0.0; 4];
+        let mut std_dev = [0.0; 4];
+        if pixel_count > 0 {
+            for channel in 0..4 {
+                let histogram = &histograms[channel];
+                let sum: f64 = (0..256).map(|v| v as f64 * histogram[v] as f64).sum();
+                mean[channel] = sum / pixel_count as f64;
+                let variance: f64 = (0..256)
+                    .map(|v| (v as f64 - mean[channel]).powi(2) * histogram[v] as f64)
+                    .sum::<f64>()
+                    / pixel_count as f64;
+                std_dev[channel] = variance.sqrt();
+            }
+        }
+
+        let uniform = color_counts.len() <= 1;
+        let mut top_colors: Vec<(Color, u64)> = color_counts
+            .into_iter()
+            .map(|([b, g, r, a], count)| {
+                (
+                    Color {
+                        B: b,
+                        G: g,
+                        R: r,
+                        A: a,
+                    },
+                    count,
+                )
+            })
+            .collect();
+        // Break ties by color so the summary is the same from run to run.
+        top_colors.sort_by_key(|(color, count)| {
+            (
+                std::cmp::Reverse(*count),
+                [color.B, color.G, color.R, color.A],
+            )
+        });
+        top_colors.truncate(TOP_COLOR_COUNT);
+
+        Self {
+            pixel_count,
+            fully_transparent: histograms[3][0] == pixel_count,
+            histograms,
+            mean,
+            std_dev,
+            top_colors,
+            uniform,
+        }
+    }
+
+    /// A multi-line description in the same style as the failure messages.
+    pub fn summary(&self) -> String {
+        let mut summary = String::new();
+        writeln!(summary, "  Image statistics ({} pixels):", self.pixel_count).unwrap();
+        if self.fully_transparent {
+            summary.push_str("    The image is fully transparent!\n");
+        } else if self.uniform {
+            summary.push_str("    The image is a single uniform color!\n");
+        }
+        for (channel, name) in CHANNEL_NAMES.iter().enumerate() {
+            writeln!(
+                summary,
+                "    {}: mean {:6.2}, std dev {:6.2}, histogram [{}]",
+                name,
+                self.mean[channel],
+                self.std_dev[channel],
+                self.histogram_bar(channel)
+            )
+            .unwrap();
+        }
+        summary.push_str("    Most frequent colors:\n");
+        for (color, count) in &self.top_colors {
+            writeln!(
+                summary,
+                "      ( B: {}, G: {}, R: {}, A: {} ) x {} ({:.1}%)",
+                color.B,
+                color.G,
+                color.R,
+                color.A,
+                count,
+                *count as f64 * 100.0 / self.pixel_count as f64
+            )
+            .unwrap();
+        }
+        summary
+    }
+
+    /// Draws the channel's histogram as a row of characters, one per bucket of
+    /// 16 values, scaled so that the fullest bucket uses the densest character.
+    fn histogram_bar(&self, channel: usize) -> String {
+        let buckets: Vec<u64> = self.histograms[channel]
+            .chunks(256 / HISTOGRAM_BUCKETS)
+            .map(|bucket| bucket.iter().sum())
+            .collect();
+        let max = buckets.iter().copied().max().unwrap_or(0).max(1);
+        buckets
+            .iter()
+            .map(|count| {
+                let level = if *count == 0 {
+                    0
+                } else {
+                    // Any non-empty bucket should be visible.
+                    (*count * (HISTOGRAM_LEVELS.len() as u64 - 1)).div_ceil(max) as usize
+                };
+                HISTOGRAM_LEVELS[level] as char
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::common_colors;
+
+    /// Three quarters red, one quarter blue.
+    fn red_and_blue() -> CpuImage {
+        CpuImage::from_fn(4, 4, |x, _| {
+            if x < 3 {
+                common_colors::RED
+            } else {
+                common_colors::BLUE
+            }
+        })
+    }
+
+    #[test]
+    fn known_image_has_known_stats() {
+        let stats = ImageStats::compute(&red_and_blue());
+        assert_eq!(stats.pixel_count, 16);
+        assert_eq!(stats.histograms[2][255], 12);
+        assert_eq!(stats.histograms[2][0], 4);
+        assert_eq!(stats.histograms[3][255], 16);
+        // B is 255 on a quarter of the pixels: mean 63.75, std dev 255 * sqrt(3) / 4.
+        assert!((stats.mean[0] - 63.75).abs() < 1e-9);
+        assert!((stats.std_dev[0] - 255.0 * 3f64.sqrt() / 4.0).abs() < 1e-9);
+        assert!((stats.mean[2] - 191.25).abs() < 1e-9);
+        assert_eq!(stats.mean[1], 0.0);
+        assert_eq!(stats.std_dev[3], 0.0);
+        assert_eq!(
+            stats.top_colors,
+            vec![(common_colors::RED, 12), (common_colors::BLUE, 4)]
+        );
+        assert!(!stats.uniform);
+        assert!(!stats.fully_transparent);
+    }
+
+    #[test]
+    fn blank_images_are_flagged() {
+        let transparent = ImageStats::compute(&CpuImage::new(8, 8));
+        assert!(transparent.fully_transparent);
+        assert!(transparent.uniform);
+        assert!(transparent
+            .summary()
+            .contains("The image is fully transparent!"));
+
+        let green = ImageStats::compute(&CpuImage::from_fn(8, 8, |_, _| common_colors::GREEN));
+        assert!(!green.fully_transparent);
+        assert!(green.uniform);
+        assert!(green
+            .summary()
+            .contains("The image is a single uniform color!"));
+    }
+
+    #[test]
+    fn summary_lists_channels_and_colors() {
+        let summary = ImageStats::compute(&red_and_blue()).summary();
+        assert!(summary.starts_with("  Image statistics (16 pixels):\n"));
+        assert!(
+            summary.contains("    R: mean 191.25, std dev 110.42, histogram [-              @]\n")
+        );
+        assert!(summary.contains("      ( B: 0, G: 0, R: 255, A: 255 ) x 12 (75.0%)\n"));
+        assert!(summary.contains("      ( B: 255, G: 0, R: 0, A: 255 ) x 4 (25.0%)\n"));
+    }
+
+    #[test]
+    fn empty_image_has_no_nan() {
+        let stats = ImageStats::compute(&CpuImage::new(0, 0));
+        assert_eq!(stats.pixel_count, 0);
+        assert_eq!(stats.mean, [0.0; 4]);
+        assert!(stats.top_colors.is_empty());
+    }
+}
@@ -0,0 +1,458 @@
+use std::{
+    any::Any,
+    cell::{Ref, RefCell},
+    io,
+    path::PathBuf,
+};
+
+use super::{
+    assertion::Capture,
+    error::{AssertionError, TestError, TestResult},
+    preview::{render_preview, PreviewStyle},
+    report::{Failure, Metric, Outcome, TestRecord, TestReport},
+    stats::ImageStats,
+};
+
+const PREVIEW_COLUMNS: u32 = 64;
+
+/// Settings shared by every test in a run, parsed from the command line, along
+/// with the results collected so far.
+pub struct TestContext {
+    bless: bool,
+    record: bool,
+    preview: Option<PreviewStyle>,
+    golden_dir: PathBuf,
+    output_dir: PathBuf,
+    report: RefCell<TestReport>,
+    metrics: RefCell<Vec<Metric>>,
+}
+
+impl TestContext {
+    /// Recognized arguments:
+    ///   --bless            Rewrite golden images instead of comparing against them.
+    ///   --record           Record the frames tests capture to `<test>.wgcrec`.
+    ///   --preview[=ascii]  Print a preview of the captured image for failed tests,
+    ///                      using ANSI colors unless "ascii" is given.
+    /// Fails on any other argument, so that a typo doesn't go unnoticed.
+    pub fn from_args() -> io::Result<Self> {
+        let (context, inputs) = Self::from_args_with_inputs()?;
+        match inputs.first() {
+            Some(input) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unexpected argument {}", input),
+            )),
+            None => Ok(context),
+        }
+    }
+
+    /// Like `from_args`, but also returns the arguments that aren't options,
+    /// e.g. the recordings to replay. Unknown options still fail.
+    pub fn from_args_with_inputs() -> io::Result<(Self, Vec<String>)> {
+        Self::parse_args(std::env::args().skip(1))
+    }
+
+    fn parse_args<I: IntoIterator<Item = String>>(args: I) -> io::Result<(Self, Vec<String>)> {
+        let mut bless = false;
+        let mut record = false;
+        let mut preview = None;
+        let mut inputs = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "--bless" => bless = true,
+                "--record" => record = true,
+                "--preview" | "--preview=ansi" => preview = Some(PreviewStyle::Ansi),
+                "--preview=ascii" => preview = Some(PreviewStyle::Ascii),
+                option if option.starts_with("--") => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "unknown option {}, expected --bless, --record or --preview[=ascii]",
+                            option
+                        ),
+                    ))
+                }
+                _ => inputs.push(arg),
+            }
+        }
+        let context = Self {
+            bless,
+            record,
+            preview,
+            ..Self::new(std::env::current_dir()?)
+        };
+        Ok((context, inputs))
+    }
+
+    /// A context that writes its output to `output_dir` and looks for golden
+    /// images in `output_dir/goldens`, with every option off.
+    pub fn new(output_dir: PathBuf) -> Self {
+        Self {
+            bless: false,
+            record: false,
+            preview: None,
+            golden_dir: output_dir.join("goldens"),
+            output_dir,
+            report: RefCell::new(TestReport::default()),
+            metrics: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The same context with `--bless` on.
+    #[cfg(test)]
+    pub(crate) fn with_bless(self) -> Self {
+        Self {
+            bless: true,
+            ..self
+        }
+    }
+
+    /// Whether golden images should be rewritten instead of compared against.
+    pub fn bless(&self) -> bool {
+        self.bless
+    }
+
+    pub fn golden_path(&self, name: &str) -> PathBuf {
+        self.golden_dir.join(format!("{}.png", name))
+    }
+
+    /// Where the frames captured by `test_name` should be recorded, or `None`
+    /// if recording wasn't asked for.
+    pub fn recording_path(&self, test_name: &str) -> Option<PathBuf> {
+        self.record
+            .then(|| self.output_dir.join(format!("{}.wgcrec", test_name)))
+    }
+
+    /// Records a measurement for the currently running test, which will show up
+    /// in the report whether or not the test passes.
+    pub fn record_metric(&self, name: String, value: f64) {
+        self.metrics.borrow_mut().push(Metric { name, value });
+    }
+
+    /// Prints the outcome of a test, saves any images attached to a failure and
+    /// adds the test to the report. An image that can't be read back or saved
+    /// is noted in the test's failure, the run goes on either way.
+    pub fn record_result(&self, test_name: &str, result: TestResult<()>) {
+        let mut artifacts = Vec::new();
+        let outcome = match result {
+            Ok(_) => {
+                println!("{}: PASSED", test_name);
+                Outcome::Passed
+            }
+            Err(error) if error.skip_reason().is_some() => {
+                let reason = error.skip_reason().unwrap().to_owned();
+                println!("{}: SKIPPED - {}", test_name, reason);
+                Outcome::Skipped(reason)
+            }
+            Err(error) => {
+                let kind = error.kind();
+                let message = error.to_string();
+                let mut context = Vec::new();
+                let mut error = error;
+                while let TestError::Context {
+                    context: step,
+                    source,
+                } = error
+                {
+                    context.push(step);
+                    error = *source;
+                }
+                let mut image = None;
+                let assertion = match error {
+                    TestError::Assertion(error) => {
+                        let AssertionError {
+                            mut failure,
+                            capture,
+                            artifacts: extra_images,
+                        } = *error;
+                        let captured = match capture {
+                            Some(Capture::Texture(texture)) => match texture.read_back() {
+                                Ok(captured) => Some(captured),
+                                Err(error) => {
+                                    failure.notes.push(format!(
+                                        "The capture couldn't be read back: {}",
+                                        error
+                                    ));
+                                    None
+                                }
+                            },
+                            Some(Capture::Image(captured)) => Some(captured),
+                            None => None,
+                        };
+                        if let Some(captured) = captured {
+                            let file_name = format!("{}.png", test_name);
+                            match captured.save_png(self.output_dir.join(&file_name)) {
+                                Ok(()) => artifacts.push(file_name),
+                                Err(error) => failure
+                                    .notes
+                                    .push(format!("Couldn't save {}: {}", file_name, error)),
+                            }
+                            image = Some(captured);
+                        }
+                        for artifact in &extra_images {
+                            let file_name = format!("{}.{}.png", test_name, artifact.name);
+                            match artifact.image.save_png(self.output_dir.join(&file_name)) {
+                                Ok(()) => artifacts.push(file_name),
+                                Err(error) => failure
+                                    .notes
+                                    .push(format!("Couldn't save {}: {}", file_name, error)),
+                            }
+                        }
+                        Some(failure)
+                    }
+                    _ => None,
+                };
+                let image_summary = image
+                    .as_ref()
+                    .map(|image| ImageStats::compute(image).summary());
+                println!(
+                    "{}: FAILED - {}{}",
+                    test_name,
+                    message,
+                    image_summary.as_deref().unwrap_or_default()
+                );
+                if let (Some(image), Some(style)) = (&image, self.preview) {
+                    let markers: Vec<_> = assertion
+                        .as_ref()
+                        .and_then(|assertion| assertion.location)
+                        .into_iter()
+                        .collect();
+                    print!(
+                        "{}",
+                        render_preview(image, PREVIEW_COLUMNS, style, &markers)
+                    );
+                }
+                Outcome::Failed(Box::new(Failure {
+                    kind,
+                    message,
+                    context,
+                    assertion,
+                    image_summary,
+                }))
+            }
+        };
+        self.report.borrow_mut().add(TestRecord {
+            name: test_name.to_owned(),
+            outcome,
+            artifacts,
+            metrics: self.metrics.take(),
+        });
+    }
+
+    /// The tests recorded so far.
+    pub fn report(&self) -> Ref<'_, TestReport> {
+        self.report.borrow()
+    }
+
+    /// Writes the report as `report.html`, `report.json` and JUnit XML in `report.xml`.
+    pub fn write_report(&self) -> std::io::Result<()> {
+        let report = self.report.borrow();
+        report.write_html(self.output_dir.join("report.html"))?;
+        report.write_json(self.output_dir.join("report.json"))?;
+        report.write_junit(self.output_dir.join("report.xml"))
+    }
+}
+
+/// Gets the message out of a panic payload, which is a `&str` or a `String` for
+/// panics raised with `panic!`.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_owned()
+    }
+}
+
+/// Runs a test and records its result. A panicking test fails with
+/// `TestError::Panicked` instead of taking down the whole run.
+#[macro_export]
+macro_rules! run_test {
+    ($context:ident, $test_name:ident, $($param:tt)*) => {
+        {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                $test_name($($param)*)
+            }))
+            .unwrap_or_else(|payload| {
+                Err($crate::error::TestError::Panicked(
+                    $crate::test_runner::panic_message(payload.as_ref()),
+                ))
+            });
+            $context.record_result(stringify!($test_name), result);
+        }
+    }
+}
+
+/// A fresh directory for a unit test's output.
+#[cfg(test)]
+pub(crate) fn test_output_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("wgctest-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assertion::{Artifact, AssertionFailure, AssertionKind, Readback},
+        cpu_image::CpuImage,
+        error::Context,
+    };
+
+    #[derive(Debug)]
+    struct LostTexture;
+
+    impl Readback for LostTexture {
+        fn read_back(&self) -> TestResult<CpuImage> {
+            Err(io::Error::other("the device was removed").into())
+        }
+    }
+
+    fn failure(capture: Capture, artifacts: Vec<Artifact>) -> TestResult<()> {
+        TestError::Assertion(Box::new(AssertionError {
+            failure: AssertionFailure::new(AssertionKind::PixelColor),
+            capture: Some(capture),
+            artifacts,
+        }))
+        .ok()
+    }
+
+    fn notes(context: &TestContext, test_name: &str) -> Vec<String> {
+        let report = context.report();
+        let record = report
+            .records()
+            .iter()
+            .find(|record| record.name == test_name)
+            .unwrap();
+        match &record.outcome {
+            Outcome::Failed(failure) => failure.assertion.as_ref().unwrap().notes.clone(),
+            _ => panic!("{} didn't fail", test_name),
+        }
+    }
+
+    fn args(args: &[&str]) -> io::Result<(TestContext, Vec<String>)> {
+        TestContext::parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn junit(context: &TestContext) -> String {
+        context.write_report().unwrap();
+        std::fs::read_to_string(context.output_dir.join("report.xml")).unwrap()
+    }
+
+    #[test]
+    fn skip_with_context_is_a_skip() {
+        let context = TestContext::new(test_output_dir("skip-with-context"));
+        let result: TestResult<()> = Err(TestError::Skipped("the window is off screen".to_owned()))
+            .context("taking a snapshot");
+        context.record_result("wrapped_skip", result);
+
+        let xml = junit(&context);
+        assert!(
+            xml.contains("<skipped message=\"the window is off screen\"/>"),
+            "{}",
+            xml
+        );
+        assert!(xml.contains("errors=\"0\" skipped=\"1\""), "{}", xml);
+        assert!(!xml.contains("<error"), "{}", xml);
+        assert!(!xml.contains("<failure"), "{}", xml);
+    }
+
+    #[test]
+    fn outcomes_are_reported() {
+        let context = TestContext::new(test_output_dir("outcomes"));
+        context.record_result("passes", Ok(()));
+        context.record_result("skips", TestError::Skipped("no GPU".to_owned()).ok());
+        context.record_result(
+            "times_out",
+            TestError::Timeout {
+                waiting_for: "the next frame".to_owned(),
+                timeout: std::time::Duration::from_secs(1),
+            }
+            .ok()
+            .context("waiting for the first frame"),
+        );
+
+        let xml = junit(&context);
+        assert!(xml.contains("<skipped message=\"no GPU\"/>"), "{}", xml);
+        assert!(xml.contains("<error type=\"timeout\""), "{}", xml);
+        assert!(
+            xml.contains(
+                "<property name=\"error.context.0\" value=\"waiting for the first frame\"/>"
+            ),
+            "{}",
+            xml
+        );
+    }
+
+    #[test]
+    fn failed_read_backs_are_noted_and_the_run_goes_on() {
+        let context = TestContext::new(test_output_dir("failed-read-back"));
+        context.record_result(
+            "lost_texture",
+            failure(Capture::Texture(Box::new(LostTexture)), Vec::new()),
+        );
+        context.record_result("passes", Ok(()));
+
+        assert_eq!(
+            notes(&context, "lost_texture"),
+            ["The capture couldn't be read back: the device was removed"]
+        );
+        assert!(context.report().records()[0].artifacts.is_empty());
+        assert!(junit(&context).contains("tests=\"2\""));
+    }
+
+    #[test]
+    fn failed_saves_are_noted() {
+        let output_dir = test_output_dir("failed-save");
+        // Saving into a directory that doesn't exist fails.
+        let context = TestContext::new(output_dir.join("missing"));
+        context.record_result(
+            "unsaved",
+            failure(
+                Capture::Image(CpuImage::new(2, 2)),
+                vec![Artifact {
+                    name: "diff".to_owned(),
+                    image: CpuImage::new(2, 2),
+                }],
+            ),
+        );
+
+        let notes = notes(&context, "unsaved");
+        assert_eq!(notes.len(), 2);
+        assert!(
+            notes[0].starts_with("Couldn't save unsaved.png: "),
+            "{:?}",
+            notes
+        );
+        assert!(
+            notes[1].starts_with("Couldn't save unsaved.diff.png: "),
+            "{:?}",
+            notes
+        );
+        assert!(context.report().records()[0].artifacts.is_empty());
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let (context, inputs) =
+            args(&["--bless", "a.wgcrec", "--preview=ascii", "b.wgcrec"]).unwrap();
+        assert!(context.bless());
+        assert!(!context.record);
+        assert_eq!(context.preview, Some(PreviewStyle::Ascii));
+        assert_eq!(inputs, ["a.wgcrec", "b.wgcrec"]);
+
+        let (context, inputs) = args(&["--record", "--preview"]).unwrap();
+        assert!(context.record);
+        assert_eq!(context.preview, Some(PreviewStyle::Ansi));
+        assert!(inputs.is_empty());
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        let error = args(&["--bles"]).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("--bles"), "{}", error);
+    }
+}
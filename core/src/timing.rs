@@ -0,0 +1,353 @@
+use std::time::Duration;
+
+use super::{
+    assertion::{AssertionFailure, AssertionKind, Detail, Value},
+    error::{AssertionError, TestError, TestResult},
+};
+
+/// When a frame was produced and when the test received it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameTiming {
+    /// The frame's `SystemRelativeTime`.
+    pub timestamp: Duration,
+    /// When the frame arrived, on the capture session's clock. This isn't
+    /// comparable with `timestamp`, only with other arrival times.
+    pub arrival: Duration,
+}
+
+/// Percentiles of a set of intervals, picked by nearest rank.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IntervalStats {
+    pub min: Duration,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl IntervalStats {
+    /// Returns `None` if there are no intervals.
+    pub fn compute(intervals: &[Duration]) -> Option<Self> {
+        let mut sorted = intervals.to_vec();
+        sorted.sort();
+        Some(Self {
+            min: *sorted.first()?,
+            p50: percentile(&sorted, 50.0)?,
+            p95: percentile(&sorted, 95.0)?,
+            p99: percentile(&sorted, 99.0)?,
+            max: *sorted.last()?,
+        })
+    }
+}
+
+/// The interval at `percentile` of intervals sorted in ascending order, or
+/// `None` if there are none.
+pub fn percentile(sorted: &[Duration], percentile: f64) -> Option<Duration> {
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.clamp(1, sorted.len().max(1)) - 1).copied()
+}
+
+/// An interval between two consecutive frames that was longer than allowed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Gap {
+    /// The index of the frame after the gap.
+    pub frame: usize,
+    pub interval: Duration,
+}
+
+/// How regularly a sequence of frames was produced and delivered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimingAnalysis {
+    pub frame_count: usize,
+    /// Frames per second over the time between the first and the last timestamp.
+    pub effective_fps: f64,
+    /// The intervals between consecutive timestamps, leaving out the ones that
+    /// went backwards.
+    pub intervals: Option<IntervalStats>,
+    /// The intervals between consecutive arrivals.
+    pub arrival_intervals: Option<IntervalStats>,
+    /// How many frames had the same timestamp as the frame before them.
+    pub duplicate_timestamps: usize,
+    /// How many frames had an earlier timestamp than the frame before them.
+    pub out_of_order_timestamps: usize,
+    /// The timestamp intervals longer than the gap threshold.
+    pub gaps: Vec<Gap>,
+    sorted_intervals: Vec<Duration>,
+}
+
+impl TimingAnalysis {
+    /// Analyzes `timings` in the order the frames were received. Intervals
+    /// longer than `gap_threshold` are reported as gaps.
+    pub fn analyze(timings: &[FrameTiming], gap_threshold: Duration) -> Self {
+        let mut intervals = Vec::new();
+        let mut arrival_intervals = Vec::new();
+        let mut duplicate_timestamps = 0;
+        let mut out_of_order_timestamps = 0;
+        let mut gaps = Vec::new();
+        for (i, pair) in timings.windows(2).enumerate() {
+            let (previous, current) = (pair[0], pair[1]);
+            arrival_intervals.push(current.arrival.saturating_sub(previous.arrival));
+            if current.timestamp < previous.timestamp {
+                out_of_order_timestamps += 1;
+                continue;
+            }
+            if current.timestamp == previous.timestamp {
+                duplicate_timestamps += 1;
+            }
+            let interval = current.timestamp - previous.timestamp;
+            if interval > gap_threshold {
+                gaps.push(Gap {
+                    frame: i + 1,
+                    interval,
+                });
+            }
+            intervals.push(interval);
+        }
+
+        let span = match (timings.first(), timings.last()) {
+            (Some(first), Some(last)) => last.timestamp.saturating_sub(first.timestamp),
+            _ => Duration::ZERO,
+        };
+        let effective_fps = if span.is_zero() {
+            0.0
+        } else {
+            (timings.len() - 1) as f64 / span.as_secs_f64()
+        };
+
+        intervals.sort();
+        Self {
+            frame_count: timings.len(),
+            effective_fps,
+            intervals: IntervalStats::compute(&intervals),
+            arrival_intervals: IntervalStats::compute(&arrival_intervals),
+            duplicate_timestamps,
+            out_of_order_timestamps,
+            gaps,
+            sorted_intervals: intervals,
+        }
+    }
+
+    /// Fails unless every timestamp is later than the one before it.
+    pub fn expect_strictly_increasing(&self) -> TestResult<()> {
+        if self.duplicate_timestamps == 0 && self.out_of_order_timestamps == 0 {
+            Ok(())
+        } else {
+            self.failure("timestamps strictly increase")
+        }
+    }
+
+    /// Fails unless the interval at `percentile`, e.g. 95 for p95, is below `limit`.
+    pub fn expect_interval_percentile_below(
+        &self,
+        percentile: f64,
+        limit: Duration,
+    ) -> TestResult<()> {
+        match self.interval_percentile(percentile) {
+            Some(interval) if interval < limit => Ok(()),
+            _ => self.failure(&format!(
+                "p{} interval < {:.1} ms",
+                percentile,
+                limit.as_secs_f64() * 1000.0
+            )),
+        }
+    }
+
+    /// Fails if there are any gaps.
+    pub fn expect_no_gaps(&self) -> TestResult<()> {
+        if self.gaps.is_empty() {
+            Ok(())
+        } else {
+            self.failure("no gaps between frames")
+        }
+    }
+
+    /// The timestamp interval at `percentile`, e.g. 95 for p95.
+    pub fn interval_percentile(&self, percentile: f64) -> Option<Duration> {
+        self::percentile(&self.sorted_intervals, percentile)
+    }
+
+    fn failure(&self, property: &str) -> TestResult<()> {
+        Err(TestError::Assertion(Box::new(AssertionError {
+            failure: AssertionFailure {
+                subject: Some(property.to_owned()),
+                details: self.details(),
+                ..AssertionFailure::new(AssertionKind::FrameTiming)
+            },
+            capture: None,
+            artifacts: Vec::new(),
+        })))
+    }
+
+    fn details(&self) -> Vec<Detail> {
+        let mut details = vec![
+            Detail {
+                label: "Frames",
+                value: Value::Count(self.frame_count as u64),
+            },
+            Detail {
+                label: "Effective FPS",
+                value: Value::Number(self.effective_fps),
+            },
+        ];
+        if let Some(intervals) = &self.intervals {
+            for (label, interval) in [
+                ("Min interval (ms)", intervals.min),
+                ("p50 interval (ms)", intervals.p50),
+                ("p95 interval (ms)", intervals.p95),
+                ("p99 interval (ms)", intervals.p99),
+                ("Max interval (ms)", intervals.max),
+            ] {
+                details.push(Detail {
+                    label,
+                    value: Value::Number(interval.as_secs_f64() * 1000.0),
+                });
+            }
+        }
+        details.push(Detail {
+            label: "Duplicate timestamps",
+            value: Value::Count(self.duplicate_timestamps as u64),
+        });
+        details.push(Detail {
+            label: "Out of order timestamps",
+            value: Value::Count(self.out_of_order_timestamps as u64),
+        });
+        details.push(Detail {
+            label: "Gaps",
+            value: Value::Count(self.gaps.len() as u64),
+        });
+        details
+    }
+}
+
+/// Collects the timing of frames as they are received.
+#[derive(Clone, Debug, Default)]
+pub struct TimingRecorder {
+    timings: Vec<FrameTiming>,
+}
+
+impl TimingRecorder {
+    pub fn record(&mut self, timestamp: Duration, arrival: Duration) {
+        self.timings.push(FrameTiming { timestamp, arrival });
+    }
+
+    pub fn timings(&self) -> &[FrameTiming] {
+        &self.timings
+    }
+
+    pub fn analyze(&self, gap_threshold: Duration) -> TimingAnalysis {
+        TimingAnalysis::analyze(&self.timings, gap_threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn timings(timestamps: &[u64]) -> Vec<FrameTiming> {
+        timestamps
+            .iter()
+            .enumerate()
+            .map(|(i, timestamp)| FrameTiming {
+                timestamp: ms(*timestamp),
+                arrival: ms(100 + 16 * i as u64),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn percentiles_pick_the_nearest_rank() {
+        let sorted: Vec<_> = (1..=100).map(ms).collect();
+        assert_eq!(percentile(&sorted, 50.0), Some(ms(50)));
+        assert_eq!(percentile(&sorted, 95.0), Some(ms(95)));
+        assert_eq!(percentile(&sorted, 99.5), Some(ms(100)));
+        assert_eq!(percentile(&sorted, 0.0), Some(ms(1)));
+        assert_eq!(percentile(&sorted, 100.0), Some(ms(100)));
+
+        // Four intervals: the p50 rank is 2, the p95 rank rounds up to 4.
+        let sorted = [ms(1), ms(2), ms(3), ms(40)];
+        assert_eq!(percentile(&sorted, 50.0), Some(ms(2)));
+        assert_eq!(percentile(&sorted, 95.0), Some(ms(40)));
+    }
+
+    #[test]
+    fn percentiles_of_no_or_one_interval() {
+        assert_eq!(percentile(&[], 50.0), None);
+        assert_eq!(IntervalStats::compute(&[]), None);
+        let single = IntervalStats::compute(&[ms(7)]).unwrap();
+        for value in [single.min, single.p50, single.p95, single.p99, single.max] {
+            assert_eq!(value, ms(7));
+        }
+    }
+
+    #[test]
+    fn no_frames_or_one_frame_have_no_intervals() {
+        for frames in [timings(&[]), timings(&[5])] {
+            let analysis = TimingAnalysis::analyze(&frames, ms(20));
+            assert_eq!(analysis.frame_count, frames.len());
+            assert_eq!(analysis.effective_fps, 0.0);
+            assert_eq!(analysis.intervals, None);
+            assert_eq!(analysis.arrival_intervals, None);
+            assert_eq!(analysis.interval_percentile(50.0), None);
+            assert!(analysis.expect_strictly_increasing().is_ok());
+            assert!(analysis.expect_no_gaps().is_ok());
+            // There is no interval to be below the limit.
+            assert!(analysis
+                .expect_interval_percentile_below(50.0, ms(100))
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn regular_frames_pass_every_check() {
+        let analysis = TimingAnalysis::analyze(&timings(&[0, 16, 32, 48, 64]), ms(20));
+        assert_eq!(analysis.effective_fps, 4.0 / 0.064);
+        let intervals = analysis.intervals.unwrap();
+        assert_eq!((intervals.min, intervals.max), (ms(16), ms(16)));
+        assert_eq!(analysis.arrival_intervals.unwrap().p50, ms(16));
+        assert!(analysis.expect_strictly_increasing().is_ok());
+        assert!(analysis.expect_no_gaps().is_ok());
+        assert!(analysis
+            .expect_interval_percentile_below(99.0, ms(17))
+            .is_ok());
+    }
+
+    #[test]
+    fn irregular_frames_are_counted() {
+        // A duplicate, a timestamp going backwards and then a long gap.
+        let analysis = TimingAnalysis::analyze(&timings(&[0, 10, 10, 5, 40]), ms(20));
+        assert_eq!(analysis.frame_count, 5);
+        assert_eq!(analysis.duplicate_timestamps, 1);
+        assert_eq!(analysis.out_of_order_timestamps, 1);
+        assert_eq!(
+            analysis.gaps,
+            [Gap {
+                frame: 4,
+                interval: ms(35)
+            }]
+        );
+        assert_eq!(analysis.effective_fps, 4.0 / 0.04);
+        // The interval that went backwards is left out.
+        assert_eq!(analysis.interval_percentile(50.0), Some(ms(10)));
+        assert_eq!(analysis.interval_percentile(95.0), Some(ms(35)));
+        assert!(analysis
+            .expect_interval_percentile_below(50.0, ms(11))
+            .is_ok());
+        assert!(analysis
+            .expect_interval_percentile_below(95.0, ms(35))
+            .is_err());
+        assert!(analysis.expect_no_gaps().is_err());
+
+        let error = analysis.expect_strictly_increasing().unwrap_err();
+        let TestError::Assertion(error) = error else {
+            panic!("expected an assertion");
+        };
+        assert_eq!(
+            error.failure.subject.as_deref(),
+            Some("timestamps strictly increase")
+        );
+    }
+}
@@ -1,22 +1,45 @@
+#[cfg(windows)]
 mod tests;
-#[macro_use]
+#[cfg(windows)]
 mod util;
 
+use std::error::Error;
+#[cfg(windows)]
 use std::sync::mpsc::channel;
 
+#[cfg(windows)]
+use wgctest_core::{run_test, test_runner::TestContext};
+#[cfg(windows)]
 use windows::Win32::System::WinRT::{RoInitialize, RO_INIT_MULTITHREADED};
+#[cfg(windows)]
 use windows::Win32::UI::HiDpi::{
     SetProcessDpiAwarenessContext, DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2,
 };
+#[cfg(windows)]
 use windows::{
     System::{DispatcherQueueController, DispatcherQueueHandler},
     UI::Composition::Core::CompositorController,
 };
 
-use crate::tests::{alpha_test, basic_window_test, fullscreen_transition_test};
+#[cfg(windows)]
+use crate::tests::{
+    alpha_test, basic_window_test, fullscreen_transition_test, monitor_capture_test,
+};
+#[cfg(windows)]
 use crate::util::d3d::{create_d3d_device, create_direct3d_device};
 
-fn main() -> windows::core::Result<()> {
+#[cfg(not(windows))]
+fn main() -> Result<(), Box<dyn Error>> {
+    Err(
+        "the capture tests only run on Windows, see wgctest-core for the checks that run anywhere"
+            .into(),
+    )
+}
+
+#[cfg(windows)]
+fn main() -> Result<(), Box<dyn Error>> {
+    let context = TestContext::from_args()?;
+
     // NOTE: We don't properly scale any of the UI or properly respond to DPI changes, but none of
     //       the UI is meant to be interacted with. This is just so that the tests don't get
     //       virtualized coordinates on high DPI machines.
@@ -47,14 +70,37 @@ fn main() -> windows::core::Result<()> {
 
     // Run tests
     // TODO: Allow filters to only run certain tests
-    run_test!(alpha_test, &compositor_controller, &device);
     run_test!(
+        context,
+        alpha_test,
+        &context,
+        &compositor_controller,
+        &device
+    );
+    run_test!(
+        context,
         basic_window_test,
+        &context,
+        &compositor_queue,
+        &compositor_controller,
+        &device
+    );
+    run_test!(
+        context,
+        fullscreen_transition_test,
+        &context,
+        &compositor_queue,
+        &device
+    );
+    run_test!(
+        context,
+        monitor_capture_test,
         &compositor_queue,
         &compositor_controller,
         &device
     );
-    run_test!(fullscreen_transition_test, &compositor_queue, &device);
+
+    context.write_report()?;
 
     Ok(())
 }
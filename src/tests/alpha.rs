@@ -1,23 +1,34 @@
+use std::time::Duration;
+
+use wgctest_core::{
+    assertion::Tolerance,
+    blobs::expect_circle,
+    color::{common_colors, expect_region_color, NEIGHBORHOOD_SIZE},
+    error::{Context, TestResult},
+    geometry::Rect,
+    golden::expect_matches_golden,
+    mask::Mask,
+    test_runner::TestContext,
+};
 use windows::{
     Foundation::Numerics::Vector2,
-    Graphics::{
-        Capture::GraphicsCaptureItem,
-        DirectX::{Direct3D11::IDirect3DDevice, DirectXPixelFormat},
-    },
+    Graphics::{Capture::GraphicsCaptureItem, DirectX::Direct3D11::IDirect3DDevice},
     UI::Composition::Core::CompositorController,
 };
 
 use crate::util::{
-    color::{check_color, common_colors},
-    error::TestResult,
-    mapped::MappedTexture,
-    snapshot::take_snapshot_with_commit,
+    color::check_pixel,
+    graphics_capture::require_capture_support,
+    mapped::{texture_capture, MappedTexture},
+    snapshot::{take_snapshot_with_commit, Crop, OutputKind, Snapshot, TakenSnapshot},
 };
 
 pub fn alpha_test(
+    context: &TestContext,
     compositor_controller: &CompositorController,
     device: &IDirect3DDevice,
 ) -> TestResult<()> {
+    require_capture_support()?;
     let compositor = compositor_controller.Compositor()?;
 
     // Build the visual tree
@@ -28,31 +39,68 @@ pub fn alpha_test(
     geometry.SetCenter(Vector2::new(50.0, 50.0))?;
     geometry.SetRadius(Vector2::new(50.0, 50.0))?;
     let shape = compositor.CreateSpriteShapeWithGeometry(&geometry)?;
-    shape.SetFillBrush(&compositor.CreateColorBrushWithColor(common_colors::RED)?)?;
+    shape.SetFillBrush(&compositor.CreateColorBrushWithColor(common_colors::RED.into())?)?;
     visual.Shapes()?.Append(&shape)?;
 
     // Capture the tree
     let item = GraphicsCaptureItem::CreateFromVisual(&visual)?;
-    let frame = take_snapshot_with_commit(
-        device,
-        &item,
-        DirectXPixelFormat::B8G8R8A8UIntNormalized,
-        true,
-        true,
-        compositor_controller,
-    )?;
+    let frame = take_snapshot_with_commit(device, &item, compositor_controller)
+        .context("taking a snapshot of the visual")?;
 
     // Map the texture and check the image
-    {
+    let image = {
         let mapped = MappedTexture::new(&frame)?;
 
-        check_color(mapped.read_pixel(50, 50).unwrap(), common_colors::RED).ok(&frame)?;
-        check_color(
-            mapped.read_pixel(5, 5).unwrap(),
+        check_pixel(&mapped, 50, 50, common_colors::RED, NEIGHBORHOOD_SIZE)
+            .ok_with(|| texture_capture(&frame))?;
+        check_pixel(
+            &mapped,
+            5,
+            5,
             common_colors::TRANSPARENT_BLACK,
+            NEIGHBORHOOD_SIZE,
         )
-        .ok(&frame)?;
-    }
+        .ok_with(|| texture_capture(&frame))?;
+
+        mapped.to_cpu_image()
+    };
+    // The red pixels should form a circle of radius 50 in the center. Partially
+    // covered pixels along the edge are blended, so only count the mostly red ones.
+    expect_circle(&image, common_colors::RED, 64, (50.0, 50.0), 50.0, 1.0)?;
+
+    // A snapshot can also be cropped to any part of the frame and kept on the
+    // GPU. Nothing changed since the first snapshot, so its frame should come
+    // right away. The middle of the circle is solid red.
+    let middle = Snapshot::new(device, &item)
+        .with_commit(compositor_controller)
+        .with_crop(Crop::Custom(Rect::new(30, 30, 40, 40)))
+        .with_output(OutputKind::ShaderResource)
+        .with_timeout(Duration::from_secs(2))
+        .take()
+        .and_then(TakenSnapshot::into_image)
+        .context("taking a snapshot of the middle of the visual")?;
+    expect_region_color(
+        &middle,
+        Rect::new(0, 0, 40, 40),
+        common_colors::RED,
+        0,
+        None,
+    )?;
+
+    // Everything outside of the circle should be transparent. Leave a pixel of
+    // room for anti-aliasing.
+    let mut circle = Mask::new(image.width(), image.height());
+    circle.ignore_ellipse(Rect::new(-1, -1, 102, 102));
+    expect_region_color(
+        &image,
+        Rect::new(0, 0, 100, 100),
+        common_colors::TRANSPARENT_BLACK,
+        0,
+        Some(&circle),
+    )?;
+
+    // The edge of the circle is anti-aliased, which can vary slightly between GPUs.
+    expect_matches_golden(context, "alpha/red_circle", &image, Tolerance::Ssim(0.99))?;
 
     Ok(())
 }
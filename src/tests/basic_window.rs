@@ -1,26 +1,33 @@
+use wgctest_core::{
+    color::{common_colors, expect_region_color},
+    error::{Context, TestResult, WindowsResultExt},
+    geometry::Rect,
+    golden::expect_matches_golden_masked,
+    mask::Mask,
+    test_runner::TestContext,
+};
 use windows::{
-    Foundation::Numerics::Vector2,
-    Graphics::DirectX::{Direct3D11::IDirect3DDevice, DirectXPixelFormat},
-    System::DispatcherQueue,
-    UI::Composition::Core::CompositorController,
+    Foundation::Numerics::Vector2, Graphics::DirectX::Direct3D11::IDirect3DDevice,
+    System::DispatcherQueue, UI::Composition::Core::CompositorController,
 };
 
 use crate::util::{
-    color::{check_color, common_colors},
-    error::TestResult,
+    graphics_capture::require_capture_support,
     interop::CompositorDesktopInterop,
     mapped::MappedTexture,
-    snapshot::take_snapshot_of_client_area,
+    snapshot::{take_snapshot_of_client_area, OutputKind, Snapshot, TakenSnapshot},
     test_window::TestWindow,
 };
 
 pub fn basic_window_test(
+    context: &TestContext,
     test_thread_queue: &DispatcherQueue,
     compositor_controller: &CompositorController,
     device: &IDirect3DDevice,
 ) -> TestResult<()> {
     let width = 500;
     let height = 500;
+    require_capture_support()?;
 
     // Create and setup the test window
     let window = TestWindow::new_on_thread(
@@ -28,33 +35,51 @@ pub fn basic_window_test(
         "wgctest - Basic Window Test",
         width,
         height,
-    )?;
+    )
+    .during_setup("creating the test window")?;
     let compositor = compositor_controller.Compositor()?;
     let target = compositor.create_desktop_window_target(&window.handle(), false)?;
     let root = compositor.CreateSpriteVisual()?;
     root.SetRelativeSizeAdjustment(Vector2::new(1.0, 1.0))?;
-    root.SetBrush(&compositor.CreateColorBrushWithColor(common_colors::GREEN)?)?;
+    root.SetBrush(&compositor.CreateColorBrushWithColor(common_colors::GREEN.into())?)?;
     target.SetRoot(&root)?;
     compositor_controller.Commit()?;
 
     // Capture the window
-    let frame = take_snapshot_of_client_area(
-        device,
-        DirectXPixelFormat::B8G8R8A8UIntNormalized,
-        true,
-        true,
-        &window.handle(),
+    let TakenSnapshot { output, cursor } = Snapshot::of_client_area(device, &window.handle())?
+        .with_cursor(true)
+        .with_output(OutputKind::CpuImage)
+        .take()
+        .context("taking a snapshot of the client area")?;
+    let image = output.into_image()?;
+    // The cursor is part of the capture, so we need to know where it was.
+    let mut mask = Mask::for_cursor(image.width(), image.height(), cursor);
+    // Some builds draw a border around the captured window, which can overlap
+    // the edges of the client area.
+    mask.ignore_border(3);
+
+    // Check the whole client area
+    expect_region_color(
+        &image,
+        Rect::new(0, 0, width as i32, height as i32),
+        common_colors::GREEN,
+        0,
+        Some(&mask),
     )?;
+    expect_matches_golden_masked(context, "basic_window/client_area", &image, 0, &mask)?;
 
-    // Map the texture and check the image
-    {
-        let mapped = MappedTexture::new(&frame)?;
-        check_color(
-            mapped.read_pixel(width / 2, height / 2).unwrap(),
-            common_colors::GREEN,
-        )
-        .ok(&frame)?;
-    }
+    // The plain wrapper takes the same snapshot into a staging texture. The
+    // cursor hasn't moved, so the same mask applies.
+    let texture = take_snapshot_of_client_area(device, &window.handle())
+        .context("taking a snapshot of the client area into a texture")?;
+    let image = MappedTexture::new(&texture)?.to_cpu_image();
+    expect_region_color(
+        &image,
+        Rect::new(0, 0, width as i32, height as i32),
+        common_colors::GREEN,
+        0,
+        Some(&mask),
+    )?;
 
     Ok(())
 }
@@ -1,6 +1,11 @@
-use std::thread::sleep;
-use std::time::Duration;
-
+use wgctest_core::{
+    async_capture::{block_on, with_timeout},
+    capture::{CaptureBackend, CaptureOptions, CaptureSession},
+    color::{common_colors, Color},
+    error::{Context, TestError, TestResult, WindowsResultExt},
+    scenarios::{expect_transitions, FULLSCREEN_TRANSITIONS},
+    test_runner::TestContext,
+};
 use windows::core::Interface;
 use windows::{
     Graphics::{Capture::GraphicsCaptureItem, DirectX::Direct3D11::IDirect3DDevice},
@@ -18,73 +23,85 @@ use windows::{
                 DXGI_SWAP_EFFECT_FLIP_SEQUENTIAL, DXGI_USAGE_RENDER_TARGET_OUTPUT,
             },
         },
-        UI::WindowsAndMessaging::GetClientRect,
+        UI::WindowsAndMessaging::{GetClientRect, WM_DESTROY},
     },
-    UI::Color,
 };
 
 use crate::util::{
-    graphics_capture::GraphicsCapture,
-    color::{common_colors, test_center_of_surface},
     d3d::get_d3d_interface_from_object,
-    error::TestResult,
+    graphics_capture::{require_capture_support, WgcBackend, FRAME_TIMEOUT},
     interop::GraphicsCaptureItemInterop,
     test_window::TestWindow,
 };
 
 pub fn fullscreen_transition_test(
+    context: &TestContext,
     test_thread_queue: &DispatcherQueue,
     device: &IDirect3DDevice,
 ) -> TestResult<()> {
     let width = 800;
     let height = 600;
+    require_capture_support()?;
     let d3d_device: ID3D11Device = get_d3d_interface_from_object(device)?;
 
     // Create and setup the test window
-    let window = TestWindow::new_on_thread(
+    let mut window = TestWindow::new_on_thread(
         &test_thread_queue,
         "wgctest - Fullscreen Transition Test",
         width,
         height,
-    )?;
-    let mut swap_chain = TestSwapChain::new(&d3d_device, width, height, &window.handle())?;
-    swap_chain.flip(&common_colors::RED)?;
-
-    sleep(Duration::from_millis(500));
+    )
+    .during_setup("creating the test window")?;
+    let mut swap_chain = TestSwapChain::new(&d3d_device, width, height, &window.handle())
+        .during_setup("creating the swap chain")?;
+    swap_chain
+        .flip(&common_colors::RED)
+        .during_setup("presenting the first frame")?;
 
     // Start the capture
     let item = GraphicsCaptureItem::create_for_window(&window.handle())?;
-    let capture = GraphicsCapture::new(device, item)?;
-
-    // The first frame should be red
-    let frame = capture.get_next_frame()?;
-    test_center_of_surface(frame.Surface()?, &common_colors::RED)?;
-
-    // Transition to fullscreen
-    swap_chain.set_fullscreen(true)?;
-    swap_chain.flip(&common_colors::GREEN)?;
-    // Wait for the transition
-    sleep(Duration::from_millis(500));
-
-    // Release the previous frame and get a new one
-    frame.Close()?;
-    let frame = capture.get_next_frame()?;
-
-    // Test for green
-    test_center_of_surface(frame.Surface()?, &common_colors::GREEN)?;
-
-    // Transition to windowed
-    swap_chain.set_fullscreen(false)?;
-    swap_chain.flip(&common_colors::BLUE)?;
-    // Wait for the transition
-    sleep(Duration::from_millis(500));
+    let mut capture = WgcBackend::new(device).start(&item, CaptureOptions::default())?;
+    if let Some(path) = context.recording_path("fullscreen_transition_test") {
+        capture.record_to(path)?;
+    }
 
-    // Release the previous frame and get a new one
-    frame.Close()?;
-    let frame = capture.get_next_frame()?;
+    // The window is red once it's shown, green in fullscreen and blue once it's
+    // back to windowed
+    expect_transitions(
+        context,
+        &mut capture,
+        &FULLSCREEN_TRANSITIONS,
+        FRAME_TIMEOUT,
+        |index| {
+            match index {
+                1 => {
+                    swap_chain.set_fullscreen(true)?;
+                    swap_chain.flip(&common_colors::GREEN)?;
+                }
+                2 => {
+                    swap_chain.set_fullscreen(false)?;
+                    swap_chain.flip(&common_colors::BLUE)?;
+                }
+                _ => {}
+            }
+            Ok(())
+        },
+    )?;
 
-    // Test for blue
-    test_center_of_surface(frame.Surface()?, &common_colors::BLUE)?;
+    // Closing the window should end the capture. Wait for the window to go
+    // first, so that a window that doesn't close isn't blamed on the capture.
+    window.close()?;
+    block_on(with_timeout(
+        window.wait_for_message(WM_DESTROY),
+        FRAME_TIMEOUT,
+    ))
+    .ok_or_else(|| TestError::Timeout {
+        waiting_for: "the window to be destroyed".to_owned(),
+        timeout: FRAME_TIMEOUT,
+    })?;
+    capture
+        .wait_for_item_closed(FRAME_TIMEOUT)
+        .context("closing the window")?;
 
     Ok(())
 }
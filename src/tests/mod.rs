@@ -1,7 +1,9 @@
 mod alpha;
 mod basic_window;
 mod fullscreen_transition;
+mod monitor_capture;
 
 pub use alpha::*;
 pub use basic_window::*;
 pub use fullscreen_transition::*;
+pub use monitor_capture::*;
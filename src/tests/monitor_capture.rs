@@ -0,0 +1,64 @@
+use wgctest_core::{
+    color::{check_image_pixel, common_colors, NEIGHBORHOOD_SIZE},
+    error::{Context, TestResult, WindowsResultExt},
+};
+use windows::{
+    Foundation::Numerics::Vector2, Graphics::DirectX::Direct3D11::IDirect3DDevice,
+    System::DispatcherQueue, UI::Composition::Core::CompositorController,
+};
+
+use crate::util::{
+    graphics_capture::require_capture_support,
+    interop::CompositorDesktopInterop,
+    snapshot::{OutputKind, Snapshot, TakenSnapshot},
+    test_window::TestWindow,
+};
+
+pub fn monitor_capture_test(
+    test_thread_queue: &DispatcherQueue,
+    compositor_controller: &CompositorController,
+    device: &IDirect3DDevice,
+) -> TestResult<()> {
+    let width = 500;
+    let height = 500;
+    require_capture_support()?;
+
+    // Create and setup the test window
+    let window = TestWindow::new_on_thread(
+        test_thread_queue,
+        "wgctest - Monitor Capture Test",
+        width,
+        height,
+    )
+    .during_setup("creating the test window")?;
+    let compositor = compositor_controller.Compositor()?;
+    let target = compositor.create_desktop_window_target(&window.handle(), false)?;
+    let root = compositor.CreateSpriteVisual()?;
+    root.SetRelativeSizeAdjustment(Vector2::new(1.0, 1.0))?;
+    root.SetBrush(&compositor.CreateColorBrushWithColor(common_colors::GREEN.into())?)?;
+    target.SetRoot(&root)?;
+    compositor_controller.Commit()?;
+
+    // Capture the monitor, cropped to the window. Leave out the cursor and the
+    // border drawn around the monitor, neither is part of the window.
+    let image = Snapshot::of_window_on_monitor(device, &window.handle())?
+        .with_cursor(false)
+        .with_border(false)
+        .with_output(OutputKind::CpuImage)
+        .take()
+        .and_then(TakenSnapshot::into_image)
+        .context("taking a snapshot of the monitor")?;
+
+    // The title bar takes up the top of the window, but the center is well
+    // within the client area.
+    check_image_pixel(
+        &image,
+        image.width() / 2,
+        image.height() / 2,
+        common_colors::GREEN,
+        NEIGHBORHOOD_SIZE,
+    )
+    .ok_with_image(&image)?;
+
+    Ok(())
+}
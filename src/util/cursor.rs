@@ -0,0 +1,60 @@
+use wgctest_core::geometry::Rect;
+use windows::Win32::{
+    Graphics::Gdi::{DeleteObject, GetObjectW, BITMAP},
+    UI::WindowsAndMessaging::{
+        GetCursorInfo, GetIconInfo, CURSORINFO, CURSOR_SHOWING, HICON, ICONINFO,
+    },
+};
+
+/// Returns the bounds of the current cursor image in screen coordinates, or
+/// `None` if the cursor is hidden.
+pub fn cursor_bounds() -> windows::core::Result<Option<Rect>> {
+    let mut cursor_info = CURSORINFO {
+        cbSize: std::mem::size_of::<CURSORINFO>() as u32,
+        ..Default::default()
+    };
+    unsafe { GetCursorInfo(&mut cursor_info).ok()? };
+    if cursor_info.flags.0 & CURSOR_SHOWING.0 == 0 || cursor_info.hCursor.is_invalid() {
+        return Ok(None);
+    }
+
+    let mut icon_info = ICONINFO::default();
+    unsafe { GetIconInfo(HICON(cursor_info.hCursor.0), &mut icon_info).ok()? };
+    // Monochrome cursors don't have a color bitmap. Instead, their mask bitmap
+    // holds both the AND and the XOR masks stacked on top of each other.
+    let (bitmap, is_monochrome) = if icon_info.hbmColor.is_invalid() {
+        (icon_info.hbmMask, true)
+    } else {
+        (icon_info.hbmColor, false)
+    };
+    let mut bitmap_info = BITMAP::default();
+    let result = unsafe {
+        GetObjectW(
+            bitmap,
+            std::mem::size_of::<BITMAP>() as i32,
+            Some(&mut bitmap_info as *mut _ as *mut _),
+        )
+    };
+    unsafe {
+        if !icon_info.hbmColor.is_invalid() {
+            DeleteObject(icon_info.hbmColor);
+        }
+        DeleteObject(icon_info.hbmMask);
+    }
+    if result == 0 {
+        return Err(windows::core::Error::from_win32());
+    }
+
+    let width = bitmap_info.bmWidth;
+    let height = if is_monochrome {
+        bitmap_info.bmHeight / 2
+    } else {
+        bitmap_info.bmHeight
+    };
+    Ok(Some(Rect::new(
+        cursor_info.ptScreenPos.x - icon_info.xHotspot as i32,
+        cursor_info.ptScreenPos.y - icon_info.yHotspot as i32,
+        width,
+        height,
+    )))
+}
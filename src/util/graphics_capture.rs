@@ -1,61 +1,393 @@
-use std::sync::mpsc::{channel, Receiver};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
+use wgctest_core::{
+    assertion::{Capture, Readback},
+    async_capture::{block_on, frame_stream, FrameSender, FrameStream, StreamEnd},
+    capture::{
+        CaptureBackend, CaptureFrame, CaptureOptions, CaptureSession, FramePoolKind,
+        InvalidCaptureOptions, Lifecycle, SessionState,
+    },
+    error::{TestError, TestResult, WindowsResultExt},
+    recording::FrameRecorder,
+    resize::{stale_frame_error, FrameSize, ResizeTracker, SizeChange},
+    timing::TimingRecorder,
+};
 use windows::{
-    Foundation::TypedEventHandler,
+    Foundation::{EventRegistrationToken, TimeSpan, TypedEventHandler},
     Graphics::{
         Capture::{
             Direct3D11CaptureFrame, Direct3D11CaptureFramePool, GraphicsCaptureItem,
             GraphicsCaptureSession,
         },
         DirectX::{Direct3D11::IDirect3DDevice, DirectXPixelFormat},
+        SizeInt32,
     },
+    Win32::Graphics::Direct3D11::ID3D11Texture2D,
 };
 
+use super::{d3d::get_d3d_interface_from_object, mapped::TextureReadback};
+
+/// How long to wait for a frame before giving up. Frames normally arrive well
+/// within a second, so hitting this means the capture is stuck.
+pub const FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Skips the test on machines where Windows.Graphics.Capture isn't available.
+pub fn require_capture_support() -> TestResult<()> {
+    if GraphicsCaptureSession::IsSupported().during_setup("checking for capture support")? {
+        Ok(())
+    } else {
+        TestError::Skipped("Windows.Graphics.Capture isn't supported on this machine".to_owned())
+            .ok()
+    }
+}
+
+/// Creates a frame pool for `size` the way `options` ask for.
+pub fn create_frame_pool(
+    device: &IDirect3DDevice,
+    size: SizeInt32,
+    options: &CaptureOptions,
+) -> TestResult<Direct3D11CaptureFramePool> {
+    options.validate()?;
+    let pixel_format = DirectXPixelFormat(options.pixel_format as i32);
+    let buffer_count = options.buffer_count as i32;
+    match options.frame_pool {
+        FramePoolKind::FreeThreaded => {
+            Direct3D11CaptureFramePool::CreateFreeThreaded(device, pixel_format, buffer_count, size)
+        }
+        FramePoolKind::DispatcherQueue => {
+            Direct3D11CaptureFramePool::Create(device, pixel_format, buffer_count, size)
+        }
+    }
+    .during_capture("creating the frame pool")
+}
+
+/// Creates a capture session for `item` and applies the rest of `options`. The
+/// capture isn't started yet.
+pub fn create_session(
+    frame_pool: &Direct3D11CaptureFramePool,
+    item: &GraphicsCaptureItem,
+    options: &CaptureOptions,
+) -> TestResult<GraphicsCaptureSession> {
+    // Both need a newer Windows SDK than the bindings were generated from.
+    // Tests that ask for them fail, rather than silently not running.
+    if options.min_update_interval.is_some() {
+        return Err(InvalidCaptureOptions::Unsupported("a minimum update interval").into());
+    }
+    if options.dirty_region_mode.is_some() {
+        return Err(InvalidCaptureOptions::Unsupported("dirty regions").into());
+    }
+    let session = frame_pool
+        .CreateCaptureSession(item)
+        .during_capture("creating the capture session")?;
+    // Only touch the cursor and the border when asked to, older versions of
+    // Windows don't know about them and always capture the cursor and draw a
+    // border.
+    if !options.cursor_enabled {
+        session
+            .SetIsCursorCaptureEnabled(false)
+            .during_capture("turning off cursor capture")?;
+    }
+    if !options.border_required {
+        session
+            .SetIsBorderRequired(false)
+            .during_capture("turning off the capture border")?;
+    }
+    Ok(session)
+}
+
+/// A frame and when it reached the test.
+pub type ArrivedFrame = (Direct3D11CaptureFrame, Instant);
+
+/// Streams the frames of `frame_pool`. Frames are taken from the pool as soon
+/// as they arrive, so that their arrival time is accurate. Once the stream is
+/// full, they are held until the test takes a frame from the stream. Held
+/// frames keep their buffer, so the pool stops producing frames meanwhile.
+pub fn stream_frames(
+    frame_pool: &Direct3D11CaptureFramePool,
+    options: &CaptureOptions,
+) -> TestResult<FrameStream<ArrivedFrame>> {
+    let (sender, stream) = frame_stream(options.buffer_count as usize);
+    let held = Arc::new(Mutex::new(VecDeque::new()));
+    let handler_held = held.clone();
+    let handler = TypedEventHandler::<Direct3D11CaptureFramePool, windows::core::IInspectable>::new(
+        move |frame_pool, _| {
+            let frame_pool = frame_pool.as_ref().unwrap();
+            let mut held = handler_held.lock().unwrap();
+            // Stops once the pool is empty, or closed.
+            while let Ok(frame) = frame_pool.TryGetNextFrame() {
+                held.push_back((frame, Instant::now()));
+            }
+            send_held_frames(&mut held, &sender);
+            Ok(())
+        },
+    );
+    frame_pool
+        .FrameArrived(&handler)
+        .during_capture("watching for frames")?;
+    Ok(stream.with_refill(move |sender| send_held_frames(&mut held.lock().unwrap(), sender)))
+}
+
+/// Moves held frames to the stream while it has room. A frame the stream turns
+/// away goes back to the front, so frames keep their order.
+fn send_held_frames(held: &mut VecDeque<ArrivedFrame>, sender: &FrameSender<ArrivedFrame>) {
+    while let Some(frame) = held.pop_front() {
+        if let Err(frame) = sender.try_send(frame) {
+            held.push_front(frame);
+            break;
+        }
+    }
+}
+
 pub struct GraphicsCapture {
-    _item: GraphicsCaptureItem,
+    device: IDirect3DDevice,
+    item: GraphicsCaptureItem,
     frame_pool: Direct3D11CaptureFramePool,
     session: GraphicsCaptureSession,
-    receiver: Receiver<Direct3D11CaptureFrame>,
+    frames: FrameStream<ArrivedFrame>,
+    item_closed_token: EventRegistrationToken,
+    lifecycle: Lifecycle,
+    recorder: Option<FrameRecorder<BufWriter<File>>>,
+    timing: TimingRecorder,
+    resize: ResizeTracker,
+    options: CaptureOptions,
+    started: Instant,
 }
 
 impl GraphicsCapture {
-    pub fn new(device: &IDirect3DDevice, item: GraphicsCaptureItem) -> windows::core::Result<Self> {
-        let frame_pool = Direct3D11CaptureFramePool::CreateFreeThreaded(
-            device,
-            DirectXPixelFormat::B8G8R8A8UIntNormalized,
-            1,
-            item.Size()?,
-        )?;
-        let (sender, receiver) = channel();
-        let handler =
-            TypedEventHandler::<Direct3D11CaptureFramePool, windows::core::IInspectable>::new(
-                move |frame_pool, _| -> windows::core::Result<()> {
-                    let frame_pool = frame_pool.as_ref().unwrap();
-                    let frame = frame_pool.TryGetNextFrame()?;
-                    sender.send(frame).unwrap();
-                    Ok(())
-                },
-            );
-        frame_pool.FrameArrived(&handler)?;
-        let session = frame_pool.CreateCaptureSession(&item)?;
-        session.StartCapture()?;
+    pub fn with_options(
+        device: &IDirect3DDevice,
+        item: GraphicsCaptureItem,
+        options: CaptureOptions,
+    ) -> TestResult<Self> {
+        let size = item.Size()?;
+        let frame_pool = create_frame_pool(device, size, &options)?;
+        let frames = stream_frames(&frame_pool, &options)?;
+        let item_closed_sender = frames.sender();
+        let item_closed_token = item
+            .Closed(&TypedEventHandler::new(move |_, _| {
+                // Frames that already arrived are still delivered.
+                item_closed_sender.end(StreamEnd::ItemClosed);
+                Ok(())
+            }))
+            .during_capture("watching for the item to close")?;
+        let session = create_session(&frame_pool, &item, &options)?;
+        session
+            .StartCapture()
+            .during_capture("starting the capture")?;
         Ok(Self {
-            _item: item,
+            device: device.clone(),
+            item,
             frame_pool,
             session,
-            receiver,
+            frames,
+            item_closed_token,
+            lifecycle: Lifecycle::default(),
+            recorder: None,
+            timing: TimingRecorder::default(),
+            resize: ResizeTracker::new(from_size(size)),
+            options,
+            started: Instant::now(),
         })
     }
 
-    pub fn get_next_frame(&self) -> windows::core::Result<Direct3D11CaptureFrame> {
-        let frame = self.receiver.recv().unwrap();
+    /// Records every frame read through `CaptureSession::next_frame` from now on
+    /// to `path`, so that the capture can be replayed with
+    /// `FakeBackend::from_recording`.
+    pub fn record_to<P: AsRef<Path>>(&mut self, path: P) -> TestResult<()> {
+        self.recorder = Some(FrameRecorder::create(path)?);
+        Ok(())
+    }
+
+    /// Waits for the next frame. When the item has changed size, the frame pool
+    /// is recreated to match, so that the frames after this one aren't cropped.
+    /// Fails with `TestError::ItemClosed` once the item has gone away.
+    pub fn get_next_frame_within(
+        &mut self,
+        timeout: Duration,
+    ) -> TestResult<Direct3D11CaptureFrame> {
+        block_on(self.next_frame_within(timeout))
+    }
+
+    /// Like `get_next_frame_within`, but doesn't block, so that the test can
+    /// wait on other things at the same time, e.g. with `race`.
+    pub async fn next_frame_within(
+        &mut self,
+        timeout: Duration,
+    ) -> TestResult<Direct3D11CaptureFrame> {
+        if self.lifecycle.state() != SessionState::Capturing {
+            return Err(self.lifecycle.no_frame(timeout));
+        }
+        let (frame, arrival) = match self.frames.next_within(timeout).await {
+            Ok(frame) => frame,
+            Err(TestError::ItemClosed) => {
+                self.lifecycle.item_closed();
+                return Err(self.lifecycle.no_frame(timeout));
+            }
+            Err(error) => return Err(error),
+        };
+        self.timing.record(
+            from_time_span(frame.SystemRelativeTime()?),
+            arrival - self.started,
+        );
+        match self
+            .resize
+            .on_frame(frame_size(&frame)?, from_size(frame.ContentSize()?))
+        {
+            FrameSize::Current => {}
+            FrameSize::Resized(change) => self
+                .frame_pool
+                .Recreate(
+                    &self.device,
+                    DirectXPixelFormat(self.options.pixel_format as i32),
+                    self.options.buffer_count as i32,
+                    to_size(change.to),
+                )
+                .during_capture("recreating the frame pool")?,
+            FrameSize::Stale {
+                frame_size,
+                pool_size,
+            } => {
+                if self.options.fail_on_stale_frames {
+                    let capture = read_back_frame(&frame)
+                        .ok()
+                        .map(|frame| Capture::Image(frame.image));
+                    return Err(stale_frame_error(frame_size, pool_size, capture));
+                }
+            }
+        }
         Ok(frame)
     }
 }
 
 impl Drop for GraphicsCapture {
+    /// Drops can happen while a test is already failing, so a capture that
+    /// doesn't close cleanly is only reported.
     fn drop(&mut self) {
-        self.session.Close().unwrap();
-        self.frame_pool.Close().unwrap();
+        if let Err(error) = CaptureSession::close(self) {
+            eprintln!("warning: {}", error);
+        }
+    }
+}
+
+/// Captures through Windows.Graphics.Capture. The sessions are plain
+/// `GraphicsCapture`s, which read every frame back into system memory when used
+/// through `CaptureSession`.
+pub struct WgcBackend {
+    device: IDirect3DDevice,
+}
+
+impl WgcBackend {
+    pub fn new(device: &IDirect3DDevice) -> Self {
+        Self {
+            device: device.clone(),
+        }
+    }
+}
+
+impl CaptureBackend for WgcBackend {
+    type Item = GraphicsCaptureItem;
+    type Session = GraphicsCapture;
+
+    fn start(
+        &self,
+        item: &GraphicsCaptureItem,
+        options: CaptureOptions,
+    ) -> TestResult<GraphicsCapture> {
+        // Sessions hand out frames as `CpuImage`s, which only hold BGRA8.
+        options.validate_for_read_back()?;
+        GraphicsCapture::with_options(&self.device, item.clone(), options)
+    }
+}
+
+impl CaptureSession for GraphicsCapture {
+    fn next_frame(&mut self, timeout: Duration) -> TestResult<CaptureFrame> {
+        let frame = read_back_frame(&self.get_next_frame_within(timeout)?)?;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.write_frame(&frame)?;
+        }
+        Ok(frame)
+    }
+
+    fn content_size(&self) -> TestResult<(u32, u32)> {
+        Ok(from_size(self.item.Size()?))
+    }
+
+    fn close(&mut self) -> TestResult<()> {
+        if !self.lifecycle.close() {
+            return Ok(());
+        }
+        self.item
+            .RemoveClosed(self.item_closed_token)
+            .and_then(|_| self.session.Close())
+            .and_then(|_| self.frame_pool.Close())
+            .during_capture("closing the capture session")
+    }
+
+    fn state(&self) -> SessionState {
+        self.lifecycle.state()
+    }
+
+    fn size_changes(&self) -> &[SizeChange] {
+        self.resize.size_changes()
+    }
+
+    fn options(&self) -> CaptureOptions {
+        self.options
+    }
+
+    fn timing(&self) -> &TimingRecorder {
+        &self.timing
+    }
+
+    fn now(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+/// Copies a frame into system memory.
+pub fn read_back_frame(frame: &Direct3D11CaptureFrame) -> TestResult<CaptureFrame> {
+    let texture: ID3D11Texture2D = get_d3d_interface_from_object(&frame.Surface()?)
+        .during_capture("getting the frame's texture")?;
+    let image = TextureReadback(texture).read_back()?;
+    Ok(CaptureFrame {
+        image,
+        timestamp: from_time_span(frame.SystemRelativeTime()?),
+        content_size: from_size(frame.ContentSize()?),
+        // The bindings don't expose dirty regions yet, and `create_session`
+        // rejects sessions that ask for them.
+        dirty_regions: None,
+    })
+}
+
+/// The size of the frame's buffer, which is the size of the frame pool when the
+/// buffer was allocated.
+pub fn frame_size(frame: &Direct3D11CaptureFrame) -> TestResult<(u32, u32)> {
+    let description = frame.Surface()?.Description()?;
+    Ok((
+        description.Width.max(0) as u32,
+        description.Height.max(0) as u32,
+    ))
+}
+
+fn from_time_span(time: TimeSpan) -> Duration {
+    // TimeSpan counts in 100ns ticks.
+    Duration::from_nanos(time.Duration.max(0) as u64 * 100)
+}
+
+pub fn from_size(size: SizeInt32) -> (u32, u32) {
+    (size.Width.max(0) as u32, size.Height.max(0) as u32)
+}
+
+pub fn to_size((width, height): (u32, u32)) -> SizeInt32 {
+    SizeInt32 {
+        Width: width as i32,
+        Height: height as i32,
     }
 }
@@ -3,6 +3,7 @@ use windows::{
     Graphics::Capture::GraphicsCaptureItem,
     Win32::{
         Foundation::HWND,
+        Graphics::Gdi::HMONITOR,
         System::WinRT::{
             Composition::ICompositorDesktopInterop, Graphics::Capture::IGraphicsCaptureItemInterop,
         },
@@ -31,6 +32,7 @@ impl CompositorDesktopInterop for Compositor {
 
 pub trait GraphicsCaptureItemInterop {
     fn create_for_window(window_handle: &HWND) -> windows::core::Result<GraphicsCaptureItem>;
+    fn create_for_monitor(monitor_handle: &HMONITOR) -> windows::core::Result<GraphicsCaptureItem>;
 }
 
 impl GraphicsCaptureItemInterop for GraphicsCaptureItem {
@@ -38,4 +40,9 @@ impl GraphicsCaptureItemInterop for GraphicsCaptureItem {
         let interop = windows::core::factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>()?;
         unsafe { interop.CreateForWindow(*window_handle) }
     }
+
+    fn create_for_monitor(monitor_handle: &HMONITOR) -> windows::core::Result<GraphicsCaptureItem> {
+        let interop = windows::core::factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>()?;
+        unsafe { interop.CreateForMonitor(*monitor_handle) }
+    }
 }
@@ -1,15 +1,20 @@
+use wgctest_core::{
+    assertion::{Capture, Readback},
+    color::Color,
+    cpu_image::CpuImage,
+    error::TestResult,
+};
 use windows::core::Interface;
-use windows::{
-    Win32::Graphics::{
-        Direct3D11::{
-            ID3D11DeviceContext, ID3D11Resource, ID3D11Texture2D, D3D11_MAPPED_SUBRESOURCE,
-            D3D11_MAP_READ, D3D11_TEXTURE2D_DESC,
-        },
-        Dxgi::Common::DXGI_FORMAT_B8G8R8A8_UNORM,
+use windows::Win32::Graphics::{
+    Direct3D11::{
+        ID3D11DeviceContext, ID3D11Resource, ID3D11Texture2D, D3D11_MAPPED_SUBRESOURCE,
+        D3D11_MAP_READ, D3D11_TEXTURE2D_DESC,
     },
-    UI::Color,
+    Dxgi::Common::DXGI_FORMAT_B8G8R8A8_UNORM,
 };
 
+use super::d3d::copy_texture;
+
 pub struct MappedTexture<'a> {
     d3d_context: ID3D11DeviceContext,
     texture: &'a ID3D11Texture2D,
@@ -46,6 +51,10 @@ impl<'a> MappedTexture<'a> {
         })
     }
 
+    pub fn size(&self) -> (u32, u32) {
+        (self.texture_desc.Width, self.texture_desc.Height)
+    }
+
     pub fn read_pixel(&self, x: u32, y: u32) -> Option<Color> {
         if x < self.texture_desc.Width && y < self.texture_desc.Height {
             let bytes_per_pixel = 4;
@@ -71,6 +80,26 @@ impl<'a> MappedTexture<'a> {
             None
         }
     }
+
+    pub fn to_cpu_image(&self) -> CpuImage {
+        let bytes_per_pixel = 4;
+        let row_size = (self.texture_desc.Width * bytes_per_pixel) as usize;
+        // Get a slice of bytes
+        let data: &[u8] = unsafe {
+            std::slice::from_raw_parts(
+                self.mapped_data.pData as *const _,
+                (self.texture_desc.Height * self.mapped_data.RowPitch) as usize,
+            )
+        };
+        let mut bytes = Vec::with_capacity(row_size * self.texture_desc.Height as usize);
+        for row in data
+            .chunks(self.mapped_data.RowPitch as usize)
+            .take(self.texture_desc.Height as usize)
+        {
+            bytes.extend_from_slice(&row[..row_size]);
+        }
+        CpuImage::from_bgra8(self.texture_desc.Width, self.texture_desc.Height, bytes)
+    }
 }
 
 impl<'a> Drop for MappedTexture<'a> {
@@ -78,3 +107,31 @@ impl<'a> Drop for MappedTexture<'a> {
         unsafe { self.d3d_context.Unmap(self.texture, 0) };
     }
 }
+
+/// A texture attached to a failed check. It is only copied back to system
+/// memory if the failure gets reported.
+#[derive(Debug)]
+pub struct TextureReadback(pub ID3D11Texture2D);
+
+impl Readback for TextureReadback {
+    fn read_back(&self) -> TestResult<CpuImage> {
+        let texture = &self.0;
+        let d3d_device = unsafe {
+            let mut d3d_device = None;
+            texture.GetDevice(&mut d3d_device);
+            d3d_device.unwrap()
+        };
+        let d3d_context = unsafe {
+            let mut d3d_context = None;
+            d3d_device.GetImmediateContext(&mut d3d_context);
+            d3d_context.unwrap()
+        };
+        let staging_texture = copy_texture(&d3d_device, &d3d_context, texture, true)?;
+        let mapped = MappedTexture::new(&staging_texture)?;
+        Ok(mapped.to_cpu_image())
+    }
+}
+
+pub fn texture_capture(texture: &ID3D11Texture2D) -> Capture {
+    Capture::Texture(Box::new(TextureReadback(texture.clone())))
+}
@@ -1,12 +1,10 @@
-pub mod graphics_capture;
 pub mod color;
+pub mod cursor;
 pub mod d3d;
-pub mod error;
-pub mod image;
+pub mod graphics_capture;
+pub mod handle;
 pub mod interop;
 pub mod mapped;
+pub mod monitor;
 pub mod snapshot;
-#[macro_use]
-pub mod test_runner;
-pub mod handle;
 pub mod test_window;
@@ -0,0 +1,96 @@
+use wgctest_core::{desktop::MonitorDescriptor, geometry::Rect};
+use windows::Win32::{
+    Foundation::{BOOL, HWND, LPARAM, RECT},
+    Graphics::{
+        Dwm::{DwmGetWindowAttribute, DWMWA_EXTENDED_FRAME_BOUNDS},
+        Gdi::{
+            EnumDisplayMonitors, GetMonitorInfoW, MonitorFromWindow, HDC, HMONITOR, MONITORINFO,
+            MONITOR_DEFAULTTONEAREST,
+        },
+    },
+    UI::{
+        HiDpi::{GetDpiForMonitor, MDT_EFFECTIVE_DPI},
+        WindowsAndMessaging::MONITORINFOF_PRIMARY,
+    },
+};
+
+/// A monitor that can be captured.
+#[derive(Clone, Copy, Debug)]
+pub struct DisplayMonitor {
+    pub handle: HMONITOR,
+    pub descriptor: MonitorDescriptor,
+}
+
+impl DisplayMonitor {
+    pub fn from_handle(handle: HMONITOR) -> windows::core::Result<Self> {
+        let mut info = MONITORINFO {
+            cbSize: std::mem::size_of::<MONITORINFO>() as u32,
+            ..Default::default()
+        };
+        unsafe { GetMonitorInfoW(handle, &mut info).ok()? };
+        let (mut dpi_x, mut dpi_y) = (0, 0);
+        unsafe { GetDpiForMonitor(handle, MDT_EFFECTIVE_DPI, &mut dpi_x, &mut dpi_y)? };
+        Ok(Self {
+            handle,
+            descriptor: MonitorDescriptor {
+                bounds: from_rect(&info.rcMonitor),
+                work_area: from_rect(&info.rcWork),
+                dpi: dpi_x,
+                is_primary: info.dwFlags & MONITORINFOF_PRIMARY != 0,
+            },
+        })
+    }
+
+    /// The monitor that shows most of the window.
+    pub fn for_window(window_handle: &HWND) -> windows::core::Result<Self> {
+        Self::from_handle(unsafe { MonitorFromWindow(*window_handle, MONITOR_DEFAULTTONEAREST) })
+    }
+}
+
+/// Every monitor attached to the desktop, in the order Windows lists them.
+pub fn enumerate_monitors() -> windows::core::Result<Vec<DisplayMonitor>> {
+    unsafe extern "system" fn callback(
+        monitor: HMONITOR,
+        _hdc: HDC,
+        _rect: *mut RECT,
+        data: LPARAM,
+    ) -> BOOL {
+        let handles = &mut *(data.0 as *mut Vec<HMONITOR>);
+        handles.push(monitor);
+        true.into()
+    }
+
+    let mut handles: Vec<HMONITOR> = Vec::new();
+    unsafe {
+        EnumDisplayMonitors(
+            HDC::default(),
+            None,
+            Some(callback),
+            LPARAM(&mut handles as *mut _ as isize),
+        )
+        .ok()?
+    };
+    handles
+        .into_iter()
+        .map(DisplayMonitor::from_handle)
+        .collect()
+}
+
+/// The window as it appears on screen, without the invisible resize borders,
+/// in virtual desktop coordinates.
+pub fn window_bounds(window_handle: &HWND) -> windows::core::Result<Rect> {
+    let mut bounds = RECT::default();
+    unsafe {
+        DwmGetWindowAttribute(
+            *window_handle,
+            DWMWA_EXTENDED_FRAME_BOUNDS,
+            &mut bounds as *mut _ as *mut _,
+            std::mem::size_of::<RECT>() as u32,
+        )?
+    };
+    Ok(from_rect(&bounds))
+}
+
+fn from_rect(rect: &RECT) -> Rect {
+    Rect::from_edges(rect.left, rect.top, rect.right, rect.bottom)
+}
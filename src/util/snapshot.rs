@@ -1,15 +1,23 @@
-use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
 
-use windows::core::Interface;
+use wgctest_core::{
+    assertion::Readback,
+    async_capture::block_on,
+    capture::CaptureOptions,
+    cpu_image::CpuImage,
+    desktop::window_off_monitor_error,
+    error::{TestError, TestResult, WindowsResultExt},
+    geometry::{client_in_frame, Rect},
+    resize::{FrameSize, ResizeTracker},
+};
+use windows::core::{Interface, HSTRING};
 use windows::{
-    Foundation::TypedEventHandler,
     Graphics::{
-        Capture::{Direct3D11CaptureFramePool, GraphicsCaptureItem},
+        Capture::GraphicsCaptureItem,
         DirectX::{Direct3D11::IDirect3DDevice, DirectXPixelFormat},
-        RectInt32,
     },
     Win32::{
-        Foundation::{HWND, POINT, RECT},
+        Foundation::{E_INVALIDARG, HWND, POINT, RECT},
         Graphics::{
             Direct3D11::{
                 ID3D11Device, ID3D11Resource, ID3D11Texture2D, D3D11_BIND_FLAG,
@@ -17,7 +25,6 @@ use windows::{
                 D3D11_CPU_ACCESS_READ, D3D11_RESOURCE_MISC_FLAG, D3D11_TEXTURE2D_DESC,
                 D3D11_USAGE_DEFAULT, D3D11_USAGE_STAGING,
             },
-            Dwm::{DwmGetWindowAttribute, DWMWA_EXTENDED_FRAME_BOUNDS},
             Gdi::ClientToScreen,
         },
         UI::WindowsAndMessaging::GetClientRect,
@@ -25,163 +32,425 @@ use windows::{
     UI::Composition::Core::CompositorController,
 };
 
-use super::{d3d::get_d3d_interface_from_object, interop::GraphicsCaptureItemInterop};
+use super::{
+    cursor::cursor_bounds,
+    d3d::get_d3d_interface_from_object,
+    graphics_capture::{
+        create_frame_pool, create_session, frame_size, from_size, stream_frames, to_size,
+        FRAME_TIMEOUT,
+    },
+    interop::GraphicsCaptureItemInterop,
+    mapped::{MappedTexture, TextureReadback},
+    monitor::{enumerate_monitors, window_bounds, DisplayMonitor},
+};
+
+/// Which part of the captured frame a snapshot keeps.
+#[derive(Clone, Copy, Debug)]
+pub enum Crop {
+    /// The whole frame.
+    None,
+    /// The client area of the window, leaving out the title bar and borders.
+    /// The window's position is looked up when the snapshot is taken.
+    ClientArea(HWND),
+    /// The window as it appears on screen, for a capture of the monitor it is
+    /// on. The window's position is looked up when the snapshot is taken.
+    WindowOnMonitor(HWND),
+    /// A rectangle relative to the top left corner of the frame.
+    Custom(Rect),
+}
+
+/// What a snapshot is copied into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputKind {
+    /// A texture the CPU can map, e.g. with `MappedTexture`.
+    Staging,
+    /// A texture that can be bound to the pipeline.
+    ShaderResource,
+    /// A `CpuImage` in system memory.
+    CpuImage,
+}
+
+/// A taken snapshot, in the form asked for with `Snapshot::with_output`.
+#[derive(Debug)]
+pub enum SnapshotOutput {
+    Texture(ID3D11Texture2D),
+    Image(CpuImage),
+}
+
+impl SnapshotOutput {
+    /// Fails if the snapshot was taken with `OutputKind::CpuImage`.
+    pub fn into_texture(self) -> TestResult<ID3D11Texture2D> {
+        match self {
+            SnapshotOutput::Texture(texture) => Ok(texture),
+            SnapshotOutput::Image(_) => Err(windows::core::Error::new(
+                E_INVALIDARG,
+                HSTRING::from("the snapshot was taken as a CPU image"),
+            ))
+            .during_setup("getting the snapshot's texture"),
+        }
+    }
+
+    /// Reads a texture back if needed.
+    pub fn into_image(self) -> TestResult<CpuImage> {
+        match self {
+            SnapshotOutput::Texture(texture) => TextureReadback(texture).read_back(),
+            SnapshotOutput::Image(image) => Ok(image),
+        }
+    }
+}
+
+/// A snapshot along with where the cursor was when its frame arrived.
+#[derive(Debug)]
+pub struct TakenSnapshot {
+    pub output: SnapshotOutput,
+    /// The cursor's bounds relative to the snapshot, or `None` if it was
+    /// hidden, cursor capture was off or the snapshot's position on screen
+    /// isn't known. Can reach past the edges of the snapshot.
+    pub cursor: Option<Rect>,
+}
+
+impl TakenSnapshot {
+    /// See `SnapshotOutput::into_texture`.
+    pub fn into_texture(self) -> TestResult<ID3D11Texture2D> {
+        self.output.into_texture()
+    }
+
+    /// See `SnapshotOutput::into_image`.
+    pub fn into_image(self) -> TestResult<CpuImage> {
+        self.output.into_image()
+    }
+}
+
+/// Takes a single frame of a capture item, e.g.
+/// `Snapshot::of_client_area(device, &hwnd)?.with_cursor(false).take()?`.
+/// By default the whole frame is copied into a staging texture, with the cursor
+/// and the capture border drawn.
+pub struct Snapshot {
+    device: IDirect3DDevice,
+    item: GraphicsCaptureItem,
+    crop: Crop,
+    output: OutputKind,
+    options: CaptureOptions,
+    timeout: Duration,
+    commit: Option<CompositorController>,
+}
+
+impl Snapshot {
+    pub fn new(device: &IDirect3DDevice, item: &GraphicsCaptureItem) -> Self {
+        Self {
+            device: device.clone(),
+            item: item.clone(),
+            crop: Crop::None,
+            output: OutputKind::Staging,
+            options: CaptureOptions::default(),
+            timeout: FRAME_TIMEOUT,
+            commit: None,
+        }
+    }
+
+    /// Captures the window and crops the frame to its client area.
+    pub fn of_client_area(device: &IDirect3DDevice, window_handle: &HWND) -> TestResult<Self> {
+        let item = GraphicsCaptureItem::create_for_window(window_handle)
+            .during_capture("creating a capture item for the window")?;
+        Ok(Self::new(device, &item).with_crop(Crop::ClientArea(*window_handle)))
+    }
+
+    /// Captures the monitor the window is on and crops the frame to the part
+    /// of the monitor the window covers, including whatever is on top of it.
+    pub fn of_window_on_monitor(
+        device: &IDirect3DDevice,
+        window_handle: &HWND,
+    ) -> TestResult<Self> {
+        let monitor = DisplayMonitor::for_window(window_handle)
+            .during_capture("finding the window's monitor")?;
+        let item = GraphicsCaptureItem::create_for_monitor(&monitor.handle)
+            .during_capture("creating a capture item for the monitor")?;
+        Ok(Self::new(device, &item).with_crop(Crop::WindowOnMonitor(*window_handle)))
+    }
+
+    pub fn with_crop(mut self, crop: Crop) -> Self {
+        self.crop = crop;
+        self
+    }
+
+    pub fn with_output(mut self, output: OutputKind) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn with_cursor(mut self, cursor_enabled: bool) -> Self {
+        self.options.cursor_enabled = cursor_enabled;
+        self
+    }
+
+    pub fn with_border(mut self, border_required: bool) -> Self {
+        self.options.border_required = border_required;
+        self
+    }
+
+    /// How long to wait for the frame. Defaults to `FRAME_TIMEOUT`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Commits `compositor_controller` once the capture has started, so that
+    /// the frame shows the latest changes to a visual tree.
+    pub fn with_commit(mut self, compositor_controller: &CompositorController) -> Self {
+        self.commit = Some(compositor_controller.clone());
+        self
+    }
+
+    pub fn take(&self) -> TestResult<TakenSnapshot> {
+        block_on(self.take_async())
+    }
+
+    /// Like `take`, but waits for the frame without blocking the thread.
+    pub async fn take_async(&self) -> TestResult<TakenSnapshot> {
+        // Where the top left corner of the snapshot is on screen, if known, so
+        // that the cursor can be placed in it.
+        let (rect, screen_origin) = match &self.crop {
+            Crop::None => (None, None),
+            Crop::ClientArea(window_handle) => {
+                let (rect, client_origin) = client_area_rect(window_handle)?;
+                (Some(rect), Some(client_origin))
+            }
+            Crop::WindowOnMonitor(window_handle) => {
+                let (rect, screen_origin) = window_on_monitor_rect(window_handle)?;
+                (Some(rect), Some(screen_origin))
+            }
+            Crop::Custom(rect) => (Some(*rect), None),
+        };
+
+        let d3d_device: ID3D11Device = get_d3d_interface_from_object(&self.device)?;
+        let d3d_context = unsafe {
+            let mut d3d_context = None;
+            d3d_device.GetImmediateContext(&mut d3d_context);
+            d3d_context.unwrap()
+        };
+
+        let item_size = self.item.Size()?;
+        let frame_pool = create_frame_pool(&self.device, item_size, &self.options)?;
+        let session = create_session(&frame_pool, &self.item, &self.options)?;
 
+        let mut frames = stream_frames(&frame_pool, &self.options)?;
+        session
+            .StartCapture()
+            .during_capture("starting the capture")?;
+        if let Some(compositor_controller) = &self.commit {
+            compositor_controller.Commit()?;
+        }
+
+        // If the item changed size since the frame pool was created, its frames
+        // would be cropped, so recreate the pool and wait for a frame that fits.
+        let mut resize = ResizeTracker::new(from_size(item_size));
+        let deadline = Instant::now() + self.timeout;
+        let frame = loop {
+            let (frame, _) = match frames
+                .next_within(deadline.saturating_duration_since(Instant::now()))
+                .await
+            {
+                Ok(frame) => frame,
+                // Report the whole wait, rather than what was left of it.
+                Err(TestError::Timeout { .. }) => {
+                    return Err(TestError::Timeout {
+                        waiting_for: "the snapshot's frame".to_owned(),
+                        timeout: self.timeout,
+                    })
+                }
+                Err(error) => return Err(error),
+            };
+            match resize.on_frame(frame_size(&frame)?, from_size(frame.ContentSize()?)) {
+                FrameSize::Current => break frame,
+                FrameSize::Resized(change) => frame_pool
+                    .Recreate(
+                        &self.device,
+                        DirectXPixelFormat(self.options.pixel_format as i32),
+                        self.options.buffer_count as i32,
+                        to_size(change.to),
+                    )
+                    .during_capture("recreating the frame pool")?,
+                FrameSize::Stale { .. } => {}
+            }
+            frame.Close()?;
+        };
+        // Read the cursor right away, before it has a chance to move further
+        // away from where the frame shows it.
+        let cursor = match screen_origin {
+            Some(origin) if self.options.cursor_enabled => cursor_bounds()
+                .during_capture("finding the cursor")?
+                .map(|cursor| cursor.relative_to(origin)),
+            _ => None,
+        };
+        let result_texture = unsafe {
+            let source_texture: ID3D11Texture2D = get_d3d_interface_from_object(&frame.Surface()?)?;
+            let mut desc = D3D11_TEXTURE2D_DESC::default();
+            source_texture.GetDesc(&mut desc);
+            // A crop that reaches past the frame would make the copy read out
+            // of bounds. The frame can be smaller than the window's bounds
+            // were, e.g. if the window shrank in between.
+            let rect = match &rect {
+                Some(crop) => Some(crop.crop_of((desc.Width, desc.Height))?),
+                None => None,
+            };
+            desc.MiscFlags = D3D11_RESOURCE_MISC_FLAG(0);
+            if self.output == OutputKind::ShaderResource {
+                desc.Usage = D3D11_USAGE_DEFAULT;
+                desc.BindFlags = D3D11_BIND_SHADER_RESOURCE;
+                desc.CPUAccessFlags = D3D11_CPU_ACCESS_FLAG(0);
+            } else {
+                desc.Usage = D3D11_USAGE_STAGING;
+                desc.BindFlags = D3D11_BIND_FLAG(0);
+                desc.CPUAccessFlags = D3D11_CPU_ACCESS_READ;
+            }
+            if let Some(rect) = &rect {
+                desc.Width = rect.width as u32;
+                desc.Height = rect.height as u32;
+            }
+            let texture = d3d_device.CreateTexture2D(&desc, None)?;
+            let resource: ID3D11Resource = texture.cast()?;
+            let source_resource: ID3D11Resource = source_texture.cast()?;
+            if let Some(rect) = &rect {
+                let d3d_box = D3D11_BOX {
+                    left: rect.x as u32,
+                    top: rect.y as u32,
+                    front: 0,
+                    right: rect.right() as u32,
+                    bottom: rect.bottom() as u32,
+                    back: 1,
+                };
+                d3d_context.CopySubresourceRegion(
+                    &resource,
+                    0,
+                    0,
+                    0,
+                    0,
+                    &source_resource,
+                    0,
+                    Some(&d3d_box),
+                );
+            } else {
+                d3d_context.CopyResource(&resource, &source_resource);
+            }
+
+            texture
+        };
+
+        session.Close()?;
+        frame_pool.Close()?;
+        frame.Close()?;
+
+        let output = match self.output {
+            OutputKind::CpuImage => {
+                SnapshotOutput::Image(MappedTexture::new(&result_texture)?.to_cpu_image())
+            }
+            OutputKind::Staging | OutputKind::ShaderResource => {
+                SnapshotOutput::Texture(result_texture)
+            }
+        };
+        Ok(TakenSnapshot { output, cursor })
+    }
+}
+
+/// Takes a snapshot of a visual tree into a staging texture, committing
+/// `compositor_controller` once the capture has started.
 pub fn take_snapshot_with_commit(
     device: &IDirect3DDevice,
     item: &GraphicsCaptureItem,
-    pixel_format: DirectXPixelFormat,
-    staging_texture: bool,
-    cursor_enabled: bool,
     compositor_controller: &CompositorController,
-) -> windows::core::Result<ID3D11Texture2D> {
-    let texture = take_snapshot_internal(
+) -> TestResult<ID3D11Texture2D> {
+    block_on(take_snapshot_with_commit_async(
         device,
         item,
-        pixel_format,
-        staging_texture,
-        cursor_enabled,
-        None,
-        || -> windows::core::Result<()> { compositor_controller.Commit() },
-    )?;
-    Ok(texture)
+        compositor_controller,
+    ))
 }
 
+/// The async version of `take_snapshot_with_commit`.
+pub async fn take_snapshot_with_commit_async(
+    device: &IDirect3DDevice,
+    item: &GraphicsCaptureItem,
+    compositor_controller: &CompositorController,
+) -> TestResult<ID3D11Texture2D> {
+    Snapshot::new(device, item)
+        .with_commit(compositor_controller)
+        .take_async()
+        .await
+        .and_then(TakenSnapshot::into_texture)
+}
+
+/// Takes a snapshot of the client area of a window into a staging texture.
 pub fn take_snapshot_of_client_area(
     device: &IDirect3DDevice,
-    pixel_format: DirectXPixelFormat,
-    staging_texture: bool,
-    cursor_enabled: bool,
     window_handle: &HWND,
-) -> windows::core::Result<ID3D11Texture2D> {
-    let mut client_rect = RECT::default();
-    unsafe { GetClientRect(*window_handle, &mut client_rect).ok()? };
-
-    let mut client_origin = POINT {
-        x: client_rect.left,
-        y: client_rect.top,
-    };
-    unsafe { ClientToScreen(*window_handle, &mut client_origin).ok()? };
-
-    let mut window_bounds = RECT::default();
-    unsafe {
-        DwmGetWindowAttribute(
-            *window_handle,
-            DWMWA_EXTENDED_FRAME_BOUNDS,
-            &mut window_bounds as *mut _ as *mut _,
-            std::mem::size_of::<RECT>() as u32,
-        )?
-    };
-
-    let rect = RectInt32 {
-        X: client_origin.x - window_bounds.left,
-        Y: client_origin.y - window_bounds.top,
-        Width: client_rect.right - client_rect.left,
-        Height: client_rect.bottom - client_rect.top,
-    };
-
-    let item = GraphicsCaptureItem::create_for_window(window_handle)?;
-    let texture = take_snapshot_internal(
-        device,
-        &item,
-        pixel_format,
-        staging_texture,
-        cursor_enabled,
-        Some(rect),
-        || -> windows::core::Result<()> { Ok(()) },
-    )?;
-    Ok(texture)
+) -> TestResult<ID3D11Texture2D> {
+    block_on(take_snapshot_of_client_area_async(device, window_handle))
 }
 
-fn take_snapshot_internal<F: Fn() -> windows::core::Result<()>>(
+/// The async version of `take_snapshot_of_client_area`.
+pub async fn take_snapshot_of_client_area_async(
     device: &IDirect3DDevice,
-    item: &GraphicsCaptureItem,
-    pixel_format: DirectXPixelFormat,
-    staging_texture: bool,
-    cursor_enabled: bool,
-    rect: Option<RectInt32>,
-    started: F,
-) -> windows::core::Result<ID3D11Texture2D> {
-    let item_size = item.Size()?;
-
-    let d3d_device: ID3D11Device = get_d3d_interface_from_object(device)?;
-    let d3d_context = unsafe {
-        let mut d3d_context = None;
-        d3d_device.GetImmediateContext(&mut d3d_context);
-        d3d_context.unwrap()
-    };
-
-    let frame_pool =
-        Direct3D11CaptureFramePool::CreateFreeThreaded(device, pixel_format, 1, item_size)?;
-    let session = frame_pool.CreateCaptureSession(item)?;
-    if !cursor_enabled {
-        session.SetIsCursorCaptureEnabled(false)?;
-    }
+    window_handle: &HWND,
+) -> TestResult<ID3D11Texture2D> {
+    Snapshot::of_client_area(device, window_handle)?
+        .take_async()
+        .await
+        .and_then(TakenSnapshot::into_texture)
+}
 
-    let (sender, receiver) = channel();
-    let handler = TypedEventHandler::<Direct3D11CaptureFramePool, windows::core::IInspectable>::new(
-        move |frame_pool, _| {
-            let frame_pool = frame_pool.as_ref().unwrap();
-            let frame = frame_pool.TryGetNextFrame()?;
-            sender.send(frame).unwrap();
-            Ok(())
-        },
-    );
-    frame_pool.FrameArrived(&handler)?;
-    session.StartCapture()?;
-    started()?;
-
-    let frame = receiver.recv().unwrap();
-    let result_texture = unsafe {
-        let source_texture: ID3D11Texture2D = get_d3d_interface_from_object(&frame.Surface()?)?;
-        let mut desc = D3D11_TEXTURE2D_DESC::default();
-        source_texture.GetDesc(&mut desc);
-        desc.MiscFlags = D3D11_RESOURCE_MISC_FLAG(0);
-        if staging_texture {
-            desc.Usage = D3D11_USAGE_STAGING;
-            desc.BindFlags = D3D11_BIND_FLAG(0);
-            desc.CPUAccessFlags = D3D11_CPU_ACCESS_READ;
-        } else {
-            desc.Usage = D3D11_USAGE_DEFAULT;
-            desc.BindFlags = D3D11_BIND_SHADER_RESOURCE;
-            desc.CPUAccessFlags = D3D11_CPU_ACCESS_FLAG(0);
-        }
-        if let Some(rect) = &rect {
-            desc.Width = rect.Width as u32;
-            desc.Height = rect.Height as u32;
+/// The part of the monitor the window covers, relative to the monitor's top
+/// left corner, along with where that part starts on screen. Fails if the
+/// window is entirely off the monitor, e.g. off screen.
+fn window_on_monitor_rect(window_handle: &HWND) -> TestResult<(Rect, (i32, i32))> {
+    let monitor =
+        DisplayMonitor::for_window(window_handle).during_capture("finding the window's monitor")?;
+    let bounds = window_bounds(window_handle).during_capture("finding the window's bounds")?;
+    match monitor.descriptor.crop_for(&bounds) {
+        Some(rect) => {
+            let on_screen = monitor.descriptor.to_desktop(&rect);
+            Ok((rect, (on_screen.x, on_screen.y)))
         }
-        let texture = d3d_device.CreateTexture2D(&desc, None)?;
-        let resource: ID3D11Resource = texture.cast()?;
-        let source_resource: ID3D11Resource = source_texture.cast()?;
-        if let Some(rect) = &rect {
-            let d3d_box = D3D11_BOX {
-                left: rect.X as u32,
-                top: rect.Y as u32,
-                front: 0,
-                right: (rect.X + rect.Width) as u32,
-                bottom: (rect.Y + rect.Height) as u32,
-                back: 1,
-            };
-            d3d_context.CopySubresourceRegion(
-                &resource,
-                0,
-                0,
-                0,
-                0,
-                &source_resource,
-                0,
-                Some(&d3d_box),
-            );
-        } else {
-            d3d_context.CopyResource(&resource, &source_resource);
+        None => {
+            let monitors: Vec<_> = enumerate_monitors()
+                .during_capture("listing the monitors")?
+                .into_iter()
+                .map(|monitor| monitor.descriptor)
+                .collect();
+            Err(window_off_monitor_error(
+                &monitor.descriptor,
+                &monitors,
+                &bounds,
+            ))
         }
+    }
+}
 
-        texture
-    };
+/// The client area of the window, relative to the top left corner of what
+/// Windows.Graphics.Capture captures of it, along with where the client area
+/// starts on screen. Fails if the client area isn't all in the window's bounds.
+fn client_area_rect(window_handle: &HWND) -> TestResult<(Rect, (i32, i32))> {
+    let (client_origin, client_size) =
+        client_area_on_screen(window_handle).during_capture("finding the client area")?;
+    let bounds = window_bounds(window_handle).during_capture("finding the window's bounds")?;
+    let rect = client_in_frame(&bounds, client_origin, client_size)?;
+    Ok((rect, client_origin))
+}
 
-    session.Close()?;
-    frame_pool.Close()?;
-    frame.Close()?;
+/// The top left corner of the window's client area on screen, and its size.
+fn client_area_on_screen(window_handle: &HWND) -> windows::core::Result<((i32, i32), (i32, i32))> {
+    let mut client_rect = RECT::default();
+    unsafe { GetClientRect(*window_handle, &mut client_rect).ok()? };
 
-    Ok(result_texture)
+    let mut client_origin = POINT {
+        x: client_rect.left,
+        y: client_rect.top,
+    };
+    unsafe { ClientToScreen(*window_handle, &mut client_origin).ok()? };
+    Ok((
+        (client_origin.x, client_origin.y),
+        (
+            client_rect.right - client_rect.left,
+            client_rect.bottom - client_rect.top,
+        ),
+    ))
 }
@@ -1,6 +1,7 @@
 use std::sync::mpsc::channel;
 use std::sync::Once;
 
+use wgctest_core::async_capture::{message_queue, MessageQueue, MessageSender};
 use windows::core::HSTRING;
 use windows::h;
 use windows::System::{DispatcherQueue, DispatcherQueueHandler};
@@ -9,8 +10,8 @@ use windows::Win32::System::LibraryLoader::GetModuleHandleW;
 use windows::Win32::UI::WindowsAndMessaging::{
     AdjustWindowRectEx, CreateWindowExW, DefWindowProcW, DestroyWindow, GetWindowLongPtrW,
     LoadCursorW, RegisterClassW, SetWindowLongPtrW, ShowWindow, CREATESTRUCTW, CW_USEDEFAULT,
-    GWLP_USERDATA, HMENU, IDC_ARROW, SW_SHOW, WM_NCCREATE, WNDCLASSW, WS_EX_NOREDIRECTIONBITMAP,
-    WS_OVERLAPPEDWINDOW,
+    GWLP_USERDATA, HMENU, IDC_ARROW, SW_SHOW, WM_NCCREATE, WM_NCDESTROY, WNDCLASSW,
+    WS_EX_NOREDIRECTIONBITMAP, WS_OVERLAPPEDWINDOW,
 };
 
 use super::handle::CheckHandle;
@@ -21,6 +22,7 @@ static TEST_WINDOW_CLASS_NAME: &HSTRING = h!("wgctest.TestWindow");
 pub struct TestWindow {
     handle: HWND,
     queue: DispatcherQueue,
+    messages: MessageQueue<u32>,
 }
 
 impl TestWindow {
@@ -73,12 +75,15 @@ impl TestWindow {
             (rect.right - rect.left, rect.bottom - rect.top)
         };
 
-        let mut result = Self {
-            handle: HWND(0),
-            queue: DispatcherQueue::GetForCurrentThread()?,
-        };
+        let queue = DispatcherQueue::GetForCurrentThread()?;
+        // The window procedure owns the sender until the window is destroyed,
+        // so it doesn't depend on where the `TestWindow` ends up. It takes the
+        // sender out of here on `WM_NCCREATE`. If creating the window fails
+        // before that, the sender is still here and dropped with it.
+        let (sender, messages) = message_queue();
+        let mut sender = Some(Box::new(sender));
 
-        let window = unsafe {
+        let handle = unsafe {
             CreateWindowExW(
                 window_ex_style,
                 TEST_WINDOW_CLASS_NAME,
@@ -91,13 +96,17 @@ impl TestWindow {
                 HWND(0),
                 HMENU(0),
                 instance,
-                Some(&mut result as *mut _ as _),
+                Some(&mut sender as *mut Option<Box<MessageSender<u32>>> as _),
             )
             .ok()?
         };
-        unsafe { ShowWindow(window, SW_SHOW) };
+        unsafe { ShowWindow(handle, SW_SHOW) };
 
-        Ok(result)
+        Ok(Self {
+            handle,
+            queue,
+            messages,
+        })
     }
 
     pub fn handle(&self) -> HWND {
@@ -114,8 +123,12 @@ impl TestWindow {
         Ok(())
     }
 
-    fn message_handler(&mut self, message: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
-        unsafe { DefWindowProcW(self.handle, message, wparam, lparam) }
+    /// Waits for the window to receive `message`, e.g. `WM_DESTROY` after
+    /// `close`. The messages received before it are skipped.
+    pub async fn wait_for_message(&mut self, message: u32) {
+        self.messages
+            .wait_for(|received| *received == message)
+            .await;
     }
 
     unsafe extern "system" fn wnd_proc(
@@ -126,15 +139,20 @@ impl TestWindow {
     ) -> LRESULT {
         if message == WM_NCCREATE {
             let cs = lparam.0 as *const CREATESTRUCTW;
-            let this = (*cs).lpCreateParams as *mut Self;
-            (*this).handle = window;
-
-            SetWindowLongPtrW(window, GWLP_USERDATA, this as _);
+            let sender = &mut *((*cs).lpCreateParams as *mut Option<Box<MessageSender<u32>>>);
+            if let Some(sender) = sender.take() {
+                SetWindowLongPtrW(window, GWLP_USERDATA, Box::into_raw(sender) as _);
+            }
         } else {
-            let this = GetWindowLongPtrW(window, GWLP_USERDATA) as *mut Self;
-
-            if !this.is_null() {
-                return (*this).message_handler(message, wparam, lparam);
+            let sender = GetWindowLongPtrW(window, GWLP_USERDATA) as *mut MessageSender<u32>;
+
+            if !sender.is_null() {
+                (*sender).post(message);
+                // This is the last message the window gets.
+                if message == WM_NCDESTROY {
+                    SetWindowLongPtrW(window, GWLP_USERDATA, 0);
+                    drop(Box::from_raw(sender));
+                }
             }
         }
         DefWindowProcW(window, message, wparam, lparam)